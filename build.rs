@@ -1,5 +1,5 @@
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 /// Cross-compile the evgrab helper for ARM targets.
@@ -21,7 +21,7 @@ fn main() {
     build_helper("aarch64", &out_dir);
 }
 
-fn build_helper(arch: &str, out_dir: &PathBuf) {
+fn build_helper(arch: &str, out_dir: &Path) {
     let cc = find_compiler(arch);
     let output = out_dir.join(format!("evgrab-{}", arch));
 
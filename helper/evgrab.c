@@ -1,6 +1,13 @@
 /*
  * evgrab - Grab an evdev device and stream events to stdout.
- * Exits if watchdog file /tmp/rm-pad-watchdog is older than 5 seconds.
+ * Exits if the watchdog file (default /tmp/rm-pad-watchdog) is older than
+ * 5 seconds.
+ *
+ * With --absinfo, instead prints one line per ABS axis of the device,
+ * "code minimum maximum fuzz flat resolution", and exits.
+ *
+ * Exit status: 0 on normal end, 1 on usage/open errors, EXIT_GRAB_FAILED
+ * when the device stays grabbed by someone else (the host checks for it).
  */
 
 #include <errno.h>
@@ -16,10 +23,15 @@
 #include <time.h>
 #include <unistd.h>
 
-#define WATCHDOG_FILE "/tmp/rm-pad-watchdog"
+#define DEFAULT_WATCHDOG_FILE "/tmp/rm-pad-watchdog"
 #define WATCHDOG_TIMEOUT 5
 
+#define EXIT_GRAB_FAILED 3
+#define GRAB_ATTEMPTS 3
+#define GRAB_RETRY_US 500000
+
 static volatile int running = 1;
+static const char *watchdog_file = DEFAULT_WATCHDOG_FILE;
 
 static void handle_signal(int sig) {
     (void)sig;
@@ -29,16 +41,53 @@ static void handle_signal(int sig) {
 /* Returns 1 if watchdog is OK, 0 if stale/missing */
 static int check_watchdog(void) {
     struct stat st;
-    if (stat(WATCHDOG_FILE, &st) < 0)
+    if (stat(watchdog_file, &st) < 0)
         return 0;
     return (time(NULL) - st.st_mtime) <= WATCHDOG_TIMEOUT;
 }
 
+#define BITS_PER_LONG (8 * sizeof(long))
+
+static int print_absinfo(const char *device) {
+    int fd = open(device, O_RDONLY);
+    if (fd < 0) {
+        fprintf(stderr, "evgrab: open %s: %s\n", device, strerror(errno));
+        return 1;
+    }
+
+    unsigned long bits[ABS_CNT / BITS_PER_LONG + 1];
+    memset(bits, 0, sizeof(bits));
+    if (ioctl(fd, EVIOCGBIT(EV_ABS, sizeof(bits)), bits) < 0) {
+        fprintf(stderr, "evgrab: EVIOCGBIT %s: %s\n", device, strerror(errno));
+        close(fd);
+        return 1;
+    }
+
+    for (int code = 0; code < ABS_CNT; code++) {
+        struct input_absinfo info;
+        if (!((bits[code / BITS_PER_LONG] >> (code % BITS_PER_LONG)) & 1))
+            continue;
+        if (ioctl(fd, EVIOCGABS(code), &info) < 0)
+            continue;
+        printf("%d %d %d %d %d %d\n", code, info.minimum, info.maximum,
+               info.fuzz, info.flat, info.resolution);
+    }
+
+    close(fd);
+    return 0;
+}
+
 int main(int argc, char **argv) {
-    if (argc != 2) {
-        fprintf(stderr, "Usage: %s <device>\n", argv[0]);
+    if (argc == 3 && strcmp(argv[1], "--absinfo") == 0)
+        return print_absinfo(argv[2]);
+
+    if (argc != 2 && argc != 3) {
+        fprintf(stderr, "Usage: %s <device> [watchdog-file]\n"
+                        "       %s --absinfo <device>\n", argv[0], argv[0]);
         return 1;
     }
+    if (argc == 3)
+        watchdog_file = argv[2];
 
     signal(SIGTERM, handle_signal);
     signal(SIGINT, handle_signal);
@@ -50,10 +99,16 @@ int main(int argc, char **argv) {
         return 1;
     }
 
-    if (ioctl(fd, EVIOCGRAB, 1) < 0) {
-        fprintf(stderr, "evgrab: grab %s: %s\n", argv[1], strerror(errno));
-        close(fd);
-        return 1;
+    /* A grab held by a dying previous instance is released shortly after */
+    int attempt;
+    for (attempt = 1; ioctl(fd, EVIOCGRAB, 1) < 0; attempt++) {
+        fprintf(stderr, "evgrab: grab %s (attempt %d/%d): %s\n",
+                argv[1], attempt, GRAB_ATTEMPTS, strerror(errno));
+        if (attempt == GRAB_ATTEMPTS) {
+            close(fd);
+            return EXIT_GRAB_FAILED;
+        }
+        usleep(GRAB_RETRY_US);
     }
 
     fprintf(stderr, "evgrab: grabbed %s\n", argv[1]);
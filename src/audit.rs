@@ -0,0 +1,204 @@
+//! Audit trail of the commands run on the tablet (`audit_log`).
+//!
+//! Every command rm-pad executes over SSH goes through `ssh::exec`, which
+//! appends a line here before running it: a UTC timestamp, the host and the
+//! command. This is separate from the log and unaffected by `RUST_LOG`.
+//! The watchdog's `touch`, repeated every couple of seconds, gets a line at
+//! most once a minute, saying how many more times it ran since its last
+//! line, so it doesn't bury the other commands but every run is counted
+//! (the runs after its last line are written when the watchdog stops).
+//! Commands never carry credentials (the password is only used to
+//! authenticate), so nothing needs redacting.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Shortest time between two lines for the same `record_repeated` command.
+const REPEAT_INTERVAL: Duration = Duration::from_secs(60);
+
+struct AuditLog {
+    file: File,
+    host: String,
+    failed: bool,
+    repeats: Repeats,
+}
+
+/// When each repeated command last got a line, and how often it ran since.
+#[derive(Default)]
+struct Repeats(HashMap<String, (Instant, u64)>);
+
+impl Repeats {
+    /// The line to write for this run of `command`, if one is due.
+    fn note(&mut self, command: &str, now: Instant) -> Option<String> {
+        match self.0.get_mut(command) {
+            Some((last, skipped)) if now.duration_since(*last) < REPEAT_INTERVAL => {
+                *skipped += 1;
+                None
+            }
+            Some((last, skipped)) => {
+                let line = repeat_line(command, *skipped);
+                (*last, *skipped) = (now, 0);
+                Some(line)
+            }
+            None => {
+                self.0.insert(command.to_string(), (now, 0));
+                Some(command.to_string())
+            }
+        }
+    }
+
+    /// The line for runs of `command` that haven't been written yet, if any.
+    fn finish(&mut self, command: &str) -> Option<String> {
+        match self.0.remove(command) {
+            Some((_, skipped)) if skipped > 0 => Some(repeat_line(command, skipped)),
+            _ => None,
+        }
+    }
+}
+
+static LOG: OnceLock<Mutex<AuditLog>> = OnceLock::new();
+
+/// Start appending to `path` (created with mode 0600 if missing).
+pub fn open(path: &Path, host: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(|e| format!("Failed to open audit log {}: {}", path.display(), e))?;
+    let log = AuditLog {
+        file,
+        host: host.to_string(),
+        failed: false,
+        repeats: Repeats::default(),
+    };
+    LOG.set(Mutex::new(log)).map_err(|_| "audit log already open")?;
+    Ok(())
+}
+
+/// Record `command` as about to run on the tablet. A write failure is
+/// logged once; it doesn't stop forwarding.
+pub fn record(command: &str) {
+    let Some(log) = LOG.get() else {
+        return;
+    };
+    let mut log = log.lock().unwrap_or_else(|e| e.into_inner());
+    log.write(command);
+}
+
+/// `record` for a command that runs on a timer: writes a line the first
+/// time and then at most once per `REPEAT_INTERVAL`, counting the runs
+/// in between.
+pub fn record_repeated(command: &str) {
+    let Some(log) = LOG.get() else {
+        return;
+    };
+    let mut log = log.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(line) = log.repeats.note(command, Instant::now()) {
+        log.write(&line);
+    }
+}
+
+fn repeat_line(command: &str, skipped: u64) -> String {
+    match skipped {
+        0 => command.to_string(),
+        1 => format!("{} (ran once more since its previous line)", command),
+        n => format!("{} (ran {} more times since its previous line)", command, n),
+    }
+}
+
+/// Write out the runs of a `record_repeated` command still being counted,
+/// once it stops repeating.
+pub fn finish_repeated(command: &str) {
+    let Some(log) = LOG.get() else {
+        return;
+    };
+    let mut log = log.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(line) = log.repeats.finish(command) {
+        log.write(&line);
+    }
+}
+
+impl AuditLog {
+    fn write(&mut self, command: &str) {
+        let line = format!("{} {} {}\n", utc_timestamp(SystemTime::now()), self.host, command.replace('\n', " "));
+        if let Err(e) = self.file.write_all(line.as_bytes()) {
+            if !self.failed {
+                log::warn!("Failed to write the audit log: {}", e);
+                self.failed = true;
+            }
+        }
+    }
+}
+
+/// `2026-01-31T12:34:56Z`.
+fn utc_timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_utc_timestamp() {
+        let at = |secs| utc_timestamp(UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(at(0), "1970-01-01T00:00:00Z");
+        assert_eq!(at(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(at(1_790_000_000), "2026-09-21T14:13:20Z");
+    }
+
+    #[test]
+    fn test_repeated_commands_are_counted() {
+        let mut repeats = Repeats::default();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(repeats.note("touch /tmp/w", at(0)).as_deref(), Some("touch /tmp/w"));
+        assert_eq!(repeats.note("touch /tmp/w", at(2)), None);
+        assert_eq!(repeats.note("touch /tmp/w", at(4)), None);
+        assert_eq!(
+            repeats.note("touch /tmp/w", at(60)).as_deref(),
+            Some("touch /tmp/w (ran 2 more times since its previous line)")
+        );
+        // Each command is counted on its own
+        assert_eq!(repeats.note("touch /tmp/v", at(61)).as_deref(), Some("touch /tmp/v"));
+        assert_eq!(repeats.note("touch /tmp/w", at(200)).as_deref(), Some("touch /tmp/w"));
+
+        // Runs still being counted are written when the command stops
+        assert_eq!(repeats.note("touch /tmp/w", at(202)), None);
+        assert_eq!(
+            repeats.finish("touch /tmp/w").as_deref(),
+            Some("touch /tmp/w (ran once more since its previous line)")
+        );
+        assert_eq!(repeats.finish("touch /tmp/v"), None);
+    }
+}
@@ -0,0 +1,183 @@
+//! Measure the link to the tablet (`rm-pad bench`).
+//!
+//! Streams one input device without grabbing it for a fixed time and
+//! reports how many events and bytes came through, the gaps between
+//! events as they arrived here, and how much later than the fastest event
+//! each one arrived. That last one compares the arrival time with the
+//! tablet's timestamp; the two clocks differ by an unknown offset, so only
+//! the delay added on top of the quickest delivery can be measured.
+
+use std::io::{self, Read};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+use crate::device::DeviceProfile;
+use crate::input::parse_timed_event;
+use crate::ssh;
+
+/// How often a read gives up waiting, to check whether time is up.
+const READ_POLL: Duration = Duration::from_millis(250);
+
+/// Min/avg/max of a series of durations.
+#[derive(Default)]
+struct Spread {
+    min: Option<Duration>,
+    max: Duration,
+    total: Duration,
+    count: u32,
+}
+
+impl Spread {
+    fn record(&mut self, value: Duration) {
+        self.min = Some(self.min.map_or(value, |min| min.min(value)));
+        self.max = self.max.max(value);
+        self.total += value;
+        self.count += 1;
+    }
+
+    fn describe(&self) -> String {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        match self.min {
+            Some(min) => format!(
+                "min {:.1} ms, avg {:.1} ms, max {:.1} ms",
+                ms(min),
+                ms(self.total / self.count),
+                ms(self.max)
+            ),
+            None => "-".into(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct LinkStats {
+    events: u64,
+    bytes: u64,
+    last_arrival: Option<Duration>,
+    gaps: Spread,
+    /// Arrival minus tablet timestamp, per event.
+    offsets: Vec<Duration>,
+}
+
+impl LinkStats {
+    /// Record an event of `bytes` that arrived `arrival` into the run
+    /// (wall clock in `arrived_at`) and was stamped `tablet_time` on the tablet.
+    fn record(&mut self, bytes: usize, arrival: Duration, arrived_at: Duration, tablet_time: Duration) {
+        self.events += 1;
+        self.bytes += bytes as u64;
+        if let Some(last) = self.last_arrival {
+            self.gaps.record(arrival.saturating_sub(last));
+        }
+        self.last_arrival = Some(arrival);
+        self.offsets.push(arrived_at.saturating_sub(tablet_time));
+    }
+
+    /// Delay of every event over the fastest one.
+    fn delays(&self) -> Spread {
+        let mut delays = Spread::default();
+        let fastest = self.offsets.iter().min().copied().unwrap_or_default();
+        for offset in &self.offsets {
+            delays.record(offset.saturating_sub(fastest));
+        }
+        delays
+    }
+
+    fn summary(&self, header: &str, elapsed: Duration) -> String {
+        let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+        let mut lines = vec![
+            header.to_string(),
+            format!("events:   {} ({:.1}/s)", self.events, self.events as f64 / secs),
+            format!("bytes:    {} ({:.1} KiB/s)", self.bytes, self.bytes as f64 / 1024.0 / secs),
+            format!("gaps:     {}", self.gaps.describe()),
+            format!("delay:    {} (over the fastest event)", self.delays().describe()),
+        ];
+        if self.events == 0 {
+            lines.push("No events came in: keep the pen or a finger moving while the benchmark runs.".into());
+        }
+        lines.join("\n")
+    }
+}
+
+/// `kind` is "pen" or "touch".
+pub fn run(
+    config: &Config,
+    device: &DeviceProfile,
+    kind: &str,
+    secs: u64,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let path = match kind {
+        "pen" => &config.pen_device,
+        "touch" => &config.touch_device,
+        _ => return Err(format!("Unknown device '{}'. Use 'pen' or 'touch'", kind).into()),
+    };
+    let (cleanup, mut channel) = ssh::open_input_stream(path, config, false)?;
+    cleanup.set_read_timeout(Some(READ_POLL));
+
+    eprintln!("Benchmarking {} for {} s: keep drawing (pen) or moving a finger (touch)...", cleanup.device_path(), secs);
+
+    let size = device.input_event_size;
+    let mut stats = LinkStats::default();
+    let mut pending = Vec::new();
+    let mut chunk = vec![0u8; 4096];
+    let start = Instant::now();
+    let duration = Duration::from_secs(secs);
+
+    // Plain reads rather than read_exact, so a timeout never splits an event
+    while start.elapsed() < duration {
+        let n = match channel.read(&mut chunk) {
+            Ok(0) => return Err("The input stream ended during the benchmark".into()),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let arrival = start.elapsed();
+        let arrived_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        pending.extend_from_slice(&chunk[..n]);
+        let whole = pending.len() / size * size;
+        for buf in pending[..whole].chunks_exact(size) {
+            if let Some(timed) = parse_timed_event(buf) {
+                stats.record(size, arrival, arrived_at, timed.time);
+            }
+        }
+        pending.drain(..whole);
+    }
+
+    let header = format!(
+        "rm-pad {} bench: {} ({}) on {} via {}, {} s",
+        env!("CARGO_PKG_VERSION"),
+        kind,
+        cleanup.device_path(),
+        device.name,
+        config.host,
+        secs
+    );
+    println!("{}", stats.summary(&header, start.elapsed()));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link_stats_summary() {
+        let ms = Duration::from_millis;
+        let mut stats = LinkStats::default();
+        // (arrival, tablet time): the third event took 30 ms longer to arrive
+        for (arrival, tablet) in [(0, 1000), (10, 1010), (50, 1020), (55, 1045)] {
+            stats.record(16, ms(arrival), ms(5000 + arrival), ms(tablet));
+        }
+
+        let summary = stats.summary("header", Duration::from_secs(2));
+        assert_eq!(
+            summary,
+            "header\n\
+             events:   4 (2.0/s)\n\
+             bytes:    64 (0.0 KiB/s)\n\
+             gaps:     min 5.0 ms, avg 18.3 ms, max 40.0 ms\n\
+             delay:    min 0.0 ms, avg 10.0 ms, max 30.0 ms (over the fastest event)"
+        );
+        assert!(LinkStats::default().summary("header", Duration::ZERO).contains("No events came in"));
+    }
+}
@@ -0,0 +1,111 @@
+//! Measure the usable range of the touch screen (`rm-pad calibrate touch`).
+//!
+//! Streams raw touch events and tracks the extremes of ABS_MT_POSITION_X/Y
+//! over all contacts while the user drags a finger along every edge. The
+//! live line shows the measured maxima next to the device profile's values.
+
+use std::io::{Read, Write};
+
+use crate::config::Config;
+use crate::device::DeviceProfile;
+use crate::input::{parse_input_event, ABS_MT_POSITION_X, ABS_MT_POSITION_Y, EV_ABS};
+use crate::ssh;
+
+/// Observed min/max of one axis.
+#[derive(Default)]
+struct Extent {
+    range: Option<(i32, i32)>,
+}
+
+impl Extent {
+    /// Record a value; returns true if it widened the range.
+    fn observe(&mut self, value: i32) -> bool {
+        match &mut self.range {
+            None => {
+                self.range = Some((value, value));
+                true
+            }
+            Some((min, max)) if value < *min => {
+                *min = value;
+                true
+            }
+            Some((min, max)) if value > *max => {
+                *max = value;
+                true
+            }
+            Some(_) => false,
+        }
+    }
+
+    fn describe(&self, profile_max: i32) -> String {
+        match self.range {
+            Some((min, max)) => {
+                let coverage = (max - min) as f64 / profile_max.max(1) as f64 * 100.0;
+                format!("{:5}..{:5} ({:3.0}% of profile)", min, max, coverage)
+            }
+            None => "    -..    -".into(),
+        }
+    }
+}
+
+pub fn run_touch(config: &Config, device: &DeviceProfile) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (cleanup, mut channel) = ssh::open_input_stream(&config.touch_device, config, false)?;
+
+    eprintln!("Calibrating touch range of {} (Ctrl+C when done)", cleanup.device_path());
+    eprintln!("Drag one finger slowly along all four edges, right into the corners.");
+    eprintln!(
+        "Profile {}: touch_x_max = {}, touch_y_max = {}\n",
+        device.name, device.touch_x_max, device.touch_y_max
+    );
+
+    let mut buf = vec![0u8; device.input_event_size];
+    let mut x = Extent::default();
+    let mut y = Extent::default();
+    let mut stdout = std::io::stdout();
+
+    loop {
+        channel.read_exact(&mut buf)?;
+
+        let Some(ev) = parse_input_event(&buf) else {
+            continue;
+        };
+        if ev.event_type().raw() != EV_ABS {
+            continue;
+        }
+
+        let widened = match ev.raw_code() {
+            ABS_MT_POSITION_X => x.observe(ev.raw_value()),
+            ABS_MT_POSITION_Y => y.observe(ev.raw_value()),
+            _ => false,
+        };
+        if !widened {
+            continue;
+        }
+
+        let measured = |extent: &Extent| extent.range.map_or("?".to_string(), |(_, max)| max.to_string());
+        write!(
+            stdout,
+            "\rX {}  Y {}  ->  touch_x_max = {}, touch_y_max = {}\x1b[K",
+            x.describe(device.touch_x_max),
+            y.describe(device.touch_y_max),
+            measured(&x),
+            measured(&y)
+        )?;
+        stdout.flush()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extent_widens() {
+        let mut extent = Extent::default();
+        assert!(extent.observe(50));
+        assert!(extent.observe(10));
+        assert!(extent.observe(90));
+        assert!(!extent.observe(40));
+        assert_eq!(extent.range, Some((10, 90)));
+    }
+}
@@ -0,0 +1,341 @@
+//! Probe evdev capabilities of a tablet input device over SSH.
+//!
+//! Reads the kernel's sysfs capability bitmasks
+//! (`/sys/class/input/eventN/device/capabilities/*`), which list words of
+//! the kernel's `unsigned long` size, most significant word first.
+//!
+//! `rm-pad caps <device>` prints the full set, including ABS axis ranges,
+//! which sysfs doesn't expose; those come from the grab helper's
+//! `--absinfo` mode (EVIOCGABS).
+
+use ssh2::Session;
+
+use crate::config::Config;
+use crate::device::DeviceProfile;
+use crate::grab;
+use crate::input::{ABS_MT_POSITION_X, ABS_PRESSURE};
+use crate::ssh;
+
+/// Read the set ABS axis codes of a device.
+pub fn read_abs_axes(
+    session: &Session,
+    device_path: &str,
+    word_bits: u32,
+) -> Result<Vec<u16>, Box<dyn std::error::Error + Send + Sync>> {
+    let bitmask = read_capability(session, device_path, "abs")?;
+    Ok(parse_bitmask(&bitmask, word_bits))
+}
+
+/// Maximum of one ABS axis of a device, from the grab helper's
+/// `--absinfo`; the helper is uploaded first if it isn't there yet.
+pub fn read_abs_max(
+    session: &Session,
+    device_path: &str,
+    code: u16,
+    config: &Config,
+) -> Result<i32, Box<dyn std::error::Error + Send + Sync>> {
+    let paths = grab::RemotePaths::new(&config.remote_dir);
+    ssh::prepare_grab(session, &paths, config.tablet_arch)?;
+    read_absinfo(session, device_path, &paths)?
+        .iter()
+        .find(|axis| axis.code == code)
+        .map(|axis| axis.maximum)
+        .ok_or_else(|| format!("{} reports no range for axis {:#x}", device_path, code).into())
+}
+
+/// Whether the configured pen and touch devices look like each other's.
+///
+/// True when the "pen" has multi-touch axes while the "touch" device has
+/// pressure but no multi-touch axes.
+pub fn looks_swapped(pen_axes: &[u16], touch_axes: &[u16]) -> bool {
+    pen_axes.contains(&ABS_MT_POSITION_X)
+        && !touch_axes.contains(&ABS_MT_POSITION_X)
+        && touch_axes.contains(&ABS_PRESSURE)
+}
+
+/// Kernel `unsigned long` width for a device, derived from its input_event size.
+pub fn word_bits(input_event_size: usize) -> u32 {
+    if input_event_size == crate::input::INPUT_EVENT_SIZE_64 {
+        64
+    } else {
+        32
+    }
+}
+
+/// Range of one ABS axis as reported by EVIOCGABS.
+#[derive(Debug, PartialEq)]
+struct AbsInfo {
+    code: u16,
+    minimum: i32,
+    maximum: i32,
+    fuzz: i32,
+    flat: i32,
+    resolution: i32,
+}
+
+/// Print EV types, ABS axes with ranges and keys of `spec` ("pen", "touch",
+/// a path or a `name:` spec).
+pub fn run(config: &Config, device: &DeviceProfile, spec: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let spec = match spec {
+        "pen" => config.pen_device.as_str(),
+        "touch" => config.touch_device.as_str(),
+        other => other,
+    };
+
+    let session = ssh::connect_for_detection(config)?;
+    let paths = grab::RemotePaths::new(&config.remote_dir);
+    ssh::prepare_grab(&session, &paths, config.tablet_arch)?;
+
+    let device_path = ssh::resolve_device_path(&session, spec)?;
+    let word_bits = word_bits(device.input_event_size);
+    let name = read_sysfs(&session, &device_path, "name")?;
+    let ev = parse_bitmask(&read_capability(&session, &device_path, "ev")?, word_bits);
+    let keys = parse_bitmask(&read_capability(&session, &device_path, "key")?, word_bits);
+    let axes = read_absinfo(&session, &device_path, &paths)?;
+
+    println!("{} ({})", device_path, name.trim());
+
+    let ev_names: Vec<String> = ev.iter().map(|&t| ev_type_name(t).map_or(format!("{:#x}", t), str::to_string)).collect();
+    println!("EV types: {}", ev_names.join(" "));
+
+    println!("ABS axes:");
+    for axis in &axes {
+        println!(
+            "  {:<22} min {:6}  max {:6}  resolution {:4}  fuzz {}  flat {}",
+            code_label(abs_name(axis.code), axis.code),
+            axis.minimum,
+            axis.maximum,
+            axis.resolution,
+            axis.fuzz,
+            axis.flat
+        );
+    }
+
+    println!("Keys:");
+    for &key in &keys {
+        println!("  {}", code_label(key_name(key), key));
+    }
+
+    Ok(())
+}
+
+fn read_capability(
+    session: &Session,
+    device_path: &str,
+    kind: &str,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    read_sysfs(session, device_path, &format!("capabilities/{}", kind))
+}
+
+/// Read a file below `/sys/class/input/eventN/device/`.
+fn read_sysfs(
+    session: &Session,
+    device_path: &str,
+    file: &str,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let name = device_path.rsplit('/').next().unwrap_or(device_path);
+    ssh::run_command(session, &format!("cat /sys/class/input/{}/device/{}", name, file))
+        .map_err(|e| format!("Failed to read {} of {}: {}", file, device_path, e).into())
+}
+
+fn read_absinfo(
+    session: &Session,
+    device_path: &str,
+    paths: &grab::RemotePaths,
+) -> Result<Vec<AbsInfo>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(parse_absinfo(&ssh::run_command(session, &grab::absinfo_command(device_path, paths))?))
+}
+
+/// Parse the helper's `--absinfo` lines; malformed lines are skipped.
+fn parse_absinfo(output: &str) -> Vec<AbsInfo> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<i32> = line.split_whitespace().map(str::parse).collect::<Result<_, _>>().ok()?;
+            let &[code, minimum, maximum, fuzz, flat, resolution] = fields.as_slice() else {
+                return None;
+            };
+            Some(AbsInfo {
+                code: u16::try_from(code).ok()?,
+                minimum,
+                maximum,
+                fuzz,
+                flat,
+                resolution,
+            })
+        })
+        .collect()
+}
+
+fn code_label(name: Option<&str>, code: u16) -> String {
+    match name {
+        Some(name) => format!("{} ({:#x})", name, code),
+        None => format!("{:#x}", code),
+    }
+}
+
+fn ev_type_name(code: u16) -> Option<&'static str> {
+    Some(match code {
+        0x00 => "SYN",
+        0x01 => "KEY",
+        0x02 => "REL",
+        0x03 => "ABS",
+        0x04 => "MSC",
+        0x05 => "SW",
+        0x11 => "LED",
+        0x12 => "SND",
+        0x14 => "REP",
+        0x15 => "FF",
+        0x16 => "PWR",
+        0x17 => "FF_STATUS",
+        _ => return None,
+    })
+}
+
+fn abs_name(code: u16) -> Option<&'static str> {
+    Some(match code {
+        0x00 => "ABS_X",
+        0x01 => "ABS_Y",
+        0x02 => "ABS_Z",
+        0x18 => "ABS_PRESSURE",
+        0x19 => "ABS_DISTANCE",
+        0x1a => "ABS_TILT_X",
+        0x1b => "ABS_TILT_Y",
+        0x1c => "ABS_TOOL_WIDTH",
+        0x28 => "ABS_MISC",
+        0x2f => "ABS_MT_SLOT",
+        0x30 => "ABS_MT_TOUCH_MAJOR",
+        0x31 => "ABS_MT_TOUCH_MINOR",
+        0x32 => "ABS_MT_WIDTH_MAJOR",
+        0x33 => "ABS_MT_WIDTH_MINOR",
+        0x34 => "ABS_MT_ORIENTATION",
+        0x35 => "ABS_MT_POSITION_X",
+        0x36 => "ABS_MT_POSITION_Y",
+        0x37 => "ABS_MT_TOOL_TYPE",
+        0x38 => "ABS_MT_BLOB_ID",
+        0x39 => "ABS_MT_TRACKING_ID",
+        0x3a => "ABS_MT_PRESSURE",
+        0x3b => "ABS_MT_DISTANCE",
+        0x3c => "ABS_MT_TOOL_X",
+        0x3d => "ABS_MT_TOOL_Y",
+        _ => return None,
+    })
+}
+
+fn key_name(code: u16) -> Option<&'static str> {
+    Some(match code {
+        0x74 => "KEY_POWER",
+        0x110 => "BTN_LEFT",
+        0x111 => "BTN_RIGHT",
+        0x112 => "BTN_MIDDLE",
+        0x140 => "BTN_TOOL_PEN",
+        0x141 => "BTN_TOOL_RUBBER",
+        0x142 => "BTN_TOOL_BRUSH",
+        0x143 => "BTN_TOOL_PENCIL",
+        0x144 => "BTN_TOOL_AIRBRUSH",
+        0x145 => "BTN_TOOL_FINGER",
+        0x146 => "BTN_TOOL_MOUSE",
+        0x147 => "BTN_TOOL_LENS",
+        0x148 => "BTN_TOOL_QUINTTAP",
+        0x149 => "BTN_STYLUS3",
+        0x14a => "BTN_TOUCH",
+        0x14b => "BTN_STYLUS",
+        0x14c => "BTN_STYLUS2",
+        0x14d => "BTN_TOOL_DOUBLETAP",
+        0x14e => "BTN_TOOL_TRIPLETAP",
+        0x14f => "BTN_TOOL_QUADTAP",
+        _ => return None,
+    })
+}
+
+/// Find the event node of the device called `name` in `/proc/bus/input/devices`.
+///
+/// Returns e.g. `/dev/input/event1` for the first block whose `N: Name=`
+/// matches exactly and whose `H: Handlers=` lists an `eventN` handler.
+pub fn find_event_node(devices: &str, name: &str) -> Option<String> {
+    devices.split("\n\n").find_map(|block| {
+        let mut block_name = None;
+        let mut event = None;
+
+        for line in block.lines() {
+            if let Some(value) = line.strip_prefix("N: Name=") {
+                block_name = Some(value.trim().trim_matches('"'));
+            } else if let Some(handlers) = line.strip_prefix("H: Handlers=") {
+                event = handlers.split_whitespace().find(|h| h.starts_with("event"));
+            }
+        }
+
+        match (block_name, event) {
+            (Some(n), Some(event)) if n == name => Some(format!("/dev/input/{}", event)),
+            _ => None,
+        }
+    })
+}
+
+/// Parse a sysfs capability bitmask into the list of set bit indices.
+pub fn parse_bitmask(bitmask: &str, word_bits: u32) -> Vec<u16> {
+    let mut bits = Vec::new();
+
+    for (index, word) in bitmask.split_whitespace().rev().enumerate() {
+        let Ok(value) = u64::from_str_radix(word, 16) else {
+            continue;
+        };
+        for bit in 0..word_bits {
+            if value & (1 << bit) != 0 {
+                bits.push((index as u32 * word_bits + bit) as u16);
+            }
+        }
+    }
+
+    bits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bitmask() {
+        // ABS_X, ABS_Y and ABS_MT_DISTANCE (0x3b) on a 32-bit kernel
+        assert_eq!(parse_bitmask("8000000 3\n", 32), vec![0x00, 0x01, 0x3b]);
+        assert_eq!(parse_bitmask("800000000000003\n", 64), vec![0x00, 0x01, 0x3b]);
+        assert!(parse_bitmask("0\n", 32).is_empty());
+    }
+
+    #[test]
+    fn test_find_event_node() {
+        let devices = "I: Bus=0018 Vendor=056a Product=0000 Version=0036\n\
+                       N: Name=\"Wacom I2C Digitizer\"\n\
+                       H: Handlers=event1 \n\
+                       B: EV=b\n\
+                       \n\
+                       I: Bus=0000 Vendor=0000 Product=0000 Version=0000\n\
+                       N: Name=\"pt_mt\"\n\
+                       H: Handlers=kbd event2 \n";
+        assert_eq!(find_event_node(devices, "Wacom I2C Digitizer").as_deref(), Some("/dev/input/event1"));
+        assert_eq!(find_event_node(devices, "pt_mt").as_deref(), Some("/dev/input/event2"));
+        assert_eq!(find_event_node(devices, "Wacom"), None);
+    }
+
+    #[test]
+    fn test_parse_absinfo() {
+        let output = "0 0 20967 0 0 100\n24 0 4095 0 0 0\ngarbage\n26 -9000 9000 0 0 0\n";
+        let axes = parse_absinfo(output);
+        assert_eq!(axes.len(), 3);
+        assert_eq!(
+            axes[0],
+            AbsInfo { code: 0x00, minimum: 0, maximum: 20967, fuzz: 0, flat: 0, resolution: 100 }
+        );
+        assert_eq!((axes[2].code, axes[2].minimum), (0x1a, -9000));
+        assert_eq!(code_label(abs_name(0x18), 0x18), "ABS_PRESSURE (0x18)");
+        assert_eq!(code_label(key_name(0x2ff), 0x2ff), "0x2ff");
+    }
+
+    #[test]
+    fn test_looks_swapped() {
+        let pen = [0x00, 0x01, 0x18, 0x19, 0x1a, 0x1b];
+        let touch = [0x2f, 0x35, 0x36, 0x39];
+        assert!(!looks_swapped(&pen, &touch));
+        assert!(looks_swapped(&touch, &pen));
+    }
+}
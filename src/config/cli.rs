@@ -1,7 +1,10 @@
 use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 
+use super::{AuthMethod, OutputBackend};
+use crate::input::{PenContactSource, PenHover, PenMode, RegionExit, PrimaryContact, TouchMode};
 use crate::orientation::Orientation;
+use crate::remote::RemoteAction;
 
 #[derive(Parser)]
 #[command(name = "rm-pad")]
@@ -15,22 +18,118 @@ pub struct Cli {
     #[arg(long, env = "RMPAD_HOST")]
     pub host: Option<String>,
 
-    /// SSH key path for authentication
+    /// Fail instead of using the USB address 10.11.99.1 when no host is configured
     #[arg(long)]
-    pub key_path: Option<String>,
+    pub no_default_host: bool,
+
+    /// Device profile to use when detecting the tablet's model fails: rm2 or rmpp
+    #[arg(long)]
+    pub device_model: Option<String>,
+
+    /// Only connect over IPv4
+    #[arg(long, conflicts_with = "ipv6")]
+    pub ipv4: bool,
+
+    /// Only connect over IPv6
+    #[arg(long)]
+    pub ipv6: bool,
+
+    /// Preferred SSH ciphers, comma-separated (e.g. aes128-ctr)
+    #[arg(long)]
+    pub ssh_ciphers: Option<String>,
+
+    /// Preferred SSH key exchange methods, comma-separated
+    #[arg(long)]
+    pub ssh_kex: Option<String>,
+
+    /// Leave Nagle's algorithm on for the SSH connection (TCP_NODELAY is set by default)
+    #[arg(long)]
+    pub no_tcp_nodelay: bool,
+
+    /// SSH key path for authentication; repeat to try several keys in order
+    #[arg(long)]
+    pub key_path: Vec<String>,
+
+    /// Passphrase for an encrypted key (asked for on the terminal if unset)
+    #[arg(long, env = "RMPAD_KEY_PASSPHRASE")]
+    pub key_passphrase: Option<String>,
 
     /// SSH password (if set, key_path is ignored)
     #[arg(long, env = "RMPAD_PASSWORD")]
     pub password: Option<String>,
 
-    /// Pen input device path on reMarkable
+    /// Authentication method: auto (password or key) or keyring
+    #[arg(long, value_parser = clap::value_parser!(AuthMethod))]
+    pub auth: Option<AuthMethod>,
+
+    /// Pen input device path on reMarkable, or "name:<device name>"
     #[arg(long)]
     pub pen_device: Option<String>,
 
-    /// Touch input device path on reMarkable
+    /// Touch input device path on reMarkable, or "name:<device name>"
     #[arg(long)]
     pub touch_device: Option<String>,
 
+    /// Keyboard (type folio) device on reMarkable to forward, or "name:<device name>"
+    #[arg(long)]
+    pub keyboard_device: Option<String>,
+
+    /// Wait for the tablet to wake instead of reconnecting while it sleeps (follows its power button)
+    #[arg(long)]
+    pub pause_on_sleep: bool,
+
+    /// Power button device on reMarkable for --pause-on-sleep, or "name:<device name>"
+    #[arg(long)]
+    pub power_device: Option<String>,
+
+    /// Where to send input: uinput (default) or wayland (pen only, wlroots compositors)
+    #[arg(long, value_parser = clap::value_parser!(OutputBackend))]
+    pub output: Option<OutputBackend>,
+
+    /// Tablet directory for the grab helper, its log and the watchdog file (default /tmp)
+    #[arg(long)]
+    pub remote_dir: Option<String>,
+
+    /// Remote command streaming a device instead of the grab helper or cat; {device} is the device path
+    #[arg(long)]
+    pub stream_command: Option<String>,
+
+    /// Wait until this file or socket exists (e.g. the compositor's) before creating input devices
+    #[arg(long)]
+    pub wait_for: Option<String>,
+
+    /// Shell command to run when forwarding starts (gets RMPAD_HOST and RMPAD_DEVICE)
+    #[arg(long)]
+    pub on_connect: Option<String>,
+
+    /// Shell command to run when forwarding stops
+    #[arg(long)]
+    pub on_disconnect: Option<String>,
+
+    /// Stream without grabbing if the tablet device can't be grabbed exclusively
+    #[arg(long)]
+    pub grab_fallback: bool,
+
+    /// Write to pre-created local input devices instead of creating uinput devices
+    #[arg(long)]
+    pub use_existing_device: bool,
+
+    /// Local device for pen output with --use-existing-device (default: find "reMarkable Pen")
+    #[arg(long)]
+    pub existing_pen_device: Option<String>,
+
+    /// Local device for touch output with --use-existing-device (default: find "reMarkable Touch")
+    #[arg(long)]
+    pub existing_touch_device: Option<String>,
+
+    /// Keep the tablet's input controllers awake before streaming (avoids a lost first stroke)
+    #[arg(long)]
+    pub wake_input: bool,
+
+    /// Swap pen and touch device paths if they look swapped
+    #[arg(long)]
+    pub auto_fix_devices: bool,
+
     /// Run touch input only (no pen)
     #[arg(long)]
     pub touch_only: bool,
@@ -40,11 +139,11 @@ pub struct Cli {
     pub pen_only: bool,
 
     /// Grab input exclusively [default: true]
-    #[arg(long)]
+    #[arg(long, alias = "grab")]
     pub grab_input: bool,
 
-    /// Don't grab input (tablet UI will also see input)
-    #[arg(long)]
+    /// Don't grab input (tablet UI will also see input); wins over --grab-input
+    #[arg(long, alias = "no-grab")]
     pub no_grab_input: bool,
 
     /// Disable palm rejection
@@ -55,13 +154,229 @@ pub struct Cli {
     #[arg(long)]
     pub palm_grace_ms: Option<u64>,
 
-    /// Screen orientation (portrait, landscape-right, landscape-left, inverted)
+    /// Start the palm grace period at the last pen activity (hover included), not only pen-up
+    #[arg(long)]
+    pub palm_grace_from_activity: bool,
+
+    /// Report suppressed touches as palms before lifting them, so gestures in progress are cancelled
+    #[arg(long)]
+    pub palm_cancel: bool,
+
+    /// Screen orientation (portrait, landscape-right, landscape-left, inverted), for pen and touch
     #[arg(long, value_parser = clap::value_parser!(Orientation))]
     pub orientation: Option<Orientation>,
 
+    /// Orientation of the pen only, overriding --orientation
+    #[arg(long, value_parser = clap::value_parser!(Orientation))]
+    pub pen_orientation: Option<Orientation>,
+
+    /// Orientation of the touch screen only, overriding --orientation
+    #[arg(long, value_parser = clap::value_parser!(Orientation))]
+    pub touch_orientation: Option<Orientation>,
+
+    /// Drop up to this many (0-2) trailing low-pressure samples at stroke end
+    #[arg(long)]
+    pub pen_tail_trim: Option<usize>,
+
+    /// Smoothing weight of new pen samples at the lightest pressure (0-1, 1 = off)
+    #[arg(long)]
+    pub pen_smoothing_low: Option<f64>,
+
+    /// Smoothing weight of new pen samples at full pressure (0-1, 1 = off)
+    #[arg(long)]
+    pub pen_smoothing_high: Option<f64>,
+
+    /// Lowest pressure sent while the pen touches (default 0)
+    #[arg(long)]
+    pub pen_pressure_min: Option<i32>,
+
+    /// Highest pressure sent, also declared as the pen's pressure range (default: the tablet's)
+    #[arg(long)]
+    pub pen_pressure_max: Option<i32>,
+    /// Keep the tablet's aspect ratio on the monitor instead of stretching it (pen and touchscreen, letterboxed)
+    /// Keep the pen's aspect ratio on the monitor instead of stretching it (letterboxed)
+    #[arg(long)]
+    pub fit_screen: bool,
+
+    /// Screen size for --fit-screen, as WxH (default: the first connected monitor)
+    #[arg(long, env = "RMPAD_SCREEN_SIZE")]
+    pub screen_size: Option<String>,
+
+    /// Input bus id of the pen device, decimal or 0x hex (default 0x03, USB)
+    #[arg(long, value_parser = parse_id)]
+    pub pen_bus: Option<u32>,
+
+    /// Vendor id of the pen device, decimal or 0x hex (default 0x2d1f)
+    #[arg(long, value_parser = parse_id)]
+    pub pen_vendor: Option<u32>,
+
+    /// Product id of the pen device, decimal or 0x hex (default 0x0001)
+    #[arg(long, value_parser = parse_id)]
+    pub pen_product: Option<u32>,
+
+    /// Widen the pen range to the observed one if the pen goes well past the profile's
+    #[arg(long)]
+    pub pen_auto_range: bool,
+
+    /// What decides pen contact (pressure, btn-touch, auto)
+    #[arg(long, value_parser = clap::value_parser!(PenContactSource))]
+    pub pen_contact_source: Option<PenContactSource>,
+
+    /// What the hovering pen does (move, ignore, proximity-only)
+    #[arg(long, value_parser = clap::value_parser!(PenHover))]
+    pub pen_hover: Option<PenHover>,
+
+    /// Forward the pen as a tablet (absolute) or as a mouse nudged by pen movement (relative)
+    #[arg(long, value_parser = clap::value_parser!(PenMode))]
+    pub pen_mode: Option<PenMode>,
+
+    /// Mouse counts per millimetre of pen movement with pen_mode = relative (default 10)
+    #[arg(long)]
+    pub pen_relative_sensitivity: Option<f64>,
+
+    /// Present touch as a touchpad or a touchscreen (direct input, for kiosk apps)
+    #[arg(long, value_parser = clap::value_parser!(TouchMode))]
+    pub touch_mode: Option<TouchMode>,
+
+    /// Which finger drives ABS_X/ABS_Y and touch-as-pen: lowest-slot, first-down, first-only, or largest
+    #[arg(long, value_parser = clap::value_parser!(PrimaryContact))]
+    pub touch_primary: Option<PrimaryContact>,
+
+    /// Forward at most this many fingers at once, chosen by touch_primary
+    #[arg(long)]
+    pub max_fingers: Option<u32>,
+
+    /// Forward finger hover (ABS_MT_DISTANCE) if the touch device reports it
+    #[arg(long)]
+    pub touch_hover: bool,
+
+    /// Only report multi-touch axes; stop updating legacy ABS_X/ABS_Y on the touchpad
+    #[arg(long)]
+    pub touch_mt_only: bool,
+
+    /// Only re-send a finger's position when it changed, instead of every frame
+    #[arg(long)]
+    pub touch_changed_only: bool,
+
+    /// Drop pen and touch frames that change nothing (e.g. empty SYN_REPORTs)
+    #[arg(long)]
+    pub skip_repeated_frames: bool,
+
+    /// Forward the first finger as a stylus instead of a touchpad, for pen-only apps
+    #[arg(long)]
+    pub touch_as_pen: bool,
+
+    /// Only forward touch while this key is held on this computer (e.g. KEY_LEFTCTRL)
+    #[arg(long)]
+    pub touch_gate_key: Option<String>,
+
+    /// Keep a lifted finger down this many ms in case it comes right back (bridges tracking flicker)
+    #[arg(long)]
+    pub touch_lift_debounce_ms: Option<u64>,
+
+    /// Hold back a finger landing during a touch for this many ms, so brushes don't change the finger count
+    #[arg(long)]
+    pub gesture_hold_ms: Option<u64>,
+
+    /// Ignore movement of a resting finger up to this many touch units
+    #[arg(long)]
+    pub touch_motion_deadzone: Option<i32>,
+
+    /// Drop pen/touch frames that moved less than this many device units (for slow links)
+    #[arg(long)]
+    pub min_position_delta: Option<i32>,
+
+    /// Cap pen and touch together at this many forwarded events per second, pen first (for slow links)
+    #[arg(long)]
+    pub max_event_rate: Option<u32>,
+
+    /// Drop output for this many ms after a stream starts, so stale positions don't jump the cursor
+    #[arg(long)]
+    pub warmup_discard_ms: Option<u64>,
+
+    /// Shift the output right by this many screen pixels (negative: left)
+    #[arg(long, allow_hyphen_values = true)]
+    pub offset_x: Option<i32>,
+
+    /// Shift the output down by this many screen pixels (negative: up)
+    #[arg(long, allow_hyphen_values = true)]
+    pub offset_y: Option<i32>,
+
+    /// Pen outside the output area: clamp, lift, or passthrough
+    #[arg(long, value_parser = clap::value_parser!(RegionExit))]
+    pub region_exit: Option<RegionExit>,
+
+    /// Delay frames to their original spacing, smoothing out bursts after network hiccups
+    #[arg(long)]
+    pub pace: bool,
+
+    /// Make the pen button (BTN_STYLUS) left-click on a shared mouse device
+    #[arg(long)]
+    pub pen_button_click: bool,
+
+    /// Disconnect after this many seconds without input on any stream
+    #[arg(long)]
+    pub idle_disconnect_secs: Option<u64>,
+
+    /// Touch axis resolution in units/mm (affects libinput pointer speed)
+    #[arg(long)]
+    pub touch_resolution: Option<i32>,
+
+    /// Physical touchpad width in mm to report; overrides --touch-resolution
+    #[arg(long)]
+    pub touch_width_mm: Option<f64>,
+
     /// Path to config file
     #[arg(long, env = "RMPAD_CONFIG")]
     pub config: Option<PathBuf>,
+
+    /// Fork into the background
+    #[arg(long)]
+    pub daemonize: bool,
+
+    /// Write the daemon's PID to this file (removed on exit)
+    #[arg(long, requires = "daemonize")]
+    pub pidfile: Option<PathBuf>,
+
+    /// Append daemon log output to this file instead of discarding it
+    #[arg(long, requires = "daemonize")]
+    pub log_file: Option<PathBuf>,
+
+    /// Start even if another instance is already running for this host
+    #[arg(long)]
+    pub force: bool,
+
+    /// Show a live status dashboard instead of the log (interactive terminals only)
+    #[arg(long)]
+    pub tui: bool,
+
+    /// Give the created input devices to this group (mode 0660), e.g. input
+    #[arg(long)]
+    pub device_group: Option<String>,
+
+    /// Also append every forwarded pen/touch frame to this file (JSON lines)
+    #[arg(long)]
+    pub tee_output: Option<PathBuf>,
+
+    /// Append every command run on the tablet to this file, with time and host
+    #[arg(long)]
+    pub audit_log: Option<PathBuf>,
+
+    /// Serve /livez and /healthz on this address (e.g. 127.0.0.1:9813)
+    #[arg(long)]
+    pub health_addr: Option<String>,
+
+    /// /healthz fails if a stream has had no events for this many seconds
+    #[arg(long)]
+    pub health_max_idle_secs: Option<u64>,
+
+    /// Show desktop notifications when input connects, drops or fails (needs the `notify` feature)
+    #[arg(long)]
+    pub notify: bool,
+
+    /// Exit after the first disconnect instead of reconnecting (for scripts)
+    #[arg(long)]
+    pub once: bool,
 }
 
 #[derive(Subcommand)]
@@ -70,5 +385,103 @@ pub enum Command {
     Dump {
         /// Device to dump: "touch" or "pen"
         device: String,
+
+        /// Show decoded per-frame state on a single updating line (pen only)
+        #[arg(long)]
+        frames: bool,
+
+        /// Also print X/Y after the configured orientation transform
+        #[arg(long)]
+        apply_orientation: bool,
+    },
+
+    /// Measure the usable coordinate range of a device ("touch")
+    Calibrate {
+        /// Device to calibrate: "touch"
+        device: String,
+    },
+
+    /// Stream a device for a while and report event rate, throughput and delays of the link
+    Bench {
+        /// Device to stream: "pen" or "touch"
+        device: String,
+
+        /// How long to stream, in seconds
+        #[arg(long, default_value_t = 10)]
+        secs: u64,
+    },
+
+    /// Show where each orientation puts the cursor while touching the tablet's corners
+    OrientationTest {
+        /// Device to test: "pen" or "touch"
+        device: String,
+    },
+
+    /// Print a device's EV types, ABS axes with ranges and keys
+    Caps {
+        /// Device: "pen", "touch", a /dev/input path or name:<device name>
+        device: String,
     },
+
+    /// Inspect or clear rm-pad's files on the tablet: status, log or clear
+    Remote {
+        #[arg(value_enum)]
+        action: RemoteAction,
+
+        /// Print one JSON object instead of text
+        #[arg(long)]
+        json: bool,
+
+        /// Number of log lines to show (log)
+        #[arg(long, default_value_t = 20)]
+        lines: usize,
+    },
+
+    /// Store the tablet's root password in the system keyring (for auth = "keyring")
+    SetPassword,
+
+    /// Generate an SSH key at key_path and install it on the tablet, logging in with the root password once
+    Setup,
+
+    /// Forward a recorded stream (e.g. rm2-pen-stroke.bin) to the output devices
+    Replay {
+        /// Raw recording (or .bin.zst); a name like <rm2|rmpp>-<pen|touch>-<name>.bin says how to decode it
+        file: PathBuf,
+
+        /// Playback speed factor; 0 replays as fast as possible
+        #[arg(long, default_value_t = 1.0)]
+        speed: f64,
+
+        /// Decode as pen or touch, for recordings not named like the corpus
+        #[arg(long, value_parser = ["pen", "touch"])]
+        kind: Option<String>,
+    },
+
+    /// Replay the regression corpus through the decoders and diff the output
+    #[command(hide = true)]
+    CheckCorpus {
+        /// Directory with recordings (*.bin or zstd-compressed *.bin.zst) and expected frames (*.json)
+        #[arg(default_value = "corpus")]
+        dir: PathBuf,
+
+        /// Rewrite the expected frames from the current decoder output
+        #[arg(long)]
+        update: bool,
+    },
+
+    /// Print shell completions to stdout
+    #[command(hide = true)]
+    Completions {
+        /// bash, zsh, fish, powershell or elvish
+        shell: clap_complete::Shell,
+    },
+}
+
+/// Parse a device id given in decimal or as `0x` hex.
+fn parse_id(s: &str) -> Result<u32, String> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|_| format!("Invalid id '{}'. Expected a number like 0x2d1f or 11551", s))
 }
@@ -1,19 +1,36 @@
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 
+use super::{AddressFamily, AuthMethod, OutputBackend};
+use crate::input::{PenContactSource, PenHover, PenMode, RegionExit, PrimaryContact, TouchMode};
 use crate::orientation::Orientation;
 
-const DEFAULT_HOST: &str = "10.11.99.1";
-
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct FileConfig {
-    #[serde(default = "default_host")]
-    pub host: String,
-    pub key_path: Option<String>,
+    pub host: Option<String>,
+    pub device_model: Option<String>,
+    pub key_path: Option<KeyPaths>,
+    pub key_passphrase: Option<String>,
+    pub key_candidates: Option<Vec<String>>,
     pub password: Option<String>,
+    #[serde(default)]
+    pub auth: AuthMethod,
+    pub keyring_service: Option<String>,
+    pub keyring_account: Option<String>,
+    #[serde(default)]
+    pub address_family: AddressFamily,
+    pub ssh_ciphers: Option<String>,
+    pub ssh_kex: Option<String>,
+    #[serde(default = "default_true")]
+    pub tcp_nodelay: bool,
     pub pen_device: Option<String>,
     pub touch_device: Option<String>,
+    pub keyboard_device: Option<String>,
+    #[serde(default)]
+    pub pause_on_sleep: bool,
+    pub power_device: Option<String>,
     #[serde(default)]
     pub touch_only: bool,
     #[serde(default)]
@@ -24,29 +41,209 @@ pub struct FileConfig {
     pub no_palm_rejection: bool,
     pub palm_grace_ms: Option<u64>,
     #[serde(default)]
+    pub palm_grace_from_activity: bool,
+    #[serde(default)]
+    pub palm_cancel: bool,
+    #[serde(default)]
     pub orientation: Orientation,
+    pub pen_orientation: Option<Orientation>,
+    pub touch_orientation: Option<Orientation>,
+    #[serde(default)]
+    pub pen_contact_source: PenContactSource,
+    #[serde(default)]
+    pub pen_hover: PenHover,
+    #[serde(default)]
+    pub pen_mode: PenMode,
+    pub pen_relative_sensitivity: Option<f64>,
+    #[serde(default)]
+    pub touch_mode: TouchMode,
+    #[serde(default)]
+    pub touch_primary: PrimaryContact,
+    pub max_fingers: Option<u32>,
+    #[serde(default)]
+    pub touch_hover: bool,
+    #[serde(default)]
+    pub touch_mt_only: bool,
+    #[serde(default)]
+    pub touch_changed_only: bool,
+    #[serde(default)]
+    pub skip_repeated_frames: bool,
+    #[serde(default)]
+    pub touch_as_pen: bool,
+    #[serde(default)]
+    pub touch_motion_deadzone: i32,
+    pub touch_gate_key: Option<String>,
+    #[serde(default)]
+    pub touch_lift_debounce_ms: u64,
+    #[serde(default)]
+    pub gesture_hold_ms: u64,
+    #[serde(default)]
+    pub gesture_keys: BTreeMap<String, String>,
+    #[serde(default)]
+    pub min_position_delta: i32,
+    pub max_event_rate: Option<u32>,
+    #[serde(default)]
+    pub warmup_discard_ms: u64,
+    #[serde(default)]
+    pub offset_x: i32,
+    #[serde(default)]
+    pub offset_y: i32,
+    #[serde(default)]
+    pub region_exit: RegionExit,
+    #[serde(default)]
+    pub pace: bool,
+    #[serde(default)]
+    pub pen_button_click: bool,
+    pub idle_disconnect_secs: Option<u64>,
+    pub touch_resolution: Option<i32>,
+    pub touch_width_mm: Option<f64>,
+    #[serde(default)]
+    pub auto_fix_devices: bool,
+    #[serde(default)]
+    pub wake_input: bool,
+    #[serde(default)]
+    pub output: OutputBackend,
+    pub remote_dir: Option<String>,
+    pub stream_command: Option<String>,
+    pub wait_for: Option<String>,
+    pub on_connect: Option<String>,
+    pub on_disconnect: Option<String>,
+    #[serde(default)]
+    pub notify: bool,
+    pub device_group: Option<String>,
+    pub tee_output: Option<PathBuf>,
+    pub audit_log: Option<PathBuf>,
+    pub health_addr: Option<String>,
+    pub health_max_idle_secs: Option<u64>,
+    #[serde(default)]
+    pub grab_fallback: bool,
+    #[serde(default)]
+    pub use_existing_device: bool,
+    pub existing_pen_device: Option<String>,
+    pub existing_touch_device: Option<String>,
+    #[serde(default)]
+    pub pen_tail_trim: usize,
+    pub pen_smoothing_low: Option<f64>,
+    pub pen_smoothing_high: Option<f64>,
+    pub pen_pressure_min: Option<i32>,
+    pub pen_pressure_max: Option<i32>,
+    #[serde(default)]
+    pub fit_screen: bool,
+    pub screen_size: Option<String>,
+    pub pen_bus: Option<u32>,
+    pub pen_vendor: Option<u32>,
+    pub pen_product: Option<u32>,
+    #[serde(default)]
+    pub pen_auto_range: bool,
 }
 
 impl Default for FileConfig {
     fn default() -> Self {
         Self {
-            host: DEFAULT_HOST.into(),
+            host: None,
+            device_model: None,
             grab_input: true,
             key_path: None,
+            key_passphrase: None,
+            key_candidates: None,
             password: None,
+            auth: AuthMethod::default(),
+            keyring_service: None,
+            keyring_account: None,
+            address_family: AddressFamily::default(),
+            ssh_ciphers: None,
+            ssh_kex: None,
+            tcp_nodelay: true,
             pen_device: None,
             touch_device: None,
+            keyboard_device: None,
+            pause_on_sleep: false,
+            power_device: None,
             touch_only: false,
             pen_only: false,
             no_palm_rejection: false,
             palm_grace_ms: None,
+            palm_grace_from_activity: false,
+            palm_cancel: false,
             orientation: Orientation::default(),
+            pen_orientation: None,
+            touch_orientation: None,
+            pen_contact_source: PenContactSource::default(),
+            pen_hover: PenHover::default(),
+            pen_mode: PenMode::default(),
+            pen_relative_sensitivity: None,
+            touch_mode: TouchMode::default(),
+            touch_primary: PrimaryContact::default(),
+            max_fingers: None,
+            touch_hover: false,
+            touch_mt_only: false,
+            touch_changed_only: false,
+            skip_repeated_frames: false,
+            touch_as_pen: false,
+            touch_motion_deadzone: 0,
+            touch_gate_key: None,
+            touch_lift_debounce_ms: 0,
+            gesture_hold_ms: 0,
+            gesture_keys: BTreeMap::new(),
+            min_position_delta: 0,
+            max_event_rate: None,
+            warmup_discard_ms: 0,
+            offset_x: 0,
+            offset_y: 0,
+            region_exit: RegionExit::default(),
+            pace: false,
+            pen_button_click: false,
+            idle_disconnect_secs: None,
+            touch_resolution: None,
+            touch_width_mm: None,
+            auto_fix_devices: false,
+            wake_input: false,
+            output: OutputBackend::default(),
+            remote_dir: None,
+            stream_command: None,
+            wait_for: None,
+            on_connect: None,
+            on_disconnect: None,
+            notify: false,
+            device_group: None,
+            tee_output: None,
+            audit_log: None,
+            health_addr: None,
+            health_max_idle_secs: None,
+            grab_fallback: false,
+            use_existing_device: false,
+            existing_pen_device: None,
+            existing_touch_device: None,
+            pen_tail_trim: 0,
+            pen_smoothing_low: None,
+            pen_smoothing_high: None,
+            pen_pressure_min: None,
+            pen_pressure_max: None,
+            fit_screen: false,
+            screen_size: None,
+            pen_bus: None,
+            pen_vendor: None,
+            pen_product: None,
+            pen_auto_range: false,
         }
     }
 }
 
-fn default_host() -> String {
-    DEFAULT_HOST.into()
+/// `key_path`: one key file or a list of them, tried in order.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum KeyPaths {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl KeyPaths {
+    pub fn into_vec(self) -> Vec<String> {
+        match self {
+            KeyPaths::One(path) => vec![path],
+            KeyPaths::Many(paths) => paths,
+        }
+    }
 }
 
 fn default_true() -> bool {
@@ -57,7 +254,7 @@ pub fn load_from_path(path: &Path) -> Option<FileConfig> {
     let content = std::fs::read_to_string(path).ok()?;
     match toml::from_str(&content) {
         Ok(config) => {
-            log::debug!("Loaded config from {}", path.display());
+            log::info!("Loaded config from {}", path.display());
             Some(config)
         }
         Err(e) => {
@@ -68,24 +265,74 @@ pub fn load_from_path(path: &Path) -> Option<FileConfig> {
 }
 
 pub fn load_from_default_paths() -> Option<FileConfig> {
-    for path in default_config_paths() {
-        if path.exists() {
-            if let Some(config) = load_from_path(&path) {
-                return Some(config);
-            }
-        }
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let xdg_config_home = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from);
+    let paths = default_config_paths(home.as_deref(), xdg_config_home.as_deref());
+
+    let config = paths.iter().filter(|p| p.exists()).find_map(|p| load_from_path(p));
+    if config.is_none() {
+        log::debug!("No config file found, using defaults");
     }
-    None
+    config
 }
 
-fn default_config_paths() -> Vec<PathBuf> {
-    let mut paths = Vec::new();
+/// Config file candidates, highest precedence first: the current
+/// directory, the XDG config directory, the legacy `~/.config/rm-pad.toml`
+/// and finally the system-wide `/etc/rm-pad.toml`.
+fn default_config_paths(home: Option<&Path>, xdg_config_home: Option<&Path>) -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from("rm-pad.toml")];
 
-    paths.push(PathBuf::from("rm-pad.toml"));
+    // The XDG spec says to ignore a relative XDG_CONFIG_HOME
+    let xdg = xdg_config_home
+        .filter(|p| p.is_absolute())
+        .map(Path::to_path_buf)
+        .or_else(|| home.map(|h| h.join(".config")));
+    if let Some(xdg) = xdg {
+        paths.push(xdg.join("rm-pad").join("config.toml"));
+    }
 
-    if let Ok(home) = std::env::var("HOME") {
-        paths.push(PathBuf::from(home).join(".config").join("rm-pad.toml"));
+    if let Some(home) = home {
+        paths.push(home.join(".config").join("rm-pad.toml"));
     }
 
+    paths.push(PathBuf::from("/etc/rm-pad.toml"));
     paths
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_config_path_precedence() {
+        let root = std::env::temp_dir().join(format!("rm-pad-config-test-{}", std::process::id()));
+        let home = root.join("home");
+        let xdg = root.join("xdg");
+        let xdg_file = xdg.join("rm-pad").join("config.toml");
+        let legacy_file = home.join(".config").join("rm-pad.toml");
+        std::fs::create_dir_all(xdg_file.parent().unwrap()).unwrap();
+        std::fs::create_dir_all(legacy_file.parent().unwrap()).unwrap();
+        std::fs::write(&xdg_file, "").unwrap();
+        std::fs::write(&legacy_file, "").unwrap();
+
+        let first = |home: &Path, xdg: Option<&Path>| {
+            default_config_paths(Some(home), xdg)
+                .into_iter()
+                .skip(1) // ./rm-pad.toml depends on where the tests run
+                .find(|p| p.exists())
+        };
+
+        assert_eq!(first(&home, Some(&xdg)), Some(xdg_file.clone()));
+        // A relative XDG_CONFIG_HOME is ignored in favour of ~/.config
+        assert_eq!(first(&home, Some(Path::new("relative"))), Some(legacy_file.clone()));
+        std::fs::remove_file(&xdg_file).unwrap();
+        assert_eq!(first(&home, Some(&xdg)), Some(legacy_file));
+
+        let paths = default_config_paths(Some(&home), None);
+        assert_eq!(paths.first(), Some(&PathBuf::from("rm-pad.toml")));
+        assert_eq!(paths.get(1), Some(&home.join(".config").join("rm-pad").join("config.toml")));
+        assert_eq!(paths.last(), Some(&PathBuf::from("/etc/rm-pad.toml")));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
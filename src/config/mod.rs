@@ -3,32 +3,276 @@ mod file;
 
 pub use cli::{Cli, Command};
 
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
 use std::path::PathBuf;
+use std::str::FromStr;
 
 use crate::device::DeviceProfile;
+use crate::input::{PenContactSource, PenHover, PenMode, RegionExit, PrimaryContact, TouchMode};
 use crate::orientation::Orientation;
+use crate::secret;
+
+/// The tablet's address over USB, used when no host is configured.
+pub const DEFAULT_HOST: &str = "10.11.99.1";
+
+/// Key files tried in order when no `key_path` is configured.
+const DEFAULT_KEY_CANDIDATES: &[&str] = &[
+    "rm-key",
+    "~/.config/rm-pad/rm-key",
+    "~/.ssh/id_ed25519",
+    "~/.ssh/id_rsa",
+];
+
+/// Key written by `rm-pad setup` unless `key_path` is set; one of the candidates.
+const DEFAULT_SETUP_KEY: &str = "~/.config/rm-pad/rm-key";
+
+/// Bus, vendor and product of the pen device unless configured: USB and
+/// the ids libwacom is matched against.
+const DEFAULT_PEN_ID: (u32, u32, u32) = (0x03, 0x2d1f, 0x0001);
+
+/// Keyring service name used when `keyring_service` is unset.
+const DEFAULT_KEYRING_SERVICE: &str = "rm-pad";
 
 /// Authentication method for SSH connection.
 #[derive(Clone)]
 pub enum Auth {
-    Key(PathBuf),
+    /// Private key files, tried in order until one is accepted, and the
+    /// passphrase for encrypted ones (prompted for if unset).
+    Keys(Vec<PathBuf>, Option<String>),
     Password(String),
 }
 
+/// Algorithm preferences for the SSH handshake (`ssh_ciphers`, `ssh_kex`)
+/// and the TCP socket option under it (`tcp_nodelay`).
+/// Each is a comma-separated list in order of preference; unset keeps the
+/// library default.
+#[derive(Clone, Default)]
+pub struct SshMethods {
+    pub ciphers: Option<String>,
+    pub kex: Option<String>,
+    pub tcp_nodelay: bool,
+}
+
+/// Which IP family to connect over (`address_family` option).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AddressFamily {
+    /// Any resolved address, in resolver order.
+    #[default]
+    Any,
+    Ipv4,
+    Ipv6,
+}
+
+impl AddressFamily {
+    pub fn matches(&self, addr: &std::net::SocketAddr) -> bool {
+        match self {
+            AddressFamily::Any => true,
+            AddressFamily::Ipv4 => addr.is_ipv4(),
+            AddressFamily::Ipv6 => addr.is_ipv6(),
+        }
+    }
+}
+
+impl fmt::Display for AddressFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressFamily::Any => write!(f, "any"),
+            AddressFamily::Ipv4 => write!(f, "IPv4"),
+            AddressFamily::Ipv6 => write!(f, "IPv6"),
+        }
+    }
+}
+
+/// How the SSH credentials are chosen (`auth` option).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthMethod {
+    /// Password if set, otherwise key files.
+    #[default]
+    Auto,
+    /// Password from the system keyring.
+    Keyring,
+}
+
+impl fmt::Display for AuthMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthMethod::Auto => write!(f, "auto"),
+            AuthMethod::Keyring => write!(f, "keyring"),
+        }
+    }
+}
+
+impl FromStr for AuthMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(AuthMethod::Auto),
+            "keyring" => Ok(AuthMethod::Keyring),
+            _ => Err(format!("Invalid auth method '{}'. Valid values: auto, keyring", s)),
+        }
+    }
+}
+
+/// Where forwarded input goes (`output` option).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputBackend {
+    /// Virtual uinput devices (needs write access to /dev/uinput).
+    #[default]
+    Uinput,
+    /// Wayland virtual pointer (pen only, wlroots compositors).
+    Wayland,
+}
+
+impl fmt::Display for OutputBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputBackend::Uinput => write!(f, "uinput"),
+            OutputBackend::Wayland => write!(f, "wayland"),
+        }
+    }
+}
+
+impl FromStr for OutputBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "uinput" => Ok(OutputBackend::Uinput),
+            "wayland" => Ok(OutputBackend::Wayland),
+            _ => Err(format!("Invalid output '{}'. Valid values: uinput, wayland", s)),
+        }
+    }
+}
+
 /// Merged configuration from CLI args and TOML file.
 #[derive(Debug, Clone)]
 pub struct Config {
     pub host: String,
-    pub key_path: Option<String>,
+    /// No host was given on the command line or in the config file.
+    pub host_defaulted: bool,
+    pub no_default_host: bool,
+    /// Profile name (`rm2`, `rmpp`) for when model detection fails.
+    pub device_model: Option<String>,
+    /// Key files to try in order; empty to try `key_candidates`.
+    pub key_path: Vec<String>,
+    pub key_passphrase: Option<String>,
+    pub key_candidates: Vec<String>,
     pub password: Option<String>,
+    pub auth: AuthMethod,
+    pub keyring_service: String,
+    pub keyring_account: String,
+    pub address_family: AddressFamily,
+    pub ssh_ciphers: Option<String>,
+    pub ssh_kex: Option<String>,
+    pub tcp_nodelay: bool,
     pub pen_device: String,
     pub touch_device: String,
+    pub keyboard_device: Option<String>,
+    pub pause_on_sleep: bool,
+    /// Defaults to the device profile's power button, if it has one.
+    pub power_device: Option<String>,
     pub touch_only: bool,
     pub pen_only: bool,
     pub grab_input: bool,
     pub no_palm_rejection: bool,
     pub palm_grace_ms: u64,
-    pub orientation: Orientation,
+    pub palm_grace_from_activity: bool,
+    pub palm_cancel: bool,
+    pub pen_orientation: Orientation,
+    pub touch_orientation: Orientation,
+    pub pen_contact_source: PenContactSource,
+    pub pen_hover: PenHover,
+    pub pen_mode: PenMode,
+    pub pen_relative_sensitivity: f64,
+    pub touch_mode: TouchMode,
+    pub touch_primary: PrimaryContact,
+    pub max_fingers: Option<u32>,
+    pub touch_hover: bool,
+    pub touch_mt_only: bool,
+    pub touch_changed_only: bool,
+    /// Drop pen and touch frames that change nothing.
+    pub skip_repeated_frames: bool,
+    pub touch_as_pen: bool,
+    pub touch_motion_deadzone: i32,
+    /// Evdev name of the local key that has to be held for touch to pass.
+    pub touch_gate_key: Option<String>,
+    pub touch_lift_debounce_ms: u64,
+    pub gesture_hold_ms: u64,
+    /// Swipe name (e.g. "3-left") to `+`-separated key names.
+    pub gesture_keys: BTreeMap<String, String>,
+    pub min_position_delta: i32,
+    pub max_event_rate: Option<u32>,
+    pub warmup_discard_ms: u64,
+    pub offset_x: i32,
+    pub offset_y: i32,
+    pub region_exit: RegionExit,
+    pub pace: bool,
+    pub pen_button_click: bool,
+    pub idle_disconnect_secs: Option<u64>,
+    pub touch_resolution: Option<i32>,
+    pub touch_width_mm: Option<f64>,
+    pub auto_fix_devices: bool,
+    pub wake_input: bool,
+    pub output: OutputBackend,
+    pub remote_dir: String,
+    pub stream_command: Option<String>,
+    pub wait_for: Option<String>,
+    pub on_connect: Option<String>,
+    pub on_disconnect: Option<String>,
+    pub notify: bool,
+    pub device_group: Option<String>,
+    pub tee_output: Option<PathBuf>,
+    pub audit_log: Option<PathBuf>,
+    pub health_addr: Option<String>,
+    pub health_max_idle_secs: Option<u64>,
+    pub grab_fallback: bool,
+    pub use_existing_device: bool,
+    pub existing_pen_device: Option<String>,
+    pub existing_touch_device: Option<String>,
+    pub pen_tail_trim: usize,
+    /// EMA weight of a new pen sample at minimum and at full pressure.
+    pub pen_smoothing_low: f64,
+    pub pen_smoothing_high: f64,
+    /// Band the tablet's pressure range is rescaled into.
+    pub pen_pressure_min: i32,
+    pub pen_pressure_max: i32,
+    /// Map the pen and a touchscreen onto a centred area of the screen's shape (`screen_size`, or detected).
+    pub fit_screen: bool,
+    pub screen_size: Option<String>,
+    /// Input id of the pen device, for libwacom matching (checked to fit in 16 bits).
+    pub pen_bus: u32,
+    pub pen_vendor: u32,
+    pub pen_product: u32,
+    pub pen_auto_range: bool,
+    /// Command line only: stop each stream after its first disconnect.
+    pub once: bool,
+    /// Found at startup detection, not configurable; saves asking on every grab.
+    pub tablet_arch: Option<crate::grab::Arch>,
+}
+
+/// A device-specific orientation wins over the shared one, and the command
+/// line over the file.
+fn merge_orientation(
+    cli_device: Option<Orientation>,
+    cli_shared: Option<Orientation>,
+    file_device: Option<Orientation>,
+    file_shared: Orientation,
+) -> Orientation {
+    cli_device.or(cli_shared).or(file_device).unwrap_or(file_shared)
+}
+
+/// `--no-grab-input` always wins, then `--grab-input`, then the file's `grab_input`.
+fn merge_grab_input(cli_no_grab: bool, cli_grab: bool, file_grab: bool) -> bool {
+    if cli_no_grab {
+        return false;
+    }
+    cli_grab || file_grab
 }
 
 impl Config {
@@ -41,10 +285,52 @@ impl Config {
             .or_else(file::load_from_default_paths)
             .unwrap_or_default();
 
+        Self::merge(cli, file_config, device)
+    }
+
+    /// Built-in defaults only, ignoring config files, the real command line
+    /// and `RMPAD_*` variables, so corpus checks and tests are reproducible.
+    pub fn defaults(device: &DeviceProfile) -> Self {
+        use clap::FromArgMatches;
+        let matches = defaults_command().get_matches_from(["rm-pad"]);
+        let cli = Cli::from_arg_matches(&matches).expect("no arguments always parse");
+        Self::merge(&cli, file::FileConfig::default(), device)
+    }
+
+    fn merge(cli: &Cli, file_config: file::FileConfig, device: &DeviceProfile) -> Self {
+        let configured_host = cli.host.clone().or(file_config.host);
+        let host_defaulted = configured_host.is_none();
+        let host = configured_host.unwrap_or_else(|| DEFAULT_HOST.to_string());
+
         Self {
-            host: cli.host.clone().unwrap_or(file_config.host),
-            key_path: cli.key_path.clone().or(file_config.key_path),
+            keyring_account: file_config.keyring_account.unwrap_or_else(|| host.clone()),
+            host,
+            host_defaulted,
+            no_default_host: cli.no_default_host,
+            device_model: cli.device_model.clone().or(file_config.device_model),
+            key_path: match &cli.key_path {
+                paths if !paths.is_empty() => paths.clone(),
+                _ => file_config.key_path.map(file::KeyPaths::into_vec).unwrap_or_default(),
+            },
+            key_passphrase: cli.key_passphrase.clone().or(file_config.key_passphrase),
+            key_candidates: file_config.key_candidates.unwrap_or_else(|| {
+                DEFAULT_KEY_CANDIDATES.iter().map(|s| s.to_string()).collect()
+            }),
             password: cli.password.clone().or(file_config.password),
+            auth: cli.auth.unwrap_or(file_config.auth),
+            address_family: if cli.ipv4 {
+                AddressFamily::Ipv4
+            } else if cli.ipv6 {
+                AddressFamily::Ipv6
+            } else {
+                file_config.address_family
+            },
+            ssh_ciphers: cli.ssh_ciphers.clone().or(file_config.ssh_ciphers),
+            ssh_kex: cli.ssh_kex.clone().or(file_config.ssh_kex),
+            tcp_nodelay: !cli.no_tcp_nodelay && file_config.tcp_nodelay,
+            keyring_service: file_config
+                .keyring_service
+                .unwrap_or_else(|| DEFAULT_KEYRING_SERVICE.into()),
             pen_device: cli
                 .pen_device
                 .clone()
@@ -53,28 +339,153 @@ impl Config {
                 .touch_device
                 .clone()
                 .unwrap_or_else(|| file_config.touch_device.unwrap_or(device.touch_device.into())),
+            keyboard_device: cli.keyboard_device.clone().or(file_config.keyboard_device),
+            pause_on_sleep: cli.pause_on_sleep || file_config.pause_on_sleep,
+            power_device: cli
+                .power_device
+                .clone()
+                .or(file_config.power_device)
+                .or(device.power_device.map(String::from)),
             touch_only: cli.touch_only || file_config.touch_only,
             pen_only: cli.pen_only || file_config.pen_only,
-            grab_input: if cli.no_grab_input {
-                false
-            } else {
-                cli.grab_input || file_config.grab_input
-            },
+            grab_input: merge_grab_input(cli.no_grab_input, cli.grab_input, file_config.grab_input),
             no_palm_rejection: cli.no_palm_rejection || file_config.no_palm_rejection,
             palm_grace_ms: cli
                 .palm_grace_ms
                 .or(file_config.palm_grace_ms)
                 .unwrap_or(500),
-            orientation: cli.orientation.unwrap_or(file_config.orientation),
+            palm_grace_from_activity: cli.palm_grace_from_activity || file_config.palm_grace_from_activity,
+            palm_cancel: cli.palm_cancel || file_config.palm_cancel,
+            pen_orientation: merge_orientation(cli.pen_orientation, cli.orientation, file_config.pen_orientation, file_config.orientation),
+            touch_orientation: merge_orientation(
+                cli.touch_orientation,
+                cli.orientation,
+                file_config.touch_orientation,
+                file_config.orientation,
+            ),
+            pen_contact_source: cli
+                .pen_contact_source
+                .unwrap_or(file_config.pen_contact_source),
+            pen_hover: cli.pen_hover.unwrap_or(file_config.pen_hover),
+            pen_mode: cli.pen_mode.unwrap_or(file_config.pen_mode),
+            pen_relative_sensitivity: cli
+                .pen_relative_sensitivity
+                .or(file_config.pen_relative_sensitivity)
+                .unwrap_or(10.0),
+            touch_mode: cli.touch_mode.unwrap_or(file_config.touch_mode),
+            touch_primary: cli.touch_primary.unwrap_or(file_config.touch_primary),
+            max_fingers: cli.max_fingers.or(file_config.max_fingers),
+            touch_hover: cli.touch_hover || file_config.touch_hover,
+            touch_mt_only: cli.touch_mt_only || file_config.touch_mt_only,
+            touch_changed_only: cli.touch_changed_only || file_config.touch_changed_only,
+            skip_repeated_frames: cli.skip_repeated_frames || file_config.skip_repeated_frames,
+            touch_as_pen: cli.touch_as_pen || file_config.touch_as_pen,
+            touch_motion_deadzone: cli
+                .touch_motion_deadzone
+                .unwrap_or(file_config.touch_motion_deadzone),
+            touch_gate_key: cli.touch_gate_key.clone().or(file_config.touch_gate_key),
+            touch_lift_debounce_ms: cli.touch_lift_debounce_ms.unwrap_or(file_config.touch_lift_debounce_ms),
+            gesture_hold_ms: cli.gesture_hold_ms.unwrap_or(file_config.gesture_hold_ms),
+            gesture_keys: file_config.gesture_keys,
+            min_position_delta: cli
+                .min_position_delta
+                .unwrap_or(file_config.min_position_delta),
+            max_event_rate: cli.max_event_rate.or(file_config.max_event_rate),
+            warmup_discard_ms: cli.warmup_discard_ms.unwrap_or(file_config.warmup_discard_ms),
+            offset_x: cli.offset_x.unwrap_or(file_config.offset_x),
+            offset_y: cli.offset_y.unwrap_or(file_config.offset_y),
+            region_exit: cli.region_exit.unwrap_or(file_config.region_exit),
+            pace: cli.pace || file_config.pace,
+            pen_button_click: cli.pen_button_click || file_config.pen_button_click,
+            idle_disconnect_secs: cli
+                .idle_disconnect_secs
+                .or(file_config.idle_disconnect_secs)
+                .filter(|&secs| secs > 0),
+            touch_resolution: cli.touch_resolution.or(file_config.touch_resolution),
+            touch_width_mm: cli.touch_width_mm.or(file_config.touch_width_mm),
+            auto_fix_devices: cli.auto_fix_devices || file_config.auto_fix_devices,
+            wake_input: cli.wake_input || file_config.wake_input,
+            output: cli.output.unwrap_or(file_config.output),
+            wait_for: cli.wait_for.clone().or(file_config.wait_for),
+            on_connect: cli.on_connect.clone().or(file_config.on_connect),
+            on_disconnect: cli.on_disconnect.clone().or(file_config.on_disconnect),
+            notify: cli.notify || file_config.notify,
+            device_group: cli.device_group.clone().or(file_config.device_group),
+            tee_output: cli.tee_output.clone().or(file_config.tee_output),
+            audit_log: cli.audit_log.clone().or(file_config.audit_log),
+            health_addr: cli.health_addr.clone().or(file_config.health_addr),
+            health_max_idle_secs: cli.health_max_idle_secs.or(file_config.health_max_idle_secs),
+            remote_dir: cli
+                .remote_dir
+                .clone()
+                .or(file_config.remote_dir)
+                .unwrap_or_else(|| crate::grab::DEFAULT_REMOTE_DIR.to_string()),
+            stream_command: cli.stream_command.clone().or(file_config.stream_command),
+            grab_fallback: cli.grab_fallback || file_config.grab_fallback,
+            use_existing_device: cli.use_existing_device || file_config.use_existing_device,
+            existing_pen_device: cli.existing_pen_device.clone().or(file_config.existing_pen_device),
+            existing_touch_device: cli.existing_touch_device.clone().or(file_config.existing_touch_device),
+            pen_tail_trim: cli.pen_tail_trim.unwrap_or(file_config.pen_tail_trim),
+            pen_smoothing_low: cli
+                .pen_smoothing_low
+                .or(file_config.pen_smoothing_low)
+                .unwrap_or(1.0),
+            pen_smoothing_high: cli
+                .pen_smoothing_high
+                .or(file_config.pen_smoothing_high)
+                .unwrap_or(1.0),
+            pen_pressure_min: cli.pen_pressure_min.or(file_config.pen_pressure_min).unwrap_or(0),
+            pen_pressure_max: cli
+                .pen_pressure_max
+                .or(file_config.pen_pressure_max)
+                .unwrap_or(device.pen_pressure_max),
+            fit_screen: cli.fit_screen || file_config.fit_screen,
+            screen_size: cli.screen_size.clone().or(file_config.screen_size),
+            pen_bus: cli.pen_bus.or(file_config.pen_bus).unwrap_or(DEFAULT_PEN_ID.0),
+            pen_vendor: cli.pen_vendor.or(file_config.pen_vendor).unwrap_or(DEFAULT_PEN_ID.1),
+            pen_product: cli.pen_product.or(file_config.pen_product).unwrap_or(DEFAULT_PEN_ID.2),
+            pen_auto_range: cli.pen_auto_range || file_config.pen_auto_range,
+            once: cli.once,
+            tablet_arch: None,
         }
     }
 
+    /// With `auth = "keyring"`, read the password from the keyring into
+    /// `password`, once: every connection, the watchdog's included, then
+    /// uses it without asking the keyring again.
+    pub fn resolve_keyring(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if self.auth == AuthMethod::Keyring {
+            self.password = Some(secret::get_password(&self.keyring_service, &self.keyring_account)?);
+            self.auth = AuthMethod::Auto;
+        }
+        Ok(())
+    }
+
     pub fn auth(&self) -> Auth {
         if let Some(ref password) = self.password {
             return Auth::Password(password.clone());
         }
-        let path = self.key_path.as_deref().unwrap_or("rm-key");
-        Auth::Key(expand_tilde(path))
+        if !self.key_path.is_empty() {
+            return Auth::Keys(self.key_path.iter().map(|p| expand_tilde(p)).collect(), self.key_passphrase.clone());
+        }
+        Auth::Keys(
+            self.key_candidates.iter().map(|p| expand_tilde(p)).collect(),
+            self.key_passphrase.clone(),
+        )
+    }
+
+    /// Where `rm-pad setup` keeps its key: the first `key_path`, else the
+    /// default under ~/.config/rm-pad.
+    pub fn setup_key_path(&self) -> PathBuf {
+        expand_tilde(self.key_path.first().map_or(DEFAULT_SETUP_KEY, String::as_str))
+    }
+
+    pub fn ssh_methods(&self) -> SshMethods {
+        SshMethods {
+            ciphers: self.ssh_ciphers.clone(),
+            kex: self.ssh_kex.clone(),
+            tcp_nodelay: self.tcp_nodelay,
+        }
     }
 
     pub fn run_pen(&self) -> bool {
@@ -85,17 +496,122 @@ impl Config {
         !self.pen_only
     }
 
+    /// With `--no-default-host`, refuse to fall back to the USB address.
+    ///
+    /// Checked before connecting as well as in `validate`, so a missing host
+    /// never turns into a connection attempt.
+    pub fn check_host(&self) -> Result<(), &'static str> {
+        if self.no_default_host && self.host_defaulted {
+            return Err("No host given (set host in the config or pass --host); --no-default-host is set");
+        }
+        Ok(())
+    }
+
     pub fn validate(&self) -> Result<(), &'static str> {
+        self.check_host()?;
         if self.touch_only && self.pen_only {
             return Err("Cannot use both --touch-only and --pen-only");
         }
         if !self.run_pen() && !self.run_touch() {
             return Err("No input device enabled");
         }
+        if self.pen_tail_trim > 2 {
+            return Err("pen_tail_trim must be between 0 and 2");
+        }
+        if self.touch_resolution.is_some_and(|r| r <= 0) {
+            return Err("touch_resolution must be positive");
+        }
+        if self.touch_width_mm.is_some_and(|w| w <= 0.0 || !w.is_finite()) {
+            return Err("touch_width_mm must be positive");
+        }
+        if self.output == OutputBackend::Wayland && self.run_touch() {
+            return Err("output = \"wayland\" only forwards the pen for now; also set pen_only");
+        }
+        if self.output == OutputBackend::Wayland && self.use_existing_device {
+            return Err("use_existing_device only applies to output = \"uinput\"");
+        }
+        if !self.ssh_ciphers.as_deref().is_none_or(is_method_list) {
+            return Err("ssh_ciphers must be a comma-separated list of algorithm names");
+        }
+        if !self.ssh_kex.as_deref().is_none_or(is_method_list) {
+            return Err("ssh_kex must be a comma-separated list of algorithm names");
+        }
+        if self.pause_on_sleep && self.power_device.is_none() {
+            return Err("pause_on_sleep needs power_device on this tablet: its power button's input node (see rm-pad caps)");
+        }
+        if self.touch_as_pen && self.touch_mode == TouchMode::Touchscreen {
+            return Err("touch_as_pen replaces the touch device; drop touch_mode = \"touchscreen\"");
+        }
+        if self.touch_as_pen && self.use_existing_device {
+            return Err("touch_as_pen creates its own device and cannot be combined with use_existing_device");
+        }
+        let unsafe_char = |c: char| c.is_whitespace() || "'\"$`\\;&|".contains(c);
+        if !self.remote_dir.starts_with('/') || self.remote_dir.contains(unsafe_char) {
+            return Err("remote_dir must be an absolute path without spaces or shell special characters");
+        }
+        if self.stream_command.as_deref().is_some_and(|cmd| !cmd.contains(crate::ssh::DEVICE_PLACEHOLDER)) {
+            return Err("stream_command must contain {device}, where the device path goes");
+        }
+        if self.device_group.as_deref().is_some_and(|g| crate::input::resolve_group(g).is_err()) {
+            return Err("Unknown device_group; use a group name or a numeric gid");
+        }
+        if self.device_group.is_some() && (self.use_existing_device || self.output == OutputBackend::Wayland) {
+            return Err("device_group only applies to devices rm-pad creates (output = \"uinput\" without use_existing_device)");
+        }
+        if self.touch_gate_key.as_deref().is_some_and(|k| crate::input::parse_gate_key(k).is_err()) {
+            return Err("Invalid touch_gate_key; use an evdev key name like KEY_LEFTCTRL");
+        }
+        if crate::input::parse_gesture_bindings(&self.gesture_keys).is_err() {
+            return Err("Invalid gesture_keys entry; expected e.g. \"3-left\" = \"KEY_LEFTMETA+KEY_PAGEUP\"");
+        }
+        if self.max_fingers == Some(0) {
+            return Err("max_fingers must be at least 1");
+        }
+        if self.touch_motion_deadzone < 0 {
+            return Err("touch_motion_deadzone must not be negative");
+        }
+        if self.min_position_delta < 0 {
+            return Err("min_position_delta must not be negative");
+        }
+        if self.max_event_rate == Some(0) {
+            return Err("max_event_rate must be at least 1");
+        }
+        let valid_weight = |w: f64| w > 0.0 && w <= 1.0;
+        if !valid_weight(self.pen_smoothing_low) || !valid_weight(self.pen_smoothing_high) {
+            return Err("pen_smoothing_low and pen_smoothing_high must be in (0, 1]");
+        }
+        if self.pen_pressure_min < 0 || self.pen_pressure_max <= self.pen_pressure_min {
+            return Err("pen_pressure_min must not be negative and must be below pen_pressure_max");
+        }
+        if self.pen_mode == PenMode::Relative && (self.output == OutputBackend::Wayland || self.use_existing_device) {
+            return Err("pen_mode = \"relative\" creates its own uinput device; it can't be combined with output = \"wayland\" or use_existing_device");
+        }
+        if self.pen_relative_sensitivity <= 0.0 || self.pen_relative_sensitivity.is_nan() {
+            return Err("pen_relative_sensitivity must be above 0");
+        }
+        if self.screen_size.as_deref().is_some_and(|s| crate::screen::parse_size(s).is_err()) {
+            return Err("screen_size must be WxH, e.g. 1920x1080");
+        }
+        if [self.pen_bus, self.pen_vendor, self.pen_product].iter().any(|&id| id > 0xffff) {
+            return Err("pen_bus, pen_vendor and pen_product must be at most 0xffff");
+        }
+        if self.pen_auto_range && self.use_existing_device {
+            return Err("pen_auto_range resizes the pen device rm-pad creates and cannot be combined with use_existing_device");
+        }
         Ok(())
     }
 }
 
+/// Whether `list` looks like `name[,name...]` with SSH algorithm names.
+fn is_method_list(list: &str) -> bool {
+    list.split(',').all(|name| {
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_.@".contains(c))
+    })
+}
+
 /// Expand a leading `~` or `~/` to the user's home directory.
 fn expand_tilde(path: &str) -> PathBuf {
     if path == "~" {
@@ -109,3 +625,103 @@ fn expand_tilde(path: &str) -> PathBuf {
     }
     PathBuf::from(path)
 }
+
+/// The command line parser with every `env = "RMPAD_*"` fallback removed.
+fn defaults_command() -> clap::Command {
+    use clap::CommandFactory;
+    Cli::command().mut_args(|arg| arg.env(None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::RM2;
+    use clap::Parser;
+
+    #[test]
+    fn test_defaults_ignore_environment() {
+        use clap::CommandFactory;
+        assert!(Cli::command().get_arguments().any(|arg| arg.get_env().is_some()));
+        assert!(defaults_command().get_arguments().all(|arg| arg.get_env().is_none()));
+        assert_eq!(Config::defaults(&RM2).host, DEFAULT_HOST);
+    }
+
+    #[test]
+    fn test_orientation_precedence() {
+        let merged = |args: &[&str], file_config: file::FileConfig| {
+            let cli = Cli::parse_from(std::iter::once("rm-pad").chain(args.iter().copied()));
+            let config = Config::merge(&cli, file_config, &RM2);
+            (config.pen_orientation, config.touch_orientation)
+        };
+        let file_config = || file::FileConfig {
+            orientation: Orientation::Inverted,
+            touch_orientation: Some(Orientation::Portrait),
+            ..Default::default()
+        };
+
+        assert_eq!(merged(&[], file_config()), (Orientation::Inverted, Orientation::Portrait));
+        // --orientation sets both and wins over the file's device-specific value
+        assert_eq!(
+            merged(&["--orientation", "landscape-left"], file_config()),
+            (Orientation::LandscapeLeft, Orientation::LandscapeLeft)
+        );
+        assert_eq!(
+            merged(&["--orientation", "landscape-left", "--pen-orientation", "portrait"], file_config()),
+            (Orientation::Portrait, Orientation::LandscapeLeft)
+        );
+        assert_eq!(merged(&[], file::FileConfig::default()), (Orientation::default(), Orientation::default()));
+    }
+
+    #[test]
+    fn test_grab_input_precedence() {
+        for file_grab in [false, true] {
+            let merged = |args: &[&str]| {
+                let cli = Cli::parse_from(std::iter::once("rm-pad").chain(args.iter().copied()));
+                let file_config = file::FileConfig {
+                    grab_input: file_grab,
+                    ..Default::default()
+                };
+                Config::merge(&cli, file_config, &RM2).grab_input
+            };
+
+            assert_eq!(merged(&[]), file_grab);
+            assert!(merged(&["--grab-input"]));
+            assert!(!merged(&["--no-grab-input"]));
+            assert!(!merged(&["--grab-input", "--no-grab-input"]));
+            assert!(!merged(&["--no-grab-input", "--grab-input"]));
+
+            // The short aliases behave exactly like the full flags
+            assert!(merged(&["--grab"]));
+            assert!(!merged(&["--no-grab"]));
+            assert!(!merged(&["--grab", "--no-grab"]));
+        }
+    }
+
+    #[test]
+    fn test_pen_input_id() {
+        let merged = |args: &[&str]| {
+            let cli = Cli::parse_from(std::iter::once("rm-pad").chain(args.iter().copied()));
+            Config::merge(&cli, file::FileConfig::default(), &RM2)
+        };
+
+        let config = merged(&[]);
+        assert_eq!((config.pen_bus, config.pen_vendor, config.pen_product), DEFAULT_PEN_ID);
+        assert_eq!(merged(&["--pen-vendor", "0x056a", "--pen-product", "891"]).pen_vendor, 0x056a);
+        assert!(merged(&["--pen-product", "0x10000"]).validate().is_err());
+        assert!(Cli::try_parse_from(["rm-pad", "--pen-bus", "usb"]).is_err());
+    }
+
+    #[test]
+    fn test_key_path_list() {
+        let merged = |args: &[&str], toml: &str| {
+            let cli = Cli::parse_from(std::iter::once("rm-pad").chain(args.iter().copied()));
+            Config::merge(&cli, toml::from_str(toml).unwrap(), &RM2).key_path
+        };
+
+        assert_eq!(merged(&[], r#"key_path = "/keys/a""#), ["/keys/a"]);
+        assert_eq!(merged(&[], r#"key_path = ["/keys/a", "/keys/b"]"#), ["/keys/a", "/keys/b"]);
+        // The command line replaces the file's list
+        assert_eq!(merged(&["--key-path", "/c", "--key-path", "/d"], r#"key_path = "/keys/a""#), ["/c", "/d"]);
+        assert!(merged(&[], "").is_empty());
+    }
+}
@@ -0,0 +1,223 @@
+//! Regression corpus: replay recorded streams through the decoders.
+//!
+//! A recording `<profile>-<kind>-<name>.bin` is a raw event stream as
+//! produced by `cat /dev/input/eventN` on the tablet, where `profile` is
+//! `rm2` or `rmpp` and `kind` is `pen` or `touch`. The shipped recordings
+//! are synthetic, written by hand in the rM2's event format rather than
+//! captured from a tablet; like evdev, `rm2-pen-sparse-axes` only reports
+//! the axes that changed in each frame. Recordings are replayed with `Config::defaults`. Next to it,
+//! `<same name>.json` holds the frames the decoder is expected to emit,
+//! each a list of `[type, code, value]` triples as handed to the sink.
+//!
+//! `rm-pad check-corpus` diffs every recording against its expectation;
+//! `--update` rewrites the expectations from the current decoder output.
+//!
+//! A recording may also be zstd-compressed as `<name>.bin.zst` (with the
+//! `zstd` cargo feature), which keeps long captures small; its expectation
+//! is still `<name>.json`.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use evdevil::event::InputEvent;
+
+use crate::config::Config;
+use crate::device::{DeviceProfile, RM2, RMPP};
+use crate::input::{self, RecordingSink};
+
+type Frame = Vec<(u16, u16, i32)>;
+
+/// Replay a recording through the decoder for `kind` ("pen" or "touch").
+pub fn replay(
+    path: &Path,
+    device: &DeviceProfile,
+    kind: &str,
+) -> Result<Vec<Frame>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut reader = open_recording(path)?;
+    let mut sink = RecordingSink::default();
+    let config = Config::defaults(device);
+
+    let result = match kind {
+        "pen" => input::run_pen_loop(&mut reader, &mut sink, device, &config, None, None),
+        "touch" => input::run_touch_loop(&mut reader, &mut sink, device, &config, None, false, None),
+        _ => return Err(format!("Unknown recording kind '{}'", kind).into()),
+    };
+
+    // The loops only stop on a read error; running out of recording is the normal end.
+    match result {
+        Err(e) if is_eof(e.as_ref()) => {}
+        Err(e) => return Err(e),
+        Ok(()) => {}
+    }
+
+    Ok(sink.frames.iter().map(|f| frame_triples(f)).collect())
+}
+
+/// Check every recording in `dir`. Returns the number of mismatches.
+pub fn check(dir: &Path, update: bool) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let mut recordings: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| is_recording(path))
+        .collect();
+    recordings.sort();
+
+    if recordings.is_empty() {
+        return Err(format!("No recordings (*.bin, *.bin.zst) in {}", dir.display()).into());
+    }
+
+    let mut failures = 0;
+    for path in &recordings {
+        let (device, kind) = parse_name(path)?;
+        let frames = replay(path, device, kind)?;
+        let expected_path = raw_path(path).with_extension("json");
+
+        if update {
+            std::fs::write(&expected_path, to_json(&frames))?;
+            println!("updated  {} ({} frames)", expected_path.display(), frames.len());
+            continue;
+        }
+
+        let expected: Vec<Frame> = match std::fs::read_to_string(&expected_path) {
+            Ok(content) => serde_json::from_str(&content)?,
+            Err(e) => return Err(format!("{}: {}", expected_path.display(), e).into()),
+        };
+
+        match first_difference(&expected, &frames) {
+            None => println!("ok       {} ({} frames)", path.display(), frames.len()),
+            Some(msg) => {
+                println!("MISMATCH {}: {}", path.display(), msg);
+                failures += 1;
+            }
+        }
+    }
+
+    Ok(failures)
+}
+
+/// Open a recording, decompressing `.zst` ones.
+pub fn open_recording(path: &Path) -> Result<Box<dyn Read>, Box<dyn std::error::Error + Send + Sync>> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if path.extension().is_some_and(|ext| ext == "zst") {
+        return zstd_decoder(file);
+    }
+    Ok(Box::new(BufReader::new(file)))
+}
+
+#[cfg(feature = "zstd")]
+fn zstd_decoder(file: File) -> Result<Box<dyn Read>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(Box::new(zstd::Decoder::new(file)?))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_decoder(_file: File) -> Result<Box<dyn Read>, Box<dyn std::error::Error + Send + Sync>> {
+    Err("rm-pad was built without zstd support (rebuild with `--features zstd`)".into())
+}
+
+fn is_recording(path: &Path) -> bool {
+    raw_path(path).extension().is_some_and(|ext| ext == "bin")
+}
+
+/// `name.bin` for `name.bin.zst`, otherwise the path itself.
+fn raw_path(path: &Path) -> PathBuf {
+    match path.extension() {
+        Some(ext) if ext == "zst" => path.with_extension(""),
+        _ => path.to_path_buf(),
+    }
+}
+
+pub fn parse_name(path: &Path) -> Result<(&'static DeviceProfile, &'static str), Box<dyn std::error::Error + Send + Sync>> {
+    let raw = raw_path(path);
+    let stem = raw.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+    let mut parts = stem.splitn(3, '-');
+
+    let device = match parts.next() {
+        Some("rm2") => &RM2,
+        Some("rmpp") => &RMPP,
+        _ => return Err(format!("{}: name must start with rm2- or rmpp-", path.display()).into()),
+    };
+    let kind = match parts.next() {
+        Some("pen") => "pen",
+        Some("touch") => "touch",
+        _ => return Err(format!("{}: expected pen or touch after the profile", path.display()).into()),
+    };
+
+    Ok((device, kind))
+}
+
+fn frame_triples(events: &[InputEvent]) -> Frame {
+    events
+        .iter()
+        .map(|e| (e.event_type().raw(), e.raw_code(), e.raw_value()))
+        .collect()
+}
+
+/// One frame per line keeps the expectation files diffable.
+fn to_json(frames: &[Frame]) -> String {
+    let lines: Vec<String> = frames
+        .iter()
+        .map(|f| serde_json::to_string(f).unwrap_or_default())
+        .collect();
+    format!("[\n{}\n]\n", lines.join(",\n"))
+}
+
+fn first_difference(expected: &[Frame], actual: &[Frame]) -> Option<String> {
+    for (i, (e, a)) in expected.iter().zip(actual).enumerate() {
+        if e != a {
+            return Some(format!("frame {} differs: expected {:?}, got {:?}", i, e, a));
+        }
+    }
+    if expected.len() != actual.len() {
+        return Some(format!("expected {} frames, got {}", expected.len(), actual.len()));
+    }
+    None
+}
+
+pub fn is_eof(e: &(dyn std::error::Error + 'static)) -> bool {
+    e.downcast_ref::<io::Error>()
+        .is_some_and(|e| e.kind() == io::ErrorKind::UnexpectedEof)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_corpus_matches() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("corpus");
+        assert_eq!(check(&dir, false).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_compressed_recording_names() {
+        let path = Path::new("corpus/rm2-pen-long.bin.zst");
+        assert!(is_recording(path) && !is_recording(Path::new("corpus/rm2-pen-long.zst")));
+        assert_eq!(raw_path(path).with_extension("json"), Path::new("corpus/rm2-pen-long.json"));
+        assert_eq!(parse_name(path).unwrap().1, "pen");
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd_recording_replays_the_same() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("corpus");
+        let raw = dir.join("rm2-pen-stroke.bin");
+        let compressed = std::env::temp_dir().join(format!("rm2-pen-stroke-{}.bin.zst", std::process::id()));
+        std::fs::write(&compressed, zstd::encode_all(&std::fs::read(&raw).unwrap()[..], 0).unwrap()).unwrap();
+
+        let frames = replay(&compressed, &RM2, "pen").unwrap();
+        std::fs::remove_file(&compressed).unwrap();
+        assert_eq!(frames, replay(&raw, &RM2, "pen").unwrap());
+    }
+
+    /// The no-hardware path from detection to forwarded frames: a fake
+    /// model string picks the profile, a recording stands in for the channel.
+    #[test]
+    fn test_detect_and_forward() {
+        let device = DeviceProfile::from_model("reMarkable 2.0\0").unwrap();
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("corpus");
+
+        let pen = replay(&dir.join("rm2-pen-stroke.bin"), device, "pen").unwrap();
+        let touch = replay(&dir.join("rm2-touch-tap.bin"), device, "touch").unwrap();
+        assert!(!pen.is_empty() && !touch.is_empty());
+    }
+}
@@ -0,0 +1,121 @@
+//! Run rm-pad in the background with an optional PID file.
+//!
+//! Daemonizing happens before any SSH connection or thread is created, so
+//! the watchdog, grab helper and `GrabCleanup` guards all live in the final
+//! child process and behave exactly as in the foreground.
+
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// PID file path as a C string, for removal from the signal handler.
+static PIDFILE_CPATH: OnceLock<CString> = OnceLock::new();
+
+/// Removes the PID file when dropped.
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            log::warn!("Failed to remove pid file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Detach from the terminal (double fork + setsid) and redirect output.
+///
+/// Stdout and stderr go to `log_file` if given, otherwise to /dev/null.
+/// The working directory is kept so relative config and key paths still work.
+pub fn daemonize(
+    pidfile: Option<&Path>,
+    log_file: Option<&Path>,
+) -> Result<Option<PidFile>, Box<dyn std::error::Error + Send + Sync>> {
+    // Open files before forking so errors still reach the terminal.
+    let devnull = OpenOptions::new().read(true).write(true).open("/dev/null")?;
+    let log = match log_file {
+        Some(path) => OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Failed to open log file {}: {}", path.display(), e))?,
+        None => devnull.try_clone()?,
+    };
+
+    fork_and_exit_parent()?;
+    if unsafe { libc::setsid() } < 0 {
+        return Err(format!("setsid failed: {}", std::io::Error::last_os_error()).into());
+    }
+    fork_and_exit_parent()?;
+
+    redirect(&devnull, libc::STDIN_FILENO)?;
+    redirect(&log, libc::STDOUT_FILENO)?;
+    redirect(&log, libc::STDERR_FILENO)?;
+
+    let Some(path) = pidfile else {
+        return Ok(None);
+    };
+
+    std::fs::write(path, format!("{}\n", std::process::id()))
+        .map_err(|e| format!("Failed to write pid file {}: {}", path.display(), e))?;
+
+    let _ = PIDFILE_CPATH.set(CString::new(path.as_os_str().as_encoded_bytes())?);
+    install_exit_handlers();
+
+    Ok(Some(PidFile {
+        path: path.to_path_buf(),
+    }))
+}
+
+fn fork_and_exit_parent() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    match unsafe { libc::fork() } {
+        -1 => Err(format!("fork failed: {}", std::io::Error::last_os_error()).into()),
+        0 => Ok(()),
+        _ => unsafe { libc::_exit(0) },
+    }
+}
+
+fn redirect(file: &File, target: libc::c_int) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if unsafe { libc::dup2(file.as_raw_fd(), target) } < 0 {
+        return Err(format!("dup2 failed: {}", std::io::Error::last_os_error()).into());
+    }
+    Ok(())
+}
+
+/// Remove the PID file on SIGTERM/SIGINT, which otherwise end the process
+/// without running destructors. Forwarding replaces these with its stop
+/// handlers once it starts.
+fn install_exit_handlers() {
+    let handler = handle_exit_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGTERM, handler);
+        libc::signal(libc::SIGINT, handler);
+    }
+}
+
+extern "C" fn handle_exit_signal(_sig: libc::c_int) {
+    exit_now();
+}
+
+/// Remove the PID file, if any, and exit without running destructors.
+/// Async-signal-safe.
+pub fn exit_now() {
+    remove_pidfile();
+    unsafe { libc::_exit(0) };
+}
+
+/// Remove the PID file, if any, and exit with `code`. For error exits, which
+/// skip the `PidFile` guard's drop like any `process::exit`.
+pub fn exit(code: i32) -> ! {
+    remove_pidfile();
+    std::process::exit(code)
+}
+
+fn remove_pidfile() {
+    if let Some(path) = PIDFILE_CPATH.get() {
+        unsafe { libc::unlink(path.as_ptr()) };
+    }
+}
@@ -1,11 +1,29 @@
 mod rm2;
 mod rmpp;
 
-use std::io::Read;
+use crate::grab::Arch;
+use crate::ssh;
 
 pub use rm2::RM2;
 pub use rmpp::RMPP;
 
+/// Where the model string can be found, in the order tried. Custom kernels
+/// don't always have `/proc/device-tree`; the i.MX SoC driver also exposes
+/// the device-tree model as `soc0/machine`.
+const MODEL_FILES: [&str; 3] = [
+    "/proc/device-tree/model",
+    "/sys/firmware/devicetree/base/model",
+    "/sys/devices/soc0/machine",
+];
+
+/// What startup detection found out about the tablet.
+#[derive(Debug, Clone, Copy)]
+pub struct DetectedDevice {
+    pub profile: &'static DeviceProfile,
+    /// `None` if `uname -m` gave something the grab helper isn't built for.
+    pub arch: Option<Arch>,
+}
+
 /// Device-specific parameters for input handling.
 #[derive(Debug, Clone, Copy)]
 pub struct DeviceProfile {
@@ -30,6 +48,8 @@ pub struct DeviceProfile {
     // Default device paths
     pub pen_device: &'static str,
     pub touch_device: &'static str,
+    /// Power button, for `pause_on_sleep`; `None` where it isn't known.
+    pub power_device: Option<&'static str>,
 }
 
 impl DeviceProfile {
@@ -41,24 +61,56 @@ impl DeviceProfile {
     }
 
     /// Detect device via SSH connection.
-    /// 
-    /// Reads the device model from /proc/device-tree/model on the remote device.
-    /// Returns an error if the model cannot be detected or is unsupported.
-    pub fn detect_via_ssh(session: &ssh2::Session) -> Result<&'static Self, Box<dyn std::error::Error + Send + Sync>> {
-        let mut channel = session.channel_session()?;
-        channel.exec("cat /proc/device-tree/model")?;
-
-        let mut output = String::new();
-        channel.read_to_string(&mut output)?;
-        channel.close()?;
-        channel.wait_close()?;
-
-        let status = channel.exit_status()?;
-        if status != 0 {
-            return Err(format!("Failed to read device model (exit status {})", status).into());
+    ///
+    /// Reads the device model and `uname -m` in one command; the
+    /// architecture is kept so the grab helper upload doesn't ask again.
+    /// See `resolve_profile` for how the model files and the architecture
+    /// are combined. If that fails, `fall_back` picks the profile.
+    pub fn detect_via_ssh(session: &ssh2::Session, configured: Option<&'static Self>) -> DetectedDevice {
+        // `echo` ends the model (which has no newline) and keeps the exit status 0
+        let output = match ssh::run_command(session, &format!("cat {} 2>/dev/null; echo; uname -m", MODEL_FILES[0])) {
+            Ok(output) => output,
+            Err(e) => {
+                return DetectedDevice {
+                    profile: fall_back(&e.to_string(), configured),
+                    arch: None,
+                }
+            }
+        };
+        let (model, machine) = split_detection_output(&output);
+        let arch = Arch::from_uname(machine);
+        log::debug!("Remote architecture: {}", machine);
+
+        let profile = resolve_profile(model, machine, |path| {
+            ssh::run_command(session, &format!("cat {}", path)).map_err(|e| e.to_string())
+        })
+        .unwrap_or_else(|e| fall_back(&e, configured));
+        DetectedDevice { profile, arch }
+    }
+
+    /// Profile for a `device_model` name, `rm2` or `rmpp` as in the
+    /// corpus file names.
+    pub fn from_name(name: &str) -> Result<&'static Self, String> {
+        match name.trim().to_lowercase().as_str() {
+            "rm2" => Ok(&RM2),
+            "rmpp" => Ok(&RMPP),
+            _ => Err(format!("Invalid device model '{}'. Valid values: rm2, rmpp", name)),
+        }
+    }
+
+    /// Guess the profile from `uname -m`: the reMarkable 2 has a 32-bit ARM
+    /// CPU, the Paper Pro a 64-bit one.
+    pub fn from_machine(machine: &str) -> Option<&'static Self> {
+        match machine.trim() {
+            m if m.starts_with("armv7") => Some(&RM2),
+            "aarch64" | "arm64" => Some(&RMPP),
+            _ => None,
         }
+    }
 
-        let model = output.trim();
+    /// Pick the profile for a `/proc/device-tree/model` string.
+    pub fn from_model(model: &str) -> Result<&'static Self, Box<dyn std::error::Error + Send + Sync>> {
+        let model = trim_model(model);
         if model.is_empty() {
             return Err("Device model is empty".into());
         }
@@ -80,3 +132,126 @@ impl DeviceProfile {
         Err(format!("Unsupported device model: '{}'", model).into())
     }
 }
+
+/// The kernel NUL-terminates the device-tree string.
+fn trim_model(model: &str) -> &str {
+    model.trim().trim_end_matches('\0')
+}
+
+/// Pick the profile from `first_model` (the first of `MODEL_FILES`, empty
+/// if it couldn't be read) and `uname -m`.
+///
+/// Only a missing or empty model falls through: first to the other
+/// `MODEL_FILES`, read with `read_model`, then to a guess from the
+/// architecture (with a warning). A model that was read but isn't
+/// supported is an error, as is running out of sources; the latter lists
+/// every source that failed.
+fn resolve_profile(
+    first_model: &str,
+    machine: &str,
+    mut read_model: impl FnMut(&str) -> Result<String, String>,
+) -> Result<&'static DeviceProfile, String> {
+    let mut failures = Vec::new();
+    for (i, path) in MODEL_FILES.iter().enumerate() {
+        let model = if i == 0 { Ok(first_model.to_string()) } else { read_model(path) };
+        match model {
+            Ok(model) if !trim_model(&model).is_empty() => {
+                return DeviceProfile::from_model(&model).map_err(|e| format!("{}: {}", path, e));
+            }
+            Ok(_) => failures.push(format!("{}: missing or empty", path)),
+            Err(e) => {
+                log::debug!("Model from {}: {}", path, e);
+                failures.push(format!("{}: {}", path, e));
+            }
+        }
+    }
+
+    match DeviceProfile::from_machine(machine) {
+        Some(profile) => {
+            log::warn!("Could not read the device model, guessing {} from the {} CPU", profile.name, machine);
+            Ok(profile)
+        }
+        None => {
+            failures.push(format!("uname -m: unknown architecture '{}'", machine));
+            Err(format!("Could not detect the device model ({})", failures.join("; ")))
+        }
+    }
+}
+
+/// Profile when detection failed with `error`: the configured
+/// `device_model` if there is one, otherwise the reMarkable 2.
+fn fall_back(error: &str, configured: Option<&'static DeviceProfile>) -> &'static DeviceProfile {
+    match configured {
+        Some(profile) => {
+            log::info!("{}; using the configured device model {}", error, profile.name);
+            profile
+        }
+        None => {
+            log::warn!("{}; assuming a {} (set device_model if that's wrong)", error, RM2.name);
+            &RM2
+        }
+    }
+}
+
+/// Split `cat model; echo; uname -m` output into the model and the architecture.
+fn split_detection_output(output: &str) -> (&str, &str) {
+    let output = output.trim_end();
+    match output.rsplit_once('\n') {
+        Some((model, machine)) => (model, machine.trim()),
+        None => ("", output.trim()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_model() {
+        assert_eq!(DeviceProfile::from_model("reMarkable 2.0\0").unwrap().name, RM2.name);
+        assert_eq!(DeviceProfile::from_model("reMarkable Ferrari\n").unwrap().name, RMPP.name);
+        assert!(DeviceProfile::from_model("reMarkable 1.0").is_err());
+        assert!(DeviceProfile::from_model("\0").is_err());
+    }
+
+    #[test]
+    fn test_fall_back() {
+        let error = "Could not detect the device model (uname -m: unknown architecture 'x86_64')";
+        assert_eq!(fall_back(error, Some(&RMPP)).name, RMPP.name);
+        assert_eq!(fall_back(error, None).name, RM2.name);
+
+        assert_eq!(DeviceProfile::from_name("RMPP").unwrap().name, RMPP.name);
+        assert!(DeviceProfile::from_name("rm1").is_err());
+    }
+
+    #[test]
+    fn test_split_detection_output() {
+        assert_eq!(split_detection_output("reMarkable 2.0\0\narmv7l\n"), ("reMarkable 2.0\0", "armv7l"));
+        // Model file missing: only the separator and the architecture
+        assert_eq!(split_detection_output("\naarch64\n"), ("", "aarch64"));
+        assert_eq!(split_detection_output("aarch64\n"), ("", "aarch64"));
+    }
+
+    #[test]
+    fn test_resolve_profile() {
+        let unreadable = |_: &str| Err("exit status 1".to_string());
+        assert_eq!(resolve_profile("reMarkable 2.0\0", "armv7l", unreadable).unwrap().name, RM2.name);
+
+        // A missing model falls back to the other files, then to the CPU
+        let soc = |path: &str| Ok(if path == MODEL_FILES[2] { "reMarkable Ferrari".to_string() } else { String::new() });
+        assert_eq!(resolve_profile("", "armv7l", soc).unwrap().name, RMPP.name);
+        assert_eq!(resolve_profile("\0", "armv7l", unreadable).unwrap().name, RM2.name);
+        assert!(resolve_profile("", "x86_64", unreadable).is_err());
+
+        // A model that was read but isn't supported is not guessed from the CPU
+        let e = resolve_profile("reMarkable 1.0", "armv7l", |_| panic!("read another model file")).unwrap_err();
+        assert!(e.contains("Unsupported device model: 'reMarkable 1.0'"), "{}", e);
+    }
+
+    #[test]
+    fn test_from_machine() {
+        assert_eq!(DeviceProfile::from_machine("armv7l\n").unwrap().name, RM2.name);
+        assert_eq!(DeviceProfile::from_machine("aarch64\n").unwrap().name, RMPP.name);
+        assert!(DeviceProfile::from_machine("x86_64").is_none());
+    }
+}
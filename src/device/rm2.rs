@@ -21,4 +21,5 @@ pub const RM2: DeviceProfile = DeviceProfile {
     // Default device paths
     pen_device: "/dev/input/event1",
     touch_device: "/dev/input/event2",
+    power_device: Some("name:30370000.snvs:snvs-powerkey"),
 };
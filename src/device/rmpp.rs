@@ -25,4 +25,6 @@ pub const RMPP: DeviceProfile = DeviceProfile {
 
     pen_device: "/dev/input/event2",
     touch_device: "/dev/input/event3",
+    // Not known yet; set power_device to use pause_on_sleep
+    power_device: None,
 };
@@ -1,22 +1,148 @@
-use std::io::Read;
+use std::io::{Read, Write};
 
 use crate::config::Config;
 use crate::device::DeviceProfile;
-use crate::input::parse_input_event;
+use crate::input::{
+    parse_input_event, parse_timed_event, PenFrameAssembler, PenState, ABS_MT_POSITION_X, ABS_MT_POSITION_Y, ABS_X,
+    ABS_Y, EV_ABS,
+};
+use crate::orientation::Orientation;
 use crate::ssh;
 
+/// Runs raw X/Y values through the configured orientation transform.
+#[derive(Clone, Copy)]
+struct OrientationView {
+    orientation: Orientation,
+    pen: bool,
+    x_code: u16,
+    y_code: u16,
+    x_max: i32,
+    y_max: i32,
+    x: i32,
+    y: i32,
+}
+
+impl OrientationView {
+    fn pen(orientation: Orientation, device: &DeviceProfile) -> Self {
+        Self {
+            orientation,
+            pen: true,
+            x_code: ABS_X,
+            y_code: ABS_Y,
+            x_max: device.pen_x_max,
+            y_max: device.pen_y_max,
+            x: 0,
+            y: 0,
+        }
+    }
+
+    fn touch(orientation: Orientation, device: &DeviceProfile) -> Self {
+        Self {
+            orientation,
+            pen: false,
+            x_code: ABS_MT_POSITION_X,
+            y_code: ABS_MT_POSITION_Y,
+            x_max: device.touch_x_max,
+            y_max: device.touch_y_max,
+            x: 0,
+            y: 0,
+        }
+    }
+
+    fn transform(&self, x: i32, y: i32) -> (i32, i32) {
+        if self.pen {
+            self.orientation.transform_pen(x, y, self.x_max, self.y_max)
+        } else {
+            self.orientation.transform_touch(
+                x.clamp(0, self.x_max),
+                y.clamp(0, self.y_max),
+                self.x_max,
+                self.y_max,
+            )
+        }
+    }
+
+    /// Record an X/Y event and return the transformed position,
+    /// using the last seen value for the other axis.
+    fn observe(&mut self, ty: u16, code: u16, value: i32) -> Option<(i32, i32)> {
+        if ty != EV_ABS {
+            return None;
+        }
+        if code == self.x_code {
+            self.x = value;
+        } else if code == self.y_code {
+            self.y = value;
+        } else {
+            return None;
+        }
+        Some(self.transform(self.x, self.y))
+    }
+}
+
 pub fn run_touch(
     config: &Config,
     device: &DeviceProfile,
+    apply_orientation: bool,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    run_dump(config, device.input_event_size, &config.touch_device, "touch")
+    let view = apply_orientation.then(|| OrientationView::touch(config.touch_orientation, device));
+    run_dump(config, device.input_event_size, &config.touch_device, "touch", view)
 }
 
 pub fn run_pen(
     config: &Config,
     device: &DeviceProfile,
+    apply_orientation: bool,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let view = apply_orientation.then(|| OrientationView::pen(config.pen_orientation, device));
+    run_dump(config, device.input_event_size, &config.pen_device, "pen", view)
+}
+
+/// Show the decoded pen state on a single line, refreshed every SYN_REPORT.
+pub fn run_pen_frames(
+    config: &Config,
+    device: &DeviceProfile,
+    apply_orientation: bool,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    run_dump(config, device.input_event_size, &config.pen_device, "pen")
+    let (cleanup, mut channel) = ssh::open_input_stream(&config.pen_device, config, false)?;
+
+    eprintln!("Decoding pen frames from {} (Ctrl+C to stop)\n", cleanup.device_path());
+
+    let mut buf = vec![0u8; device.input_event_size];
+    let mut assembler = PenFrameAssembler::new();
+    let mut state = PenState::new(config.pen_contact_source);
+    let view = apply_orientation.then(|| OrientationView::pen(config.pen_orientation, device));
+    let mut stdout = std::io::stdout();
+
+    loop {
+        channel.read_exact(&mut buf)?;
+
+        let Some(ev) = parse_input_event(&buf) else {
+            continue;
+        };
+        let Some(frame) = assembler.push(ev) else {
+            continue;
+        };
+
+        state.apply(&frame);
+        write!(
+            stdout,
+            "\rx={:6} y={:6} pressure={:5} distance={:5} tilt=({:6},{:6}) {}",
+            state.x,
+            state.y,
+            state.pressure,
+            state.distance,
+            state.tilt_x,
+            state.tilt_y,
+            if state.touching { "TOUCH" } else { "hover" }
+        )?;
+        if let Some(view) = &view {
+            let (out_x, out_y) = view.transform(state.x, state.y);
+            let (out_tx, out_ty) = view.orientation.transform_tilt(state.tilt_x, state.tilt_y);
+            write!(stdout, "  {} -> ({:6},{:6}) tilt=({:6},{:6})", view.orientation, out_x, out_y, out_tx, out_ty)?;
+        }
+        write!(stdout, "\x1b[K")?;
+        stdout.flush()?;
+    }
 }
 
 fn run_dump(
@@ -24,10 +150,11 @@ fn run_dump(
     input_event_size: usize,
     device: &str,
     name: &str,
+    mut view: Option<OrientationView>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let (_cleanup, mut channel) = ssh::open_input_stream(device, config, false)?;
+    let (cleanup, mut channel) = ssh::open_input_stream(device, config, false)?;
 
-    eprintln!("Dumping {} events from {} (Ctrl+C to stop)\n", name, device);
+    eprintln!("Dumping {} events from {} (Ctrl+C to stop)\n", name, cleanup.device_path());
 
     let mut buf = vec![0u8; input_event_size];
     let mut count: u64 = 0;
@@ -35,13 +162,21 @@ fn run_dump(
     loop {
         channel.read_exact(&mut buf)?;
 
-        let Some(ev) = parse_input_event(&buf) else {
+        let Some(timed) = parse_timed_event(&buf) else {
             continue;
         };
 
         count += 1;
-        let name = format_event_code(ev.event_type().raw(), ev.raw_code());
-        println!("{:6}  {}  value={}", count, name, ev.raw_value());
+        let ev = timed.event;
+        let (ty, code, value) = (ev.event_type().raw(), ev.raw_code(), ev.raw_value());
+        let name = format_event_code(ty, code);
+        let time = format!("{}.{:06}", timed.time.as_secs(), timed.time.subsec_micros());
+        match view.as_mut().and_then(|v| v.observe(ty, code, value)) {
+            Some((out_x, out_y)) => {
+                println!("{:6}  {}  {}  value={}  -> ({}, {})", count, time, name, value, out_x, out_y)
+            }
+            None => println!("{:6}  {}  {}  value={}", count, time, name, value),
+        }
     }
 }
 
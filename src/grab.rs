@@ -14,17 +14,57 @@ use std::io::{Read, Write};
 use sha2::{Digest, Sha256};
 use ssh2::Session;
 
+use crate::ssh;
+
 const GRAB_ARMV7: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/evgrab-armv7"));
 const GRAB_AARCH64: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/evgrab-aarch64"));
 
-const REMOTE_PATH: &str = "/tmp/rm-pad-grab";
+/// Default tablet directory for the helper, its log and the watchdog file.
+pub const DEFAULT_REMOTE_DIR: &str = "/tmp";
+
+/// Helper exit status when EVIOCGRAB keeps failing (keep in sync with evgrab.c).
+pub const EXIT_GRAB_FAILED: i32 = 3;
 
-#[derive(Debug, Clone, Copy)]
+/// Status of the grab command when the shell can't find or run the helper,
+/// e.g. after a reboot wiped `remote_dir` between check and start.
+pub const EXIT_HELPER_MISSING: [i32; 2] = [126, 127];
+
+/// Files rm-pad keeps on the tablet, all inside `remote_dir`.
+pub struct RemotePaths {
+    pub helper: String,
+    /// Where the helper's stderr goes.
+    pub log: String,
+    pub watchdog: String,
+}
+
+impl RemotePaths {
+    pub fn new(dir: &str) -> Self {
+        let dir = dir.trim_end_matches('/');
+        Self {
+            helper: format!("{}/rm-pad-grab", dir),
+            log: format!("{}/rm-pad-grab.log", dir),
+            watchdog: format!("{}/rm-pad-watchdog", dir),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Arch {
     Armv7,
     Aarch64,
 }
 
+impl Arch {
+    /// Parse `uname -m` output.
+    pub fn from_uname(machine: &str) -> Option<Self> {
+        match machine.trim() {
+            "armv7l" => Some(Arch::Armv7),
+            "aarch64" => Some(Arch::Aarch64),
+            _ => None,
+        }
+    }
+}
+
 impl fmt::Display for Arch {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -37,7 +77,7 @@ impl fmt::Display for Arch {
 /// Detect the tablet's CPU architecture by running `uname -m` over SSH.
 pub fn detect_arch(session: &Session) -> Result<Arch, Box<dyn std::error::Error + Send + Sync>> {
     let mut channel = session.channel_session()?;
-    channel.exec("uname -m")?;
+    ssh::exec(&mut channel, "uname -m")?;
 
     let mut output = String::new();
     channel.read_to_string(&mut output)?;
@@ -47,11 +87,7 @@ pub fn detect_arch(session: &Session) -> Result<Arch, Box<dyn std::error::Error
     channel.close()?;
     channel.wait_close()?;
 
-    match output.trim() {
-        "armv7l" => Ok(Arch::Armv7),
-        "aarch64" => Ok(Arch::Aarch64),
-        other => Err(format!("Unsupported tablet architecture: {}", other).into()),
-    }
+    Arch::from_uname(&output).ok_or_else(|| format!("Unsupported tablet architecture: {}", output.trim()).into())
 }
 
 /// Compute SHA256 hash of the embedded binary for the given architecture.
@@ -65,30 +101,60 @@ fn compute_binary_hash(arch: Arch) -> String {
     format!("{:x}", hasher.finalize())
 }
 
+/// Replacing the grab helper on the tablet failed.
+#[derive(Debug)]
+pub enum UploadFailed {
+    /// Removing the outdated helper exited with this status.
+    Remove(i32),
+    /// The upload command exited with this status.
+    Upload(i32),
+    /// The uploaded file's hash is wrong.
+    HashMismatch,
+}
+
+impl fmt::Display for UploadFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UploadFailed::Remove(status) => write!(f, "Failed to remove remote binary (exit status {})", status),
+            UploadFailed::Upload(status) => write!(f, "Failed to upload grab helper (exit status {})", status),
+            UploadFailed::HashMismatch => write!(f, "Failed to upload grab helper (the uploaded file's hash doesn't match)"),
+        }
+    }
+}
+
+impl std::error::Error for UploadFailed {}
+
+/// A `sha256sum` digest: 64 lowercase hex digits.
+fn is_sha256_hex(s: &str) -> bool {
+    s.len() == 64 && s.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
 /// Check if the remote binary exists and matches our embedded binary hash.
 /// Returns Ok(true) if hash matches, Ok(false) if file doesn't exist or hash doesn't match.
 fn check_remote_binary_hash(
     session: &Session,
     arch: Arch,
+    paths: &RemotePaths,
 ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
     let expected_hash = compute_binary_hash(arch);
     
     let mut channel = session.channel_session()?;
-    channel.exec(&format!("sha256sum {} 2>/dev/null | cut -d' ' -f1", REMOTE_PATH))?;
+    ssh::exec(&mut channel, &format!("sha256sum {} 2>/dev/null | cut -d' ' -f1", paths.helper))?;
 
     let mut output = String::new();
     channel.read_to_string(&mut output)?;
     channel.close()?;
     channel.wait_close()?;
 
-    let status = channel.exit_status()?;
-    if status != 0 {
+    // libssh2 reports 0 when the server sent no exit status, and the status
+    // is cut's anyway, so a missing file only shows as output without a digest
+    let remote_hash = output.trim();
+    if channel.exit_status()? != 0 || !is_sha256_hex(remote_hash) {
         // File doesn't exist or command failed
         log::debug!("Remote binary not found or sha256sum failed");
         return Ok(false);
     }
 
-    let remote_hash = output.trim();
     if remote_hash == expected_hash {
         log::debug!("Remote binary hash matches: {}", &expected_hash[..16]);
         Ok(true)
@@ -105,16 +171,17 @@ fn check_remote_binary_hash(
 /// Remove the remote binary if it exists.
 fn remove_remote_binary(
     session: &Session,
+    paths: &RemotePaths,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let mut channel = session.channel_session()?;
-    channel.exec(&format!("rm -f {}", REMOTE_PATH))?;
+    ssh::exec(&mut channel, &format!("rm -f {}", paths.helper))?;
 
     channel.close()?;
     channel.wait_close()?;
 
     let status = channel.exit_status()?;
     if status != 0 {
-        return Err(format!("Failed to remove remote binary (exit status {})", status).into());
+        return Err(UploadFailed::Remove(status).into());
     }
 
     log::debug!("Removed remote binary");
@@ -129,6 +196,7 @@ fn remove_remote_binary(
 pub fn upload_helper(
     session: &Session,
     arch: Arch,
+    paths: &RemotePaths,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let binary = match arch {
         Arch::Armv7 => GRAB_ARMV7,
@@ -139,15 +207,15 @@ pub fn upload_helper(
         "Uploading grab helper ({}, {} bytes) to {}",
         arch,
         binary.len(),
-        REMOTE_PATH
+        paths.helper
     );
 
     let mut channel = session.channel_session()?;
     // Write to a PID-unique temp file and atomically rename into place.
     // This avoids corruption when pen and touch threads upload concurrently.
-    channel.exec(&format!(
+    ssh::exec(&mut channel, &format!(
         "cat > {path}.$$ && chmod +x {path}.$$ && mv -f {path}.$$ {path}",
-        path = REMOTE_PATH
+        path = paths.helper
     ))?;
 
     channel.write_all(binary)?;
@@ -158,7 +226,11 @@ pub fn upload_helper(
 
     let status = channel.exit_status()?;
     if status != 0 {
-        return Err(format!("Failed to upload grab helper (exit status {})", status).into());
+        return Err(UploadFailed::Upload(status).into());
+    }
+    // A server that sends no exit status reads as 0 too, so check the result
+    if !check_remote_binary_hash(session, arch, paths)? {
+        return Err(UploadFailed::HashMismatch.into());
     }
 
     log::info!("Grab helper uploaded successfully");
@@ -170,28 +242,79 @@ pub fn upload_helper(
 pub fn ensure_binary_valid(
     session: &Session,
     arch: Arch,
+    paths: &RemotePaths,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    match check_remote_binary_hash(session, arch)? {
+    match check_remote_binary_hash(session, arch, paths)? {
         true => {
             log::debug!("Using existing remote binary (hash verified)");
             Ok(())
         }
         false => {
             log::info!("Remote binary missing or hash mismatch, removing and re-uploading");
-            remove_remote_binary(session)?;
-            upload_helper(session, arch)
+            check_remote_dir(session, paths)?;
+            remove_remote_binary(session, paths)?;
+            upload_helper(session, arch, paths)
         }
     }
 }
 
+/// Check that the helper's directory exists, is writable and allows executing.
+///
+/// Writes and runs a tiny shell script there, so a `noexec` mount shows up
+/// as a clear error instead of a helper that silently fails to start.
+fn check_remote_dir(
+    session: &Session,
+    paths: &RemotePaths,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let probe = format!("{}.probe", paths.helper);
+    let mut channel = session.channel_session()?;
+    ssh::exec(&mut channel, &format!(
+        "printf '#!/bin/sh\\necho ok\\n' > {probe} && chmod +x {probe} && {probe}; rm -f {probe}",
+        probe = probe
+    ))?;
+
+    let mut output = String::new();
+    channel.read_to_string(&mut output)?;
+    channel.close()?;
+    channel.wait_close()?;
+
+    if output.trim() != "ok" {
+        let dir = paths.helper.rsplit_once('/').map_or("", |(dir, _)| dir);
+        return Err(format!(
+            "Cannot run programs from {} on the tablet (not writable or mounted noexec); set remote_dir to another directory",
+            dir
+        )
+        .into());
+    }
+    Ok(())
+}
+
 /// Build the remote command that grabs a device and streams events.
 ///
 /// Stderr is redirected to a log file on the tablet for diagnostics.
 /// Uses `exec` to replace the shell with the grab helper so that signal
 /// delivery (on SSH disconnect) goes directly to the right process.
-pub fn grab_command(device_path: &str) -> String {
+pub fn grab_command(device_path: &str, paths: &RemotePaths) -> String {
     format!(
-        "exec {} {} 2>>{}.log",
-        REMOTE_PATH, device_path, REMOTE_PATH
+        "exec {} {} {} 2>>{}",
+        paths.helper, device_path, paths.watchdog, paths.log
     )
 }
+
+/// Build the remote command that prints a device's ABS axis ranges.
+pub fn absinfo_command(device_path: &str, paths: &RemotePaths) -> String {
+    format!("{} --absinfo {} 2>>{}", paths.helper, device_path, paths.log)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_sha256_hex() {
+        assert!(is_sha256_hex(&compute_binary_hash(Arch::Armv7)));
+        assert!(!is_sha256_hex(""));
+        assert!(!is_sha256_hex(&"A".repeat(64)));
+        assert!(!is_sha256_hex("sha256sum: /home/root/.rm-pad/evgrab: No such file or directory"));
+    }
+}
@@ -0,0 +1,153 @@
+//! Liveness and readiness probes over HTTP (`health_addr`).
+//!
+//! `GET /livez` answers 200 as long as the process runs. `GET /healthz`
+//! answers 200 once every enabled stream (pen, touch) has forwarded events
+//! since it last connected, and with `health_max_idle_secs` also within that
+//! many seconds; otherwise 503. The body lists each stream either way, with
+//! how many frames it forwarded and dropped. One request per connection,
+//! handled on a single background thread.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use crate::status::{self, FrameCounts, StreamStatus};
+
+/// Bind `addr` and serve the probes in the background.
+pub fn spawn(addr: &str, max_idle: Option<Duration>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let listener = TcpListener::bind(addr).map_err(|e| format!("Failed to listen on {}: {}", addr, e))?;
+    log::info!("Health checks on http://{}/healthz and /livez", listener.local_addr()?);
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = respond(stream, max_idle) {
+                log::debug!("Health check request failed: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+fn respond(stream: TcpStream, max_idle: Option<Duration>) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or("");
+    let (status, body) = match path {
+        "/livez" => ("200 OK", "alive\n".to_string()),
+        "/healthz" => {
+            let streams: Vec<StreamReport> = [("pen", &status::PEN), ("touch", &status::TOUCH)]
+                .into_iter()
+                .map(|(name, s)| StreamReport::of(name, s))
+                .collect();
+            match readiness(&streams, max_idle) {
+                (true, body) => ("200 OK", body),
+                (false, body) => ("503 Service Unavailable", body),
+            }
+        }
+        _ => ("404 Not Found", "not found\n".to_string()),
+    };
+
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+struct StreamReport {
+    name: &'static str,
+    active: bool,
+    streaming: bool,
+    since_last_frame: Option<Duration>,
+    counts: FrameCounts,
+}
+
+impl StreamReport {
+    fn of(name: &'static str, status: &StreamStatus) -> Self {
+        Self {
+            name,
+            active: status.is_active(),
+            streaming: status.is_streaming(),
+            since_last_frame: status.since_last_frame(),
+            counts: status.frame_counts(),
+        }
+    }
+}
+
+/// Whether every active stream is ready, and a line per stream saying why.
+fn readiness(streams: &[StreamReport], max_idle: Option<Duration>) -> (bool, String) {
+    let mut body = String::new();
+    let mut ready = streams.iter().any(|s| s.active);
+
+    for s in streams.iter().filter(|s| s.active) {
+        let recent = match (s.since_last_frame, max_idle) {
+            (Some(since), Some(max)) => since <= max,
+            (since, _) => since.is_some(),
+        };
+        let ok = s.streaming && recent;
+        ready &= ok;
+
+        let last = s
+            .since_last_frame
+            .map_or("no events yet".to_string(), |d| format!("last event {}s ago", d.as_secs()));
+        let state = if s.streaming { "streaming" } else { "not streaming" };
+        body.push_str(&format!("{} {}: {}, {}; {}\n", s.name, if ok { "ok" } else { "FAIL" }, state, last, s.counts));
+    }
+    if body.is_empty() {
+        body.push_str("no streams started\n");
+    }
+    (ready, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(name: &'static str, active: bool, streaming: bool, since_secs: Option<u64>) -> StreamReport {
+        StreamReport {
+            name,
+            active,
+            streaming,
+            since_last_frame: since_secs.map(Duration::from_secs),
+            counts: FrameCounts { forwarded: 0, skipped: 0, throttled: 0 },
+        }
+    }
+
+    #[test]
+    fn test_readiness() {
+        let max_idle = Some(Duration::from_secs(60));
+
+        let (ready, body) = readiness(&[report("pen", true, true, Some(3)), report("touch", false, false, None)], None);
+        assert!(ready, "an unused stream doesn't count");
+        assert_eq!(body, "pen ok: streaming, last event 3s ago; 0 forwarded, 0 skipped as repeats, 0 throttled\n");
+
+        // Reconnecting after having streamed before
+        assert!(!readiness(&[report("pen", true, false, Some(3))], None).0);
+        assert!(!readiness(&[report("pen", true, true, Some(3)), report("touch", true, false, None)], None).0);
+
+        assert!(readiness(&[report("pen", true, true, Some(59))], max_idle).0);
+        assert!(!readiness(&[report("pen", true, true, Some(61))], max_idle).0);
+
+        assert!(!readiness(&[report("pen", false, false, None), report("touch", false, false, None)], None).0);
+    }
+
+    #[test]
+    fn test_healthz_reports_frame_counts() {
+        let status = StreamStatus::new();
+        status.frame(1, false);
+        status.forwarded();
+        status.forwarded();
+        status.skipped();
+        status.throttled();
+
+        let (ready, body) = readiness(&[StreamReport::of("touch", &status)], None);
+        assert!(ready);
+        assert_eq!(body, "touch ok: streaming, last event 0s ago; 2 forwarded, 1 skipped as repeats, 1 throttled\n");
+    }
+}
@@ -0,0 +1,48 @@
+//! `on_connect` / `on_disconnect` commands around the forwarding lifecycle.
+//!
+//! Each hook is a shell command run with `sh -c`, with the tablet's address
+//! and model in `RMPAD_HOST` and `RMPAD_DEVICE`. rm-pad waits for it to
+//! finish so e.g. a compositor remap is in place before input arrives. A
+//! hook that fails or can't be started is logged and otherwise ignored.
+
+use std::process::{Command, ExitStatus};
+
+use crate::device::DeviceProfile;
+
+/// Run the hook `name` ("on_connect" or "on_disconnect"), if configured.
+pub fn run(name: &str, command: Option<&str>, host: &str, device: &DeviceProfile) {
+    let Some(command) = command else {
+        return;
+    };
+
+    log::info!("Running {}: {}", name, command);
+    match execute(command, host, device) {
+        Ok(status) if status.success() => {}
+        Ok(status) => log::warn!("{} hook failed ({})", name, status),
+        Err(e) => log::warn!("Failed to run {} hook: {}", name, e),
+    }
+}
+
+fn execute(command: &str, host: &str, device: &DeviceProfile) -> std::io::Result<ExitStatus> {
+    Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("RMPAD_HOST", host)
+        .env("RMPAD_DEVICE", device.name)
+        .status()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::RM2;
+
+    #[test]
+    fn test_hook_environment() {
+        let status = execute(r#"test "$RMPAD_HOST/$RMPAD_DEVICE" = "10.11.99.1/reMarkable 2""#, "10.11.99.1", &RM2);
+        assert!(status.unwrap().success());
+
+        let status = execute("exit 3", "10.11.99.1", &RM2).unwrap();
+        assert_eq!(status.code(), Some(3));
+    }
+}
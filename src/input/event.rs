@@ -1,3 +1,7 @@
+use std::fmt;
+use std::io::{self, Read};
+use std::time::{Duration, Instant};
+
 use evdevil::event::{EventType, InputEvent};
 
 pub const INPUT_EVENT_SIZE_32: usize = 16;
@@ -5,17 +9,30 @@ pub const INPUT_EVENT_SIZE_64: usize = 24;
 
 pub const EV_SYN: u16 = 0x00;
 pub const EV_KEY: u16 = 0x01;
+pub const EV_REL: u16 = 0x02;
 pub const EV_ABS: u16 = 0x03;
 pub const SYN_REPORT: u16 = 0;
 
+pub const ABS_X: u16 = 0x00;
+pub const ABS_Y: u16 = 0x01;
 pub const ABS_MT_SLOT: u16 = 0x2f;
+pub const ABS_MT_TOUCH_MAJOR: u16 = 0x30;
 pub const ABS_MT_POSITION_X: u16 = 0x35;
 pub const ABS_MT_POSITION_Y: u16 = 0x36;
 pub const ABS_MT_TRACKING_ID: u16 = 0x39;
+pub const ABS_MT_DISTANCE: u16 = 0x3b;
 pub const ABS_PRESSURE: u16 = 0x18;
 
+/// An input event together with its kernel timestamp.
+#[derive(Debug, Clone, Copy)]
+pub struct TimedEvent {
+    pub event: InputEvent,
+    /// Time since the epoch on the tablet's clock.
+    pub time: Duration,
+}
+
 /// Parse a Linux input_event from raw bytes (32-bit or 64-bit format).
-pub fn parse_input_event(buf: &[u8]) -> Option<InputEvent> {
+pub fn parse_timed_event(buf: &[u8]) -> Option<TimedEvent> {
     match buf.len() {
         INPUT_EVENT_SIZE_32 => parse_input_event_32(buf),
         INPUT_EVENT_SIZE_64 => parse_input_event_64(buf),
@@ -25,22 +42,240 @@ pub fn parse_input_event(buf: &[u8]) -> Option<InputEvent> {
     }
 }
 
-fn parse_input_event_32(buf: &[u8]) -> Option<InputEvent> {
+/// Like `parse_timed_event`, for callers that don't need the timestamp.
+pub fn parse_input_event(buf: &[u8]) -> Option<InputEvent> {
+    parse_timed_event(buf).map(|timed| timed.event)
+}
+
+/// A 16-byte (reMarkable 2) `struct input_event` with a zero timestamp, for test streams.
+#[cfg(test)]
+pub(crate) fn raw_event(ty: u16, code: u16, value: i32) -> Vec<u8> {
+    let mut buf = vec![0u8; 8];
+    buf.extend_from_slice(&ty.to_le_bytes());
+    buf.extend_from_slice(&code.to_le_bytes());
+    buf.extend_from_slice(&value.to_le_bytes());
+    buf
+}
+
+fn parse_input_event_32(buf: &[u8]) -> Option<TimedEvent> {
+    let secs = i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+    let usecs = i32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]);
     let ty = u16::from_le_bytes([buf[8], buf[9]]);
     let code = u16::from_le_bytes([buf[10], buf[11]]);
     let value = i32::from_le_bytes([buf[12], buf[13], buf[14], buf[15]]);
 
-    Some(InputEvent::new(EventType::from_raw(ty), code, value))
+    Some(TimedEvent {
+        event: InputEvent::new(EventType::from_raw(ty), code, value),
+        time: timeval(secs as i64, usecs as i64),
+    })
 }
 
-fn parse_input_event_64(buf: &[u8]) -> Option<InputEvent> {
+fn parse_input_event_64(buf: &[u8]) -> Option<TimedEvent> {
+    let secs = i64::from_le_bytes(buf[0..8].try_into().ok()?);
+    let usecs = i64::from_le_bytes(buf[8..16].try_into().ok()?);
     let ty = u16::from_le_bytes([buf[16], buf[17]]);
     let code = u16::from_le_bytes([buf[18], buf[19]]);
     let value = i32::from_le_bytes([buf[20], buf[21], buf[22], buf[23]]);
 
-    Some(InputEvent::new(EventType::from_raw(ty), code, value))
+    Some(TimedEvent {
+        event: InputEvent::new(EventType::from_raw(ty), code, value),
+        time: timeval(secs, usecs),
+    })
+}
+
+/// A negative or overflowing timeval (never sent by a sane kernel) reads as 0.
+fn timeval(secs: i64, usecs: i64) -> Duration {
+    let micros = secs.saturating_mul(1_000_000).saturating_add(usecs);
+    Duration::from_micros(micros.max(0) as u64)
+}
+
+/// Returned by the input loops when no event arrived within `idle_disconnect_secs`.
+#[derive(Debug)]
+pub struct IdleDisconnect;
+
+impl fmt::Display for IdleDisconnect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "No input for the configured idle period")
+    }
+}
+
+impl std::error::Error for IdleDisconnect {}
+
+/// Read one raw event, turning an SSH read timeout into `IdleDisconnect`.
+pub fn read_event(
+    reader: &mut impl Read,
+    buf: &mut [u8],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    match try_read_event(reader, buf)? {
+        true => Ok(()),
+        false => Err(IdleDisconnect.into()),
+    }
+}
+
+/// How many read timeouts `try_read_event` waits through for the rest of a
+/// started event before giving up on the stream.
+const PARTIAL_EVENT_TIMEOUTS: u32 = 10;
+
+/// Read one raw event, or return `false` on a read timeout between events.
+///
+/// A timeout only counts between events. Once part of an event has
+/// arrived, the rest is waited for across up to `PARTIAL_EVENT_TIMEOUTS`
+/// timeouts, since `read_exact` would drop the partial bytes and shift the
+/// framing of every later event. A stream stalled longer than that fails
+/// with `TimedOut`, so the stream is reconnected instead of hanging.
+pub fn try_read_event(
+    reader: &mut impl Read,
+    buf: &mut [u8],
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let mut filled = 0;
+    let mut timeouts = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "input stream ended").into()),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut && filled == 0 => return Ok(false),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                timeouts += 1;
+                if timeouts >= PARTIAL_EVENT_TIMEOUTS {
+                    let msg = format!("input stream stalled {} bytes into an event", filled);
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, msg).into());
+                }
+                log::debug!("Read timed out {} bytes into an event, waiting for the rest", filled);
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) if e.get_ref().is_some_and(|inner| inner.is::<IdleDisconnect>()) => return Err(IdleDisconnect.into()),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(true)
+}
+
+/// Whether moving from `from` to `to` stays below `min_delta` device units
+/// (`min_position_delta`). Always false when the option is off.
+pub fn below_min_delta(from: (i32, i32), to: (i32, i32), min_delta: i32) -> bool {
+    let (dx, dy) = ((to.0 - from.0) as i64, (to.1 - from.1) as i64);
+    dx * dx + dy * dy < (min_delta as i64) * (min_delta as i64)
+}
+
+/// Drops output for `warmup_discard_ms` after the first event of a stream,
+/// so stale values queued before the grab don't make the cursor jump. The
+/// loops keep decoding during the window; only emitting is skipped.
+pub struct Warmup {
+    window: Duration,
+    until: Option<Instant>,
+}
+
+impl Warmup {
+    pub fn new(window_ms: u64) -> Self {
+        Self {
+            window: Duration::from_millis(window_ms),
+            until: None,
+        }
+    }
+
+    /// Whether a frame completed at `now` falls into the window.
+    pub fn discard(&mut self, now: Instant) -> bool {
+        if self.window.is_zero() {
+            return false;
+        }
+        now < *self.until.get_or_insert(now + self.window)
+    }
 }
 
 pub fn key_event(code: u16, value: i32) -> InputEvent {
     InputEvent::new(EventType::from_raw(EV_KEY), code, value)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hands out one chunk per read, with `None` as a read timeout.
+    struct Chunks(Vec<Option<Vec<u8>>>);
+
+    impl Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+            match self.0.remove(0) {
+                None => Err(io::ErrorKind::TimedOut.into()),
+                Some(mut chunk) => {
+                    let n = chunk.len().min(buf.len());
+                    buf[..n].copy_from_slice(&chunk[..n]);
+                    if n < chunk.len() {
+                        self.0.insert(0, Some(chunk.split_off(n)));
+                    }
+                    Ok(n)
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_event_keeps_framing_across_timeouts() {
+        let events: Vec<u8> = (0..32).collect();
+        let mut reader = Chunks(vec![Some(events[..10].to_vec()), None, None, Some(events[10..].to_vec()), None]);
+        let mut buf = [0u8; INPUT_EVENT_SIZE_32];
+
+        read_event(&mut reader, &mut buf).unwrap();
+        assert_eq!(buf[..], events[..16]);
+        read_event(&mut reader, &mut buf).unwrap();
+        assert_eq!(buf[..], events[16..]);
+
+        // A timeout between events is the idle timeout, the end of the stream is EOF
+        let err = read_event(&mut reader, &mut buf).unwrap_err();
+        assert!(err.downcast_ref::<IdleDisconnect>().is_some());
+        let err = read_event(&mut reader, &mut buf).unwrap_err();
+        assert_eq!(err.downcast_ref::<io::Error>().unwrap().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_read_event_gives_up_on_a_stalled_event() {
+        let mut chunks = vec![Some(vec![0u8; 10])];
+        chunks.extend(std::iter::repeat_n(None, PARTIAL_EVENT_TIMEOUTS as usize + 5));
+        let mut reader = Chunks(chunks);
+        let mut buf = [0u8; INPUT_EVENT_SIZE_32];
+
+        let err = try_read_event(&mut reader, &mut buf).unwrap_err();
+        assert_eq!(err.downcast_ref::<io::Error>().unwrap().kind(), io::ErrorKind::TimedOut);
+        assert_eq!(reader.0.len(), 5);
+    }
+
+    #[test]
+    fn test_warmup_window() {
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+
+        let mut warmup = Warmup::new(50);
+        assert!(warmup.discard(start));
+        assert!(warmup.discard(start + ms(49)));
+        assert!(!warmup.discard(start + ms(50)));
+        assert!(!warmup.discard(start + ms(500)));
+
+        let mut off = Warmup::new(0);
+        assert!(!off.discard(start));
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        let mut buf32 = Vec::new();
+        buf32.extend_from_slice(&12i32.to_le_bytes());
+        buf32.extend_from_slice(&345_678i32.to_le_bytes());
+        buf32.extend_from_slice(&EV_ABS.to_le_bytes());
+        buf32.extend_from_slice(&ABS_PRESSURE.to_le_bytes());
+        buf32.extend_from_slice(&1200i32.to_le_bytes());
+
+        let mut buf64 = Vec::new();
+        buf64.extend_from_slice(&12i64.to_le_bytes());
+        buf64.extend_from_slice(&345_678i64.to_le_bytes());
+        buf64.extend_from_slice(&buf32[8..]);
+
+        for buf in [buf32, buf64] {
+            let timed = parse_timed_event(&buf).unwrap();
+            assert_eq!(timed.time, Duration::from_micros(12_345_678));
+            assert_eq!(timed.event.raw_code(), ABS_PRESSURE);
+            assert_eq!(timed.event.raw_value(), 1200);
+        }
+    }
+}
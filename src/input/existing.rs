@@ -0,0 +1,102 @@
+//! Forward into a pre-created input device instead of a new uinput device
+//! (`use_existing_device`).
+//!
+//! Events written to an evdev node are injected as if the device produced
+//! them, but the kernel drops every event the device doesn't declare. So the
+//! target must declare the same axes and keys rm-pad would create, ideally
+//! with the same ranges; missing capabilities are an error, differing ranges
+//! only a warning.
+
+use std::io;
+
+use evdevil::event::{EventType, InputEvent, Key};
+use evdevil::uinput::AbsSetup;
+use evdevil::Evdev;
+
+use super::event::{EV_SYN, SYN_REPORT};
+use super::sink::EventSink;
+
+/// Open the target device: `path` if given, otherwise the local device called `name`.
+pub fn open(
+    path: Option<&str>,
+    name: &str,
+    axes: &[AbsSetup],
+    keys: &[Key],
+) -> Result<Evdev, Box<dyn std::error::Error + Send + Sync>> {
+    let (path, device) = match path {
+        Some(path) => {
+            let device = Evdev::open(path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+            (path.to_string(), device)
+        }
+        None => find_by_name(name)?,
+    };
+
+    check_capabilities(&device, &path, axes, keys)?;
+    log::info!("Forwarding to existing device {} ({})", path, device.name().unwrap_or_default());
+    Ok(device)
+}
+
+fn find_by_name(name: &str) -> Result<(String, Evdev), Box<dyn std::error::Error + Send + Sync>> {
+    for entry in evdevil::enumerate()? {
+        let Ok((path, device)) = entry else {
+            continue;
+        };
+        if device.name().is_ok_and(|n| n == name) {
+            return Ok((path.display().to_string(), device));
+        }
+    }
+    Err(format!("No existing input device named \"{}\" (create it first or set the path)", name).into())
+}
+
+fn check_capabilities(
+    device: &Evdev,
+    path: &str,
+    axes: &[AbsSetup],
+    keys: &[Key],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let supported_axes = device.supported_abs_axes()?;
+    let supported_keys = device.supported_keys()?;
+    let mut missing = Vec::new();
+
+    for setup in axes {
+        let abs = setup.abs();
+        if !supported_axes.contains(abs) {
+            missing.push(format!("{:?}", abs));
+            continue;
+        }
+        let want = setup.abs_info();
+        let have = device.abs_info(abs)?;
+        if (have.minimum(), have.maximum()) != (want.minimum(), want.maximum()) {
+            log::warn!(
+                "{}: {:?} range is {}..{}, rm-pad sends {}..{}",
+                path,
+                abs,
+                have.minimum(),
+                have.maximum(),
+                want.minimum(),
+                want.maximum()
+            );
+        }
+    }
+    missing.extend(keys.iter().filter(|&&key| !supported_keys.contains(key)).map(|key| format!("{:?}", key)));
+
+    if !missing.is_empty() {
+        return Err(format!("{} does not declare {}", path, missing.join(", ")).into());
+    }
+    Ok(())
+}
+
+/// Unlike uinput, evdev writes don't end the frame for us.
+impl EventSink for Evdev {
+    fn write_frame(&mut self, events: &[InputEvent]) -> io::Result<()> {
+        let is_report = |e: &InputEvent| e.event_type().raw() == EV_SYN && e.raw_code() == SYN_REPORT;
+        if events.last().is_some_and(is_report) {
+            return self.write(events);
+        }
+
+        let mut frame = Vec::with_capacity(events.len() + 1);
+        frame.extend_from_slice(events);
+        frame.push(InputEvent::new(EventType::from_raw(EV_SYN), SYN_REPORT, 0));
+        self.write(&frame)
+    }
+}
@@ -0,0 +1,125 @@
+//! Touch only while a key on this computer is held (`touch_gate_key`).
+//!
+//! The key state is read from every local input device that has the key,
+//! with `EVIOCGKEY`, so nothing is grabbed and no events are consumed. A
+//! background thread polls it and refreshes the device list every few
+//! seconds to pick up keyboards plugged in later. Reading `/dev/input` needs the `input` group (or root).
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use evdevil::event::Key;
+use evdevil::Evdev;
+
+/// How often to look for newly plugged-in devices with the key.
+const RESCAN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often the key state is read, off the touch loop's thread.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The `touch_gate_key` setting as a key code.
+pub fn parse_key(key_name: &str) -> Result<Key, Box<dyn std::error::Error + Send + Sync>> {
+    key_name
+        .parse::<Key>()
+        .map_err(|_| format!("Invalid touch_gate_key '{}' (use evdev names like KEY_LEFTCTRL)", key_name).into())
+}
+
+/// Polls the key in a background thread, so the touch loop only reads a
+/// flag per frame. The thread ends when the gate is dropped.
+pub struct TouchGate {
+    held: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+}
+
+impl TouchGate {
+    pub fn new(key_name: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let mut poller = Poller {
+            key: parse_key(key_name)?,
+            devices: Vec::new(),
+            scanned: None,
+            warned: false,
+        };
+        let held = Arc::new(AtomicBool::new(false));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (poll_held, poll_stop) = (held.clone(), stop.clone());
+        thread::Builder::new().name("touch-gate".into()).spawn(move || {
+            while !poll_stop.load(Ordering::Relaxed) {
+                poll_held.store(poller.is_held(Instant::now()), Ordering::Relaxed);
+                thread::sleep(POLL_INTERVAL);
+            }
+        })?;
+        Ok(Self { held, stop })
+    }
+
+    /// Whether the key was held on any local device at the last poll.
+    pub fn is_held(&self) -> bool {
+        self.held.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for TouchGate {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+struct Poller {
+    key: Key,
+    devices: Vec<Evdev>,
+    scanned: Option<Instant>,
+    warned: bool,
+}
+
+impl Poller {
+    /// Whether the key is held on any local device right now.
+    fn is_held(&mut self, now: Instant) -> bool {
+        if self.scanned.is_none_or(|t| now.duration_since(t) >= RESCAN_INTERVAL) {
+            self.scan();
+            self.scanned = Some(now);
+        }
+
+        let mut held = false;
+        self.devices.retain(|device| match device.key_state() {
+            Ok(keys) => {
+                held |= keys.contains(self.key);
+                true
+            }
+            // Unplugged
+            Err(_) => false,
+        });
+        held
+    }
+
+    fn scan(&mut self) {
+        let Ok(devices) = evdevil::enumerate() else {
+            return;
+        };
+        self.devices = devices
+            .filter_map(Result::ok)
+            .filter(|(_, device)| device.supported_keys().is_ok_and(|keys| keys.contains(self.key)))
+            .map(|(_, device)| device)
+            .collect();
+
+        if self.devices.is_empty() && !self.warned {
+            log::warn!(
+                "[touch] No local input device with {:?} is readable (is your user in the input group?); touch stays off",
+                self.key
+            );
+        }
+        self.warned = self.devices.is_empty();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gate_key_names() {
+        assert_eq!(parse_key("KEY_LEFTCTRL").unwrap(), Key::KEY_LEFTCTRL);
+        assert!(parse_key("LEFTCTRL").is_err());
+        assert!(TouchGate::new("LEFTCTRL").is_err());
+    }
+}
@@ -0,0 +1,266 @@
+//! Multi-finger swipes as key combos (`gesture_keys`).
+//!
+//! A binding like `"3-left" = "KEY_LEFTMETA+KEY_PAGEUP"` takes every
+//! three-finger contact away from the touchpad (the fingers are lifted
+//! there, so the compositor doesn't act on them too) and presses the combo
+//! on a separate keyboard device once the fingers' centre has moved far
+//! enough in that direction. The fingers stay off the touchpad until all of
+//! them have lifted. Finger counts without a binding are forwarded as usual.
+//! Mouse buttons in a combo (`"3-down" = "BTN_MIDDLE"`) are clicked on the
+//! shared mouse instead of the keyboard.
+
+use std::collections::BTreeMap;
+use std::io;
+
+use evdevil::event::{InputEvent, Key, KeyEvent, KeyState};
+use evdevil::uinput::UinputDevice;
+
+use super::event::EV_KEY;
+use super::mouse;
+use super::sink::EventSink;
+
+const GESTURE_KEYBOARD_NAME: &str = "reMarkable Gesture Keys";
+
+/// Distance the fingers' centre has to travel, in output touch units
+/// (about 20 mm on the reMarkable 2).
+const SWIPE_DISTANCE: i32 = 180;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Binding {
+    fingers: i32,
+    direction: Direction,
+    keys: Vec<Key>,
+}
+
+/// Parse the `gesture_keys` table: `"<2-5>-<left|right|up|down>"` to
+/// `+`-separated evdev key names.
+pub fn parse_bindings(table: &BTreeMap<String, String>) -> Result<Vec<Binding>, String> {
+    table
+        .iter()
+        .map(|(gesture, combo)| {
+            let invalid = || format!("Invalid gesture '{}'. Expected e.g. \"3-left\" (2-5 fingers; left, right, up or down)", gesture);
+            let (fingers, direction) = gesture.split_once('-').ok_or_else(invalid)?;
+            let fingers: i32 = fingers.parse().map_err(|_| invalid())?;
+            if !(2..=5).contains(&fingers) {
+                return Err(invalid());
+            }
+            let direction = match direction {
+                "left" => Direction::Left,
+                "right" => Direction::Right,
+                "up" => Direction::Up,
+                "down" => Direction::Down,
+                _ => return Err(invalid()),
+            };
+
+            let keys = combo
+                .split('+')
+                .map(|name| {
+                    name.trim()
+                        .parse::<Key>()
+                        .map_err(|_| format!("Invalid key '{}' for gesture '{}' (use evdev names like KEY_LEFTMETA)", name, gesture))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            Ok(Binding { fingers, direction, keys })
+        })
+        .collect()
+}
+
+/// Whether any binding in the `gesture_keys` table clicks a mouse button,
+/// so touch needs the shared mouse. An invalid table needs nothing, it
+/// fails when touch starts.
+pub fn clicks_mouse(table: &BTreeMap<String, String>) -> bool {
+    parse_bindings(table).is_ok_and(|bindings| bindings.iter().flat_map(|b| &b.keys).any(|key| mouse::BUTTONS.contains(key)))
+}
+
+/// Keyboard device that declares every key used by `bindings`, except the
+/// mouse buttons. `None` if the bindings only click.
+pub fn create_keyboard(bindings: &[Binding]) -> Result<Option<UinputDevice>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut keys = bindings.iter().flat_map(|b| b.keys.iter().copied()).filter(|key| !mouse::BUTTONS.contains(key)).peekable();
+    if keys.peek().is_none() {
+        return Ok(None);
+    }
+    let device = UinputDevice::builder()?.with_keys(keys)?.build(GESTURE_KEYBOARD_NAME)?;
+
+    if let Ok(name) = device.sysname() {
+        log::info!("[touch] Gesture keyboard ready: /sys/devices/virtual/input/{}", name.to_string_lossy());
+    }
+    Ok(Some(device))
+}
+
+/// Where combos go: keys to the gesture keyboard, mouse buttons to the
+/// shared mouse. A frame is split into runs in order, so a modifier
+/// pressed before a click is down when it lands and released after it.
+pub struct GestureOutput<K, M> {
+    pub keyboard: Option<K>,
+    pub mouse: Option<M>,
+}
+
+impl<K: EventSink, M: EventSink> EventSink for GestureOutput<K, M> {
+    fn write_frame(&mut self, events: &[InputEvent]) -> io::Result<()> {
+        let is_click = |e: &InputEvent| e.event_type().raw() == EV_KEY && mouse::BUTTONS.iter().any(|b| b.raw() == e.raw_code());
+        for run in events.chunk_by(|a, b| is_click(a) == is_click(b)) {
+            if is_click(&run[0]) {
+                self.mouse.write_frame(run)?;
+            } else {
+                self.keyboard.write_frame(run)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Tracks one swipe at a time and reports when it completes.
+pub struct Swipes {
+    bindings: Vec<Binding>,
+    fingers: i32,
+    start: Option<(i32, i32)>,
+    fired: bool,
+    /// A bound finger count was reached and not all fingers have lifted since.
+    active: bool,
+}
+
+impl Swipes {
+    pub fn new(bindings: Vec<Binding>) -> Self {
+        Self {
+            bindings,
+            fingers: 0,
+            start: None,
+            fired: false,
+            active: false,
+        }
+    }
+
+    fn binds(&self, fingers: i32) -> bool {
+        self.bindings.iter().any(|b| b.fingers == fingers)
+    }
+
+    /// Whether the current contacts belong to a gesture (as of the last `update`).
+    pub fn in_gesture(&self) -> bool {
+        self.active
+    }
+
+    /// Feed the current finger count and their centre (output coordinates).
+    /// Returns the keys to press when a bound swipe has just completed;
+    /// each gesture fires at most once until the finger count changes.
+    pub fn update(&mut self, fingers: i32, centre: Option<(i32, i32)>) -> Option<&[Key]> {
+        if fingers != self.fingers {
+            self.fingers = fingers;
+            self.start = centre;
+            self.fired = false;
+            self.active = self.binds(fingers) || (self.active && fingers > 0);
+            return None;
+        }
+        let (start, (x, y)) = (self.start.or(centre)?, centre?);
+        self.start = Some(start);
+        if self.fired {
+            return None;
+        }
+
+        let (dx, dy) = (x - start.0, y - start.1);
+        let direction = match (dx.abs() >= dy.abs(), dx, dy) {
+            (true, dx, _) if dx <= -SWIPE_DISTANCE => Direction::Left,
+            (true, dx, _) if dx >= SWIPE_DISTANCE => Direction::Right,
+            (false, _, dy) if dy <= -SWIPE_DISTANCE => Direction::Up,
+            (false, _, dy) if dy >= SWIPE_DISTANCE => Direction::Down,
+            _ => return None,
+        };
+
+        self.fired = true;
+        self.bindings
+            .iter()
+            .find(|b| b.fingers == fingers && b.direction == direction)
+            .map(|b| b.keys.as_slice())
+    }
+}
+
+/// Press every key in order, then release them in reverse, as two frames.
+pub fn combo_frames(keys: &[Key]) -> [Vec<InputEvent>; 2] {
+    let event = |key: &Key, state| InputEvent::from(KeyEvent::new(*key, state));
+    [
+        keys.iter().map(|k| event(k, KeyState::PRESSED)).collect(),
+        keys.iter().rev().map(|k| event(k, KeyState::RELEASED)).collect(),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::RecordingSink;
+
+    fn table(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_parse_bindings() {
+        let bindings = parse_bindings(&table(&[("3-left", "KEY_LEFTMETA+KEY_PAGEUP")])).unwrap();
+        assert_eq!(
+            bindings,
+            vec![Binding {
+                fingers: 3,
+                direction: Direction::Left,
+                keys: vec![Key::KEY_LEFTMETA, Key::KEY_PAGEUP],
+            }]
+        );
+
+        assert!(parse_bindings(&table(&[("1-left", "KEY_A")])).is_err());
+        assert!(parse_bindings(&table(&[("3-sideways", "KEY_A")])).is_err());
+        assert!(parse_bindings(&table(&[("3-up", "KEY_LEFTMETA+NOPE")])).is_err());
+    }
+
+    #[test]
+    fn test_swipe_fires_once() {
+        let bindings = parse_bindings(&table(&[("3-right", "KEY_LEFTMETA+KEY_PAGEDOWN")])).unwrap();
+        let mut swipes = Swipes::new(bindings);
+        swipes.update(2, Some((1000, 500)));
+        assert!(!swipes.in_gesture());
+
+        assert_eq!(swipes.update(3, Some((1000, 500))), None);
+        assert!(swipes.in_gesture());
+        assert_eq!(swipes.update(3, Some((1100, 520))), None);
+        assert_eq!(swipes.update(3, Some((1200, 520))), Some(&[Key::KEY_LEFTMETA, Key::KEY_PAGEDOWN][..]));
+        assert_eq!(swipes.update(3, Some((1400, 520))), None);
+
+        // Lifting one finger keeps the rest out of the touchpad
+        swipes.update(2, Some((1400, 520)));
+        assert!(swipes.in_gesture());
+
+        // Unbound direction: nothing, but the gesture is used up
+        swipes.update(0, None);
+        assert!(!swipes.in_gesture());
+        swipes.update(3, Some((1000, 500)));
+        assert_eq!(swipes.update(3, Some((1000, 300))), None);
+        assert_eq!(swipes.update(3, Some((1300, 300))), None);
+    }
+
+    #[test]
+    fn test_mouse_buttons_go_to_the_mouse() {
+        assert!(clicks_mouse(&table(&[("3-down", "KEY_LEFTCTRL+BTN_MIDDLE")])));
+        assert!(!clicks_mouse(&table(&[("3-down", "KEY_LEFTMETA+KEY_PAGEUP")])));
+
+        let mut output = GestureOutput {
+            keyboard: Some(RecordingSink::default()),
+            mouse: Some(RecordingSink::default()),
+        };
+        for frame in combo_frames(&[Key::KEY_LEFTCTRL, Key::BTN_MIDDLE]) {
+            output.write_frame(&frame).unwrap();
+        }
+
+        let key = |key: Key, value| vec![InputEvent::from(KeyEvent::new(key, KeyState::from_raw(value)))];
+        // Ctrl is down before the click and released after it
+        assert_eq!(
+            output.keyboard.unwrap().frames,
+            vec![key(Key::KEY_LEFTCTRL, 1), key(Key::KEY_LEFTCTRL, 0)]
+        );
+        assert_eq!(output.mouse.unwrap().frames, vec![key(Key::BTN_MIDDLE, 1), key(Key::BTN_MIDDLE, 0)]);
+    }
+}
@@ -0,0 +1,132 @@
+//! Keyboard folio forwarding (`keyboard_device`).
+//!
+//! The Paper Pro's type folio is a plain evdev keyboard on the tablet. Its
+//! key presses and releases are passed through to a keyboard uinput device
+//! on the host. Kernel autorepeat events are dropped, since the host's
+//! compositor repeats held keys itself. LED state (caps lock) isn't sent
+//! back: the stream from the tablet only goes one way.
+
+use std::io::Read;
+use std::time::Duration;
+
+use evdevil::event::{InputEvent, Key};
+use evdevil::uinput::UinputDevice;
+
+use crate::config::Config;
+use crate::device::DeviceProfile;
+use crate::ssh;
+
+use super::event::{parse_input_event, read_event, EV_KEY, EV_SYN, SYN_REPORT};
+use super::perms;
+use super::sink::EventSink;
+use super::stop::StopFlag;
+
+const KEYBOARD_DEVICE_NAME: &str = "reMarkable Keyboard";
+
+/// Value of an EV_KEY event generated by kernel autorepeat.
+const KEY_REPEAT: i32 = 2;
+
+/// Every `KEY_*` code, leaving out the `BTN_*` ranges (0x100..0x160) so
+/// the device is classified as a keyboard only.
+fn keyboard_keys() -> impl Iterator<Item = Key> {
+    (0x01..0x100u16).chain(0x160..=0x2ffu16).map(Key::from_raw)
+}
+
+pub fn create_device() -> Result<UinputDevice, Box<dyn std::error::Error + Send + Sync>> {
+    let device = UinputDevice::builder()?
+        .with_keys(keyboard_keys())?
+        .build(KEYBOARD_DEVICE_NAME)?;
+
+    if let Ok(name) = device.sysname() {
+        log::info!("Keyboard device ready: /sys/devices/virtual/input/{}", name.to_string_lossy());
+    }
+    Ok(device)
+}
+
+pub fn run_keyboard(
+    config: &Config,
+    device_profile: &DeviceProfile,
+    device_path: &str,
+    grab: bool,
+    stop: &StopFlag,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (cleanup, channel) = ssh::open_input_stream(device_path, config, grab)?;
+    let mut channel = stop.reader(&cleanup, channel, None, None);
+
+    log::info!("Creating keyboard uinput device");
+    let mut uinput = create_device()?;
+    std::thread::sleep(Duration::from_secs(1));
+    perms::share(&uinput, config.device_group.as_deref())?;
+    log::info!("Keyboard forwarding started");
+
+    let result = run_event_loop(&mut channel, &mut uinput, device_profile);
+    ssh::check_stream_end(channel.get_mut(), &cleanup, config, grab, result)
+}
+
+/// Pass key presses and releases from `channel` to `sink`, one frame per SYN_REPORT.
+pub fn run_event_loop(
+    channel: &mut impl Read,
+    sink: &mut impl EventSink,
+    device: &DeviceProfile,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut buf = vec![0u8; device.input_event_size];
+    let mut frame: Vec<InputEvent> = Vec::new();
+
+    loop {
+        read_event(channel, &mut buf)?;
+
+        let Some(ev) = parse_input_event(&buf) else {
+            continue;
+        };
+
+        match ev.event_type().raw() {
+            EV_KEY if ev.raw_value() != KEY_REPEAT => frame.push(ev),
+            EV_SYN if ev.raw_code() == SYN_REPORT && !frame.is_empty() => {
+                sink.write_frame(&frame)?;
+                frame.clear();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::RM2;
+    use crate::input::event::raw_event;
+    use crate::input::RecordingSink;
+
+    #[test]
+    fn test_keys_pass_through_without_repeats() {
+        const EV_MSC: u16 = 0x04;
+        let key_a = Key::KEY_A.raw();
+        let stream: Vec<u8> = [
+            raw_event(EV_MSC, 0x04, 0x70004),
+            raw_event(EV_KEY, key_a, 1),
+            raw_event(EV_SYN, SYN_REPORT, 0),
+            raw_event(EV_KEY, key_a, KEY_REPEAT),
+            raw_event(EV_SYN, SYN_REPORT, 0),
+            raw_event(EV_KEY, key_a, 0),
+            raw_event(EV_SYN, SYN_REPORT, 0),
+        ]
+        .concat();
+
+        let mut sink = RecordingSink::default();
+        let result = run_event_loop(&mut stream.as_slice(), &mut sink, &RM2);
+        assert!(result.is_err(), "loop only ends at end of stream");
+
+        let values: Vec<Vec<(u16, i32)>> = sink
+            .frames
+            .iter()
+            .map(|f| f.iter().map(|e| (e.raw_code(), e.raw_value())).collect())
+            .collect();
+        assert_eq!(values, vec![vec![(key_a, 1)], vec![(key_a, 0)]]);
+    }
+
+    #[test]
+    fn test_keyboard_keys_skip_buttons() {
+        assert!(keyboard_keys().all(|k| !(0x100..0x160).contains(&k.raw())));
+        assert!(keyboard_keys().any(|k| k == Key::KEY_A));
+    }
+}
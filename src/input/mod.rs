@@ -1,7 +1,33 @@
 mod event;
+mod existing;
+mod gate;
+mod gesture;
+mod keyboard;
+mod mouse;
+mod pace;
 mod pen;
+mod perms;
+mod power;
+mod range;
+mod shared;
+mod sink;
+mod stop;
+mod throttle;
 mod touch;
+mod touch_pen;
+mod wayland;
 
-pub use event::parse_input_event;
-pub use pen::run_pen;
-pub use touch::run_touch;
+pub use gate::parse_key as parse_gate_key;
+pub use gesture::{clicks_mouse as gesture_clicks_mouse, parse_bindings as parse_gesture_bindings};
+pub use event::{parse_input_event, parse_timed_event, read_event, IdleDisconnect, INPUT_EVENT_SIZE_64};
+pub use event::{ABS_MT_POSITION_X, ABS_MT_POSITION_Y, ABS_PRESSURE, ABS_X, ABS_Y, EV_ABS, EV_SYN};
+pub use keyboard::run_keyboard;
+pub use mouse::{SharedMouse, VirtualMouse};
+pub use pen::{forward as forward_pen, run_event_loop as run_pen_loop, run_pen, PenContactSource, PenFrameAssembler, PenHover, PenMode, RegionExit, PenState};
+pub use perms::resolve_group;
+pub use power::{run_power, TabletSleep};
+pub use shared::run_shared;
+pub use sink::RecordingSink;
+pub use stop::{stop_on_signals, StopFlag};
+pub use throttle::limit_event_rate;
+pub use touch::{forward as forward_touch, run_event_loop as run_touch_loop, run_touch, PrimaryContact, TouchMode};
@@ -0,0 +1,85 @@
+//! Shared virtual mouse for actions that cross device boundaries.
+//!
+//! Pen and touch each own their uinput device, but actions routed to the
+//! pointer go through this single device so the compositor sees one
+//! consistent mouse: the pen's barrel button with `pen_button_click`, and
+//! gesture bindings that name a mouse button (`"3-down" = "BTN_MIDDLE"`).
+//! Writes are serialized with a mutex: every call emits a complete
+//! SYN_REPORT frame while holding the lock, so events from the pen and
+//! touch threads never interleave.
+
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use evdevil::event::{InputEvent, Key, KeyEvent, KeyState, Rel};
+use evdevil::uinput::UinputDevice;
+
+use super::perms;
+use super::sink::EventSink;
+
+/// Buttons the mouse declares.
+pub const BUTTONS: [Key; 3] = [Key::BTN_LEFT, Key::BTN_RIGHT, Key::BTN_MIDDLE];
+
+pub struct VirtualMouse {
+    device: Mutex<Box<dyn EventSink + Send>>,
+}
+
+pub type SharedMouse = Arc<VirtualMouse>;
+
+impl VirtualMouse {
+    /// Create the device, handing its event node to `group` (`device_group`) if set.
+    pub fn create(group: Option<&str>) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        // REL axes are declared (never moved) so libinput classifies it as a mouse.
+        let device = UinputDevice::builder()?
+            .with_rel_axes([Rel::X, Rel::Y])?
+            .with_keys(BUTTONS)?
+            .build("reMarkable Mouse")?;
+
+        if let Ok(name) = device.sysname() {
+            log::info!("Mouse device ready: /sys/devices/virtual/input/{}", name.to_string_lossy());
+        }
+        perms::share(&device, group)?;
+
+        Ok(Self::with_sink(device))
+    }
+
+    fn with_sink(sink: impl EventSink + Send + 'static) -> Self {
+        Self {
+            device: Mutex::new(Box::new(sink)),
+        }
+    }
+
+    /// Press or release a mouse button as one frame.
+    pub fn button(&self, key: Key, pressed: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let state = if pressed { KeyState::PRESSED } else { KeyState::RELEASED };
+        self.write(&[KeyEvent::new(key, state).into()])?;
+        Ok(())
+    }
+
+    /// Write one frame while holding the lock.
+    pub fn write(&self, events: &[InputEvent]) -> io::Result<()> {
+        let mut device = self.device.lock().map_err(|_| io::Error::other("Mouse device lock poisoned"))?;
+        device.write_frame(events)
+    }
+
+    /// A mouse that keeps its frames in the returned sink instead of a device.
+    #[cfg(test)]
+    pub fn recording() -> (SharedMouse, Arc<Mutex<super::sink::RecordingSink>>) {
+        struct Recording(Arc<Mutex<super::sink::RecordingSink>>);
+
+        impl EventSink for Recording {
+            fn write_frame(&mut self, events: &[InputEvent]) -> io::Result<()> {
+                self.0.lock().unwrap().write_frame(events)
+            }
+        }
+
+        let frames = Arc::default();
+        (Arc::new(Self::with_sink(Recording(Arc::clone(&frames)))), frames)
+    }
+}
+
+impl EventSink for SharedMouse {
+    fn write_frame(&mut self, events: &[InputEvent]) -> io::Result<()> {
+        self.write(events)
+    }
+}
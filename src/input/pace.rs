@@ -0,0 +1,81 @@
+//! Reproduce the tablet's frame cadence (`pace`).
+//!
+//! Without pacing, every frame is forwarded the moment its SYN_REPORT
+//! arrives, so frames held up by a network hiccup come out in one burst.
+//! With it, each frame is delayed until its original interval (from the
+//! event timestamps) after the previous one has passed. The delay this
+//! builds up is capped at `MAX_LAG`; beyond that frames go out immediately
+//! again so the cursor catches up instead of trailing behind.
+
+use std::time::{Duration, Instant};
+
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+pub struct Pacer {
+    enabled: bool,
+    /// Timestamp of the previous frame and when it was forwarded.
+    last: Option<(Duration, Instant)>,
+}
+
+impl Pacer {
+    pub fn new(enabled: bool) -> Self {
+        Self { enabled, last: None }
+    }
+
+    /// Sleep until the frame stamped `timestamp` is due.
+    pub fn wait(&mut self, timestamp: Duration) {
+        if !self.enabled {
+            return;
+        }
+        if let Some(delay) = self.delay(timestamp, Instant::now()) {
+            std::thread::sleep(delay);
+        }
+    }
+
+    /// How long to hold a frame stamped `timestamp` that arrived at `now`.
+    fn delay(&mut self, timestamp: Duration, now: Instant) -> Option<Duration> {
+        let due = self
+            .last
+            .and_then(|(last_ts, sent)| timestamp.checked_sub(last_ts).map(|interval| sent + interval))
+            .filter(|&due| due > now && due - now <= MAX_LAG);
+
+        self.last = Some((timestamp, due.unwrap_or(now)));
+        due.map(|due| due - now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_burst_is_spread_out() {
+        let mut pacer = Pacer::new(true);
+        let start = Instant::now();
+
+        // Three frames 10ms apart arrive together after a hiccup
+        assert_eq!(pacer.delay(ms(1000), start), None);
+        assert_eq!(pacer.delay(ms(1010), start), Some(ms(10)));
+        assert_eq!(pacer.delay(ms(1020), start), Some(ms(20)));
+        // On time again: nothing to wait for
+        assert_eq!(pacer.delay(ms(1030), start + ms(40)), None);
+    }
+
+    #[test]
+    fn test_lag_is_capped() {
+        let mut pacer = Pacer::new(true);
+        let start = Instant::now();
+
+        assert_eq!(pacer.delay(ms(0), start), None);
+        assert_eq!(pacer.delay(ms(80), start), Some(ms(80)));
+        // Would trail 160ms behind: forward now and pace from here
+        assert_eq!(pacer.delay(ms(160), start), None);
+        assert_eq!(pacer.delay(ms(170), start), Some(ms(10)));
+        // Timestamps going backwards (clock change) never wait
+        assert_eq!(pacer.delay(ms(5), start), None);
+    }
+}
@@ -1,82 +1,830 @@
-use std::io::Read;
-use std::time::Instant;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
-use evdevil::event::{Abs, InputEvent, Key};
+use evdevil::event::{Abs, EventType, InputEvent, Key, Rel};
 use evdevil::uinput::{AbsSetup, UinputDevice};
 use evdevil::{AbsInfo, Bus, InputId, InputProp};
+use serde::Deserialize;
 
-use crate::config::Config;
+use crate::config::{Config, OutputBackend};
 use crate::device::DeviceProfile;
-use crate::orientation::Orientation;
 use crate::palm::SharedPalmState;
+use crate::screen;
 use crate::ssh;
+use crate::status;
 
-use super::event::{key_event, parse_input_event, ABS_PRESSURE, EV_ABS, EV_SYN, SYN_REPORT};
+use super::existing;
+use super::mouse::SharedMouse;
+use super::pace::Pacer;
+use super::perms;
+use super::range::{RangeExpanded, RangeMonitor};
+use super::sink::{EventSink, FrameLog, Repairing, SkipRepeats, TeeSink};
+use super::stop::StopFlag;
+use super::throttle::{self, Stream};
+use super::wayland::VirtualPointer;
 
-const ABS_X: u16 = 0x00;
-const ABS_Y: u16 = 0x01;
+use super::event::{below_min_delta, key_event, parse_timed_event, read_event, Warmup, ABS_PRESSURE, ABS_X, ABS_Y, EV_ABS, EV_KEY, EV_REL, EV_SYN, SYN_REPORT};
+
+const PEN_DEVICE_NAME: &str = "reMarkable Pen";
+
+const ABS_DISTANCE: u16 = 0x19;
 const ABS_TILT_X: u16 = 0x1a;
 const ABS_TILT_Y: u16 = 0x1b;
+const BTN_TOUCH: u16 = 0x14a;
+const BTN_STYLUS: u16 = 0x14b;
+
+/// Which reported value decides whether the pen is touching the screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PenContactSource {
+    /// Touching while `ABS_PRESSURE > 0`.
+    #[default]
+    Pressure,
+    /// Follow the device's own `BTN_TOUCH` events.
+    BtnTouch,
+    /// Use `BTN_TOUCH` once the device has sent one, pressure until then.
+    Auto,
+}
+
+impl fmt::Display for PenContactSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PenContactSource::Pressure => write!(f, "pressure"),
+            PenContactSource::BtnTouch => write!(f, "btn-touch"),
+            PenContactSource::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+impl FromStr for PenContactSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "pressure" => Ok(PenContactSource::Pressure),
+            "btn-touch" | "btn_touch" | "btntouch" => Ok(PenContactSource::BtnTouch),
+            "auto" => Ok(PenContactSource::Auto),
+            _ => Err(format!(
+                "Invalid pen contact source '{}'. Valid values: pressure, btn-touch, auto",
+                s
+            )),
+        }
+    }
+}
+
+/// What to forward while the pen hovers above the screen (`pen_hover`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PenHover {
+    /// Move the cursor with the hovering pen.
+    #[default]
+    Move,
+    /// Forward nothing until the pen touches; the pen only exists while drawing.
+    Ignore,
+    /// Report proximity and distance, but keep the cursor still.
+    ProximityOnly,
+}
+
+impl fmt::Display for PenHover {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PenHover::Move => write!(f, "move"),
+            PenHover::Ignore => write!(f, "ignore"),
+            PenHover::ProximityOnly => write!(f, "proximity-only"),
+        }
+    }
+}
+
+impl FromStr for PenHover {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "move" => Ok(PenHover::Move),
+            "ignore" => Ok(PenHover::Ignore),
+            "proximity-only" | "proximity_only" => Ok(PenHover::ProximityOnly),
+            _ => Err(format!(
+                "Invalid pen hover mode '{}'. Valid values: move, ignore, proximity-only",
+                s
+            )),
+        }
+    }
+}
 
-fn create_pen_device(device: &DeviceProfile, orientation: Orientation) -> Result<UinputDevice, Box<dyn std::error::Error + Send + Sync>> {
-    let (out_x_max, out_y_max) = orientation.pen_output_dimensions(device.pen_x_max, device.pen_y_max);
-    let axes = [
+/// How pen positions reach the host (`pen_mode`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PenMode {
+    /// A tablet device: the cursor goes where the pen is.
+    #[default]
+    Absolute,
+    /// A mouse: pen movement nudges the cursor and contact is a left click.
+    Relative,
+}
+
+impl fmt::Display for PenMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PenMode::Absolute => write!(f, "absolute"),
+            PenMode::Relative => write!(f, "relative"),
+        }
+    }
+}
+
+impl FromStr for PenMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "absolute" => Ok(PenMode::Absolute),
+            "relative" => Ok(PenMode::Relative),
+            _ => Err(format!("Invalid pen mode '{}'. Valid values: absolute, relative", s)),
+        }
+    }
+}
+
+/// Tracks pen contact according to the configured `PenContactSource`.
+#[derive(Debug, Clone, Copy)]
+struct ContactTracker {
+    source: PenContactSource,
+    btn_touch_seen: bool,
+    btn_touch_down: bool,
+    /// Last reported pressure; frames only carry it when it changed.
+    pressure: i32,
+}
+
+impl ContactTracker {
+    fn new(source: PenContactSource) -> Self {
+        Self {
+            source,
+            btn_touch_seen: false,
+            btn_touch_down: false,
+            pressure: 0,
+        }
+    }
+
+    /// Update from a completed frame and return whether the pen is touching.
+    fn update(&mut self, frame: &PenFrame) -> bool {
+        if let Some(pressure) = frame.abs_value(ABS_PRESSURE) {
+            self.pressure = pressure;
+        }
+        if let Some(value) = frame.key_value(BTN_TOUCH) {
+            if !self.btn_touch_seen {
+                log::debug!("[pen] Device reports BTN_TOUCH");
+            }
+            self.btn_touch_seen = true;
+            self.btn_touch_down = value != 0;
+        }
+
+        let use_btn_touch = match self.source {
+            PenContactSource::Pressure => false,
+            PenContactSource::BtnTouch => true,
+            PenContactSource::Auto => self.btn_touch_seen,
+        };
+
+        if use_btn_touch {
+            self.btn_touch_down
+        } else {
+            self.pressure > 0
+        }
+    }
+}
+
+const PEN_KEYS: [Key; 3] = [Key::BTN_TOOL_PEN, Key::BTN_TOUCH, Key::BTN_STYLUS];
+
+/// Output range of the pen and the tablet's offset inside it (`fit_screen`).
+fn pen_area(config: &Config, device: &DeviceProfile) -> Result<screen::Fit, Box<dyn std::error::Error + Send + Sync>> {
+    let tablet = config.pen_orientation.pen_output_dimensions(device.pen_x_max, device.pen_y_max);
+    Ok(screen::fit(tablet, screen::size(config)?))
+}
+
+fn pen_axes(device: &DeviceProfile, (out_x_max, out_y_max): (i32, i32), pressure_max: i32) -> [AbsSetup; 6] {
+    [
         AbsSetup::new(Abs::X, AbsInfo::new(0, out_x_max).with_resolution(100)),
         AbsSetup::new(Abs::Y, AbsInfo::new(0, out_y_max).with_resolution(100)),
-        AbsSetup::new(Abs::PRESSURE, AbsInfo::new(0, device.pen_pressure_max)),
+        AbsSetup::new(Abs::PRESSURE, AbsInfo::new(0, pressure_max)),
         AbsSetup::new(Abs::DISTANCE, AbsInfo::new(0, device.pen_distance_max)),
         AbsSetup::new(Abs::TILT_X, AbsInfo::new(-device.pen_tilt_range, device.pen_tilt_range)),
         AbsSetup::new(Abs::TILT_Y, AbsInfo::new(-device.pen_tilt_range, device.pen_tilt_range)),
-    ];
+    ]
+}
+
+fn create_pen_device(device: &DeviceProfile, config: &Config) -> Result<UinputDevice, Box<dyn std::error::Error + Send + Sync>> {
+    // validate() has checked that the ids fit in 16 bits
+    let (bus, vendor, product) = (config.pen_bus as u16, config.pen_vendor as u16, config.pen_product as u16);
+    log::info!("[pen] Device id: bus 0x{:04x}, vendor 0x{:04x}, product 0x{:04x}", bus, vendor, product);
 
     let device = UinputDevice::builder()?
-        .with_input_id(InputId::new(Bus::from_raw(0x03), 0x2d1f, 0x0001, 0))?
+        .with_input_id(InputId::new(Bus::from_raw(bus), vendor, product, 0))?
         .with_props([InputProp::DIRECT])?
-        .with_abs_axes(axes)?
-        .with_keys([Key::BTN_TOOL_PEN, Key::BTN_TOUCH, Key::BTN_STYLUS])?
-        .build("reMarkable Pen")?;
+        .with_abs_axes(pen_axes(device, pen_area(config, device)?.max, config.pen_pressure_max))?
+        .with_keys(PEN_KEYS)?
+        .build(PEN_DEVICE_NAME)?;
+
+    Ok(device)
+}
+
+const RELATIVE_DEVICE_NAME: &str = "reMarkable Pen Pointer";
+
+/// Output pen units per millimetre, as declared on ABS_X/ABS_Y.
+const PEN_UNITS_PER_MM: f64 = 100.0;
+
+fn create_relative_device() -> Result<UinputDevice, Box<dyn std::error::Error + Send + Sync>> {
+    let device = UinputDevice::builder()?
+        .with_rel_axes([Rel::X, Rel::Y])?
+        .with_keys([Key::BTN_LEFT, Key::BTN_RIGHT])?
+        .build(RELATIVE_DEVICE_NAME)?;
 
     Ok(device)
 }
 
+/// Turns the pen's absolute output frames into mouse motion (`pen_mode =
+/// "relative"`): REL_X/REL_Y from the change in position, scaled by
+/// `sensitivity` counts per millimetre, BTN_TOUCH as BTN_LEFT and the side
+/// button (BTN_STYLUS) as BTN_RIGHT. With `pen_button_click` the side button
+/// never gets here, it is the shared mouse's left click instead.
+/// Leaving proximity forgets the position, so the cursor doesn't jump
+/// when the pen comes back somewhere else.
+struct RelativePen<S> {
+    sink: S,
+    scale: f64,
+    last: (Option<i32>, Option<i32>),
+    /// Sub-count motion carried over to the next frame.
+    remainder: (f64, f64),
+}
+
+impl<S: EventSink> RelativePen<S> {
+    fn new(sink: S, sensitivity: f64) -> Self {
+        Self {
+            sink,
+            scale: sensitivity / PEN_UNITS_PER_MM,
+            last: (None, None),
+            remainder: (0.0, 0.0),
+        }
+    }
+
+    /// Counts to move for a new coordinate on one axis.
+    fn delta(last: &mut Option<i32>, remainder: &mut f64, value: i32, scale: f64) -> i32 {
+        let Some(previous) = last.replace(value) else {
+            return 0;
+        };
+        let exact = f64::from(value - previous) * scale + *remainder;
+        let counts = exact.trunc();
+        *remainder = exact - counts;
+        counts as i32
+    }
+}
+
+impl<S: EventSink> EventSink for RelativePen<S> {
+    fn write_frame(&mut self, events: &[InputEvent]) -> io::Result<()> {
+        let (mut dx, mut dy) = (0, 0);
+        let mut out = Vec::new();
+
+        for ev in events {
+            match (ev.event_type().raw(), ev.raw_code()) {
+                (EV_ABS, ABS_X) => dx += Self::delta(&mut self.last.0, &mut self.remainder.0, ev.raw_value(), self.scale),
+                (EV_ABS, ABS_Y) => dy += Self::delta(&mut self.last.1, &mut self.remainder.1, ev.raw_value(), self.scale),
+                (EV_KEY, BTN_TOUCH) => out.push(key_event(Key::BTN_LEFT.raw(), ev.raw_value())),
+                (EV_KEY, code) if code == Key::BTN_STYLUS.raw() => out.push(key_event(Key::BTN_RIGHT.raw(), ev.raw_value())),
+                (EV_KEY, code) if code == Key::BTN_TOOL_PEN.raw() && ev.raw_value() == 0 => {
+                    self.last = (None, None);
+                    self.remainder = (0.0, 0.0);
+                }
+                _ => {}
+            }
+        }
+
+        if dx != 0 {
+            out.push(InputEvent::new(EventType::from_raw(EV_REL), Rel::X.raw(), dx));
+        }
+        if dy != 0 {
+            out.push(InputEvent::new(EventType::from_raw(EV_REL), Rel::Y.raw(), dy));
+        }
+        if out.is_empty() {
+            return Ok(());
+        }
+        self.sink.write_frame(&out)
+    }
+}
+
 pub fn run_pen(
     config: &Config,
     device_profile: &DeviceProfile,
+    grab: bool,
     palm: Option<SharedPalmState>,
+    mouse: Option<SharedMouse>,
+    stop: &StopFlag,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let (_cleanup, mut channel) =
-        ssh::open_input_stream(&config.pen_device, config, config.grab_input)?;
+    let (cleanup, channel) = ssh::open_input_stream(&config.pen_device, config, grab)?;
+    let mut channel = stop.reader(&cleanup, channel, None, config.idle_disconnect_secs.map(Duration::from_secs));
 
-    log::info!("Creating pen uinput device");
-    let uinput = create_pen_device(device_profile, config.orientation)?;
+    let result = forward(&mut channel, device_profile, config, palm, mouse);
+    ssh::check_stream_end(channel.get_mut(), &cleanup, config, grab, result)
+}
+
+/// Set up the configured output and run the event loop into it.
+///
+/// With `pen_auto_range`, the output is set up again with the observed
+/// range when the pen goes well past the profile's.
+pub fn forward(
+    channel: &mut impl Read,
+    device_profile: &DeviceProfile,
+    config: &Config,
+    palm: Option<SharedPalmState>,
+    mouse: Option<SharedMouse>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut profile = *device_profile;
+    loop {
+        let result = forward_to_output(channel, &profile, config, palm.clone(), mouse.clone());
+        match result.as_ref().err().and_then(|e| e.downcast_ref::<RangeExpanded>()) {
+            Some(expanded) => {
+                log::info!("[pen] Setting up the output again for a {}x{} range", expanded.x_max, expanded.y_max);
+                (profile.pen_x_max, profile.pen_y_max) = (expanded.x_max, expanded.y_max);
+            }
+            None => return result,
+        }
+    }
+}
+
+fn forward_to_output(
+    channel: &mut impl Read,
+    device_profile: &DeviceProfile,
+    config: &Config,
+    palm: Option<SharedPalmState>,
+    mouse: Option<SharedMouse>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let tee = FrameLog::open(config.tee_output.as_deref(), "pen")?;
+
+    if config.output == OutputBackend::Wayland {
+        let (x_extent, y_extent) = pen_area(config, device_profile)?.max;
+        let pointer = VirtualPointer::connect(x_extent, y_extent)?;
+        log::info!("[pen] Forwarding started (Wayland virtual pointer)");
+        let mut sink = TeeSink { primary: pointer, secondary: tee };
+        return run_event_loop(channel, &mut sink, device_profile, config, palm, mouse);
+    }
+
+    if config.use_existing_device {
+        let axes = pen_axes(device_profile, pen_area(config, device_profile)?.max, config.pen_pressure_max);
+        let target = existing::open(config.existing_pen_device.as_deref(), PEN_DEVICE_NAME, &axes, &PEN_KEYS)?;
+        log::info!("[pen] Forwarding started");
+        let mut sink = TeeSink { primary: target, secondary: tee };
+        return run_event_loop(channel, &mut sink, device_profile, config, palm, mouse);
+    }
+
+    if config.pen_mode == PenMode::Relative {
+        log::info!("[pen] Creating relative uinput device");
+        let uinput = create_relative_device()?;
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        perms::share(&uinput, config.device_group.as_deref())?;
+        log::info!("[pen] Forwarding started (relative)");
+
+        let uinput = Repairing::new(uinput, "pen", || {
+            let uinput = create_relative_device()?;
+            perms::share(&uinput, config.device_group.as_deref())?;
+            Ok(uinput)
+        });
+        let mut sink = RelativePen::new(TeeSink { primary: uinput, secondary: tee }, config.pen_relative_sensitivity);
+        return run_event_loop(channel, &mut sink, device_profile, config, palm, mouse);
+    }
+
+    log::info!("[pen] Creating uinput device");
+    let uinput = create_pen_device(device_profile, config)?;
 
     if let Ok(name) = uinput.sysname() {
-        log::info!("Pen device ready: /sys/devices/virtual/input/{}", name.to_string_lossy());
+        log::info!("[pen] Device ready: /sys/devices/virtual/input/{}", name.to_string_lossy());
     }
 
     std::thread::sleep(std::time::Duration::from_secs(1));
-    log::info!("Pen forwarding started");
+    perms::share(&uinput, config.device_group.as_deref())?;
+    log::info!("[pen] Forwarding started");
 
+    let uinput = Repairing::new(uinput, "pen", || {
+        let uinput = create_pen_device(device_profile, config)?;
+        perms::share(&uinput, config.device_group.as_deref())?;
+        Ok(uinput)
+    });
+    let mut sink = TeeSink { primary: uinput, secondary: tee };
+    run_event_loop(channel, &mut sink, device_profile, config, palm, mouse)
+}
+
+/// Decode pen events from `channel` and emit them to `sink` until the stream ends.
+pub fn run_event_loop(
+    channel: &mut impl Read,
+    sink: &mut impl EventSink,
+    device_profile: &DeviceProfile,
+    config: &Config,
+    palm: Option<SharedPalmState>,
+    mouse: Option<SharedMouse>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let sink = &mut SkipRepeats::new(sink, config.skip_repeated_frames, &status::PEN);
     let btn_touch_code = Key::BTN_TOUCH.raw();
     let mut buf = vec![0u8; device_profile.input_event_size];
-    let mut batch: Vec<InputEvent> = Vec::with_capacity(32);
+    let mut assembler = PenFrameAssembler::new();
+    let mut contact = ContactTracker::new(config.pen_contact_source);
+    let mut tail = TailTrim::new(config.pen_tail_trim);
+    let mut smoothing = PressureSmoothing::new(
+        config.pen_smoothing_low,
+        config.pen_smoothing_high,
+        device_profile.pen_pressure_max,
+    );
+    let band = PressureBand::new(device_profile.pen_pressure_max, config.pen_pressure_min, config.pen_pressure_max);
+    let mut pacer = Pacer::new(config.pace);
+    let mut warmup = Warmup::new(config.warmup_discard_ms);
+    let mut range = RangeMonitor::new(device_profile);
     let mut touch_down = false;
+    let mut last_sent: Option<(i32, i32)> = None;
     let mut frame_count: u64 = 0;
-
-    // For collecting X/Y/tilt values within a frame
-    let mut pending_x: Option<i32> = None;
-    let mut pending_y: Option<i32> = None;
-    let mut pending_tilt_x: Option<i32> = None;
-    let mut pending_tilt_y: Option<i32> = None;
-    let orientation = config.orientation;
+    let orientation = config.pen_orientation;
+    let area = pen_area(config, device_profile)?;
+    let offset = pen_offset(config, device_profile);
+    let offset = (offset.0 + area.offset.0, offset.1 + area.offset.1);
 
     loop {
-        channel.read_exact(&mut buf)?;
+        read_event(channel, &mut buf)?;
+
+        let Some(timed) = parse_timed_event(&buf) else {
+            continue;
+        };
 
-        let Some(ev) = parse_input_event(&buf) else {
+        let Some(mut frame) = assembler.push(timed.event) else {
             continue;
         };
 
+        // Keep tracking contact through the warm-up so the first real frame starts right
+        let mut now_touching = contact.update(&frame);
+        if warmup.discard(Instant::now()) {
+            // Only the stale motion goes; key changes still go out so no button is left stuck
+            click_mouse(mouse.as_ref(), &mut frame)?;
+            let hidden = config.pen_hover == PenHover::Ignore && !now_touching && !touch_down;
+            let mut keys: Vec<InputEvent> = frame
+                .events
+                .iter()
+                .filter(|e| !hidden && e.event_type().raw() == EV_KEY && e.raw_code() != btn_touch_code)
+                .filter(|e| config.pen_hover != PenHover::Ignore || !is_proximity_event(e))
+                .copied()
+                .collect();
+            if now_touching != touch_down {
+                keys.insert(0, key_event(btn_touch_code, now_touching as i32));
+                touch_down = now_touching;
+            }
+            update_palm_state(&palm, now_touching);
+            if !keys.is_empty() {
+                sink.write_frame(&keys)?;
+            }
+            continue;
+        }
+
+        if let Some((x_max, y_max)) = frame.position.and_then(|p| range.observe(p, timed.time)) {
+            let (profile_x, profile_y) = range.profile_max();
+            log::warn!(
+                "[pen] Coordinates go well past the {} profile's range: up to {}x{}, profile {}x{}. \
+                 Drawing near the edges is clipped; please report these values",
+                device_profile.name, x_max, y_max, profile_x, profile_y
+            );
+            if config.pen_auto_range {
+                return Err(RangeExpanded { x_max, y_max }.into());
+            }
+            log::warn!("[pen] Set pen_auto_range = true to use the observed range instead");
+        }
+
+        pacer.wait(timed.time);
+
+        click_mouse(mouse.as_ref(), &mut frame)?;
+
+        let pressure = contact.pressure;
+        smoothing.observe_pressure(&frame);
+        let mut position = frame
+            .position
+            .map(|position| smoothing.apply(position, now_touching));
+        let mut tilt = frame.tilt;
+        let mut batch = frame.events;
+        // BTN_TOUCH goes out from `now_touching` below, whatever the contact source
+        batch.retain(|e| e.event_type().raw() != EV_KEY || e.raw_code() != btn_touch_code);
+        let has_keys = batch.iter().any(|e| e.event_type().raw() == EV_KEY);
+
+        let mut output = position.map(|(x, y)| {
+            let (out_x, out_y) = orientation.transform_pen(
+                x, y,
+                device_profile.pen_x_max,
+                device_profile.pen_y_max,
+            );
+            exit_region((out_x.saturating_add(offset.0), out_y.saturating_add(offset.1)), area.region(), config.region_exit)
+        });
+
+        // region_exit = "lift": leaving the output area ends the stroke
+        if now_touching && output.is_some_and(|(_, lift)| lift) {
+            now_touching = false;
+            for e in batch.iter_mut().filter(|e| e.event_type().raw() == EV_ABS && e.raw_code() == ABS_PRESSURE) {
+                *e = InputEvent::new(EventType::from_raw(EV_ABS), ABS_PRESSURE, 0);
+            }
+        }
+        for e in batch.iter_mut().filter(|e| e.event_type().raw() == EV_ABS && e.raw_code() == ABS_PRESSURE) {
+            *e = InputEvent::new(EventType::from_raw(EV_ABS), ABS_PRESSURE, band.apply(e.raw_value()));
+        }
+
+        // pen_hover: keep the cursor still above the screen, or drop hover entirely
+        if config.pen_hover != PenHover::Move && !now_touching {
+            (position, output, tilt) = (None, None, None);
+        }
+        if config.pen_hover == PenHover::Ignore {
+            if !now_touching && !touch_down {
+                update_palm_state(&palm, false);
+                continue;
+            }
+            batch.retain(|e| !is_proximity_event(e));
+            if now_touching != touch_down {
+                batch.insert(0, key_event(Key::BTN_TOOL_PEN.raw(), now_touching as i32));
+            }
+        }
+
+        // Emit the transformed position
+        if let Some(((out_x, out_y), _)) = output {
+            batch.insert(0, InputEvent::new(EventType::from_raw(EV_ABS), Abs::X.raw(), out_x));
+            batch.insert(1, InputEvent::new(EventType::from_raw(EV_ABS), Abs::Y.raw(), out_y));
+        }
+
+        // Transform and emit tilt events
+        if let Some((tx, ty)) = tilt {
+            let (out_tx, out_ty) = orientation.transform_tilt(tx, ty);
+            batch.insert(0, InputEvent::new(EventType::from_raw(EV_ABS), Abs::TILT_X.raw(), out_tx));
+            batch.insert(1, InputEvent::new(EventType::from_raw(EV_ABS), Abs::TILT_Y.raw(), out_ty));
+        }
+
+        update_palm_state(&palm, now_touching);
+
+        // Thin out small moves (min_position_delta) and keep to max_event_rate, but never
+        // drop contact or button changes. A small move only loses its position.
+        let transition = has_keys || now_touching != touch_down;
+        let small_move = !transition && is_small_move(last_sent, position, config.min_position_delta);
+        if small_move {
+            batch.retain(|e| e.event_type().raw() != EV_ABS || (e.raw_code() != ABS_X && e.raw_code() != ABS_Y));
+            if batch.iter().all(|e| e.event_type().raw() == EV_SYN) {
+                continue;
+            }
+        }
+        if transition {
+            throttle::charge(batch.len() + 2);
+        } else if !throttle::admit(Stream::Pen, batch.len() + 1) {
+            status::PEN.throttled();
+            continue;
+        }
+        if position.is_some() && !small_move {
+            last_sent = position;
+        }
+
+        if now_touching != touch_down {
+            let key_ev = key_event(btn_touch_code, if now_touching { 1 } else { 0 });
+            batch.insert(0, key_ev);
+        }
+        touch_down = now_touching;
+
+        if frame_count == 0 {
+            log::info!("[pen] Events flowing");
+        }
+        frame_count += 1;
+        status::PEN.frame(now_touching as i32, false);
+
+        if now_touching {
+            if let Some(ready) = tail.push(pressure, batch) {
+                sink.write_frame(&ready)?;
+            }
+        } else {
+            // Never delay the release: flush (or drop) held samples and lift now
+            for held in tail.finish_stroke() {
+                sink.write_frame(&held)?;
+            }
+            sink.write_frame(&batch)?;
+        }
+
+        if frame_count.is_multiple_of(500) {
+            log::debug!("[pen] Frames: {} ({})", frame_count, status::PEN.frame_counts());
+        }
+    }
+}
+
+/// Linear map of the tablet's pressure range onto `pen_pressure_min..=pen_pressure_max`.
+/// No contact stays 0 and the lightest touch stays above it, so "pressure > 0"
+/// still means touching for everything downstream.
+struct PressureBand {
+    source_max: i32,
+    min: i32,
+    max: i32,
+}
+
+impl PressureBand {
+    fn new(source_max: i32, min: i32, max: i32) -> Self {
+        Self {
+            source_max: source_max.max(2),
+            min: min.max(1),
+            max,
+        }
+    }
+
+    fn apply(&self, pressure: i32) -> i32 {
+        if pressure <= 0 {
+            return 0;
+        }
+        let t = (pressure.min(self.source_max) - 1) as i64;
+        let span = (self.max - self.min) as i64;
+        (self.min as i64 + t * span / (self.source_max - 1) as i64) as i32
+    }
+}
+
+/// Exponential smoothing of the pen position, weighted by pressure
+/// (`pen_smoothing_low`/`pen_smoothing_high`).
+///
+/// The weight of a new sample is interpolated linearly between the two
+/// configured values over the pressure range, so faint strokes are filtered
+/// more than firm ones. Only applies while touching; the filter restarts at
+/// every pen-down so hover movement never drags the stroke start.
+///
+/// The smoothed position is in raw device coordinates, before orientation.
+/// Config is fixed for the lifetime of a loop; if it ever becomes reloadable,
+/// a reload must call `reset` so a stroke in progress doesn't jump.
+struct PressureSmoothing {
+    alpha_low: f64,
+    alpha_high: f64,
+    pressure_max: i32,
+    /// Last reported pressure; frames only carry it when it changed.
+    pressure: i32,
+    smoothed: Option<(f64, f64)>,
+}
+
+impl PressureSmoothing {
+    fn new(alpha_low: f64, alpha_high: f64, pressure_max: i32) -> Self {
+        Self {
+            alpha_low,
+            alpha_high,
+            pressure_max,
+            pressure: 0,
+            smoothed: None,
+        }
+    }
+
+    /// Forget the filter state; the next sample starts a fresh stroke.
+    fn reset(&mut self) {
+        self.smoothed = None;
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.alpha_low < 1.0 || self.alpha_high < 1.0
+    }
+
+    fn observe_pressure(&mut self, frame: &PenFrame) {
+        if let Some(pressure) = frame.abs_value(ABS_PRESSURE) {
+            self.pressure = pressure;
+        }
+    }
+
+    /// Weight of a new sample at `pressure`.
+    fn alpha(&self, pressure: i32) -> f64 {
+        let t = (pressure as f64 / self.pressure_max.max(1) as f64).clamp(0.0, 1.0);
+        self.alpha_low + (self.alpha_high - self.alpha_low) * t
+    }
+
+    fn apply(&mut self, (x, y): (i32, i32), touching: bool) -> (i32, i32) {
+        if !self.is_enabled() || !touching {
+            self.reset();
+            return (x, y);
+        }
+
+        let alpha = self.alpha(self.pressure);
+        let (sx, sy) = match self.smoothed {
+            Some((sx, sy)) => (sx + alpha * (x as f64 - sx), sy + alpha * (y as f64 - sy)),
+            None => (x as f64, y as f64),
+        };
+        self.smoothed = Some((sx, sy));
+        (sx.round() as i32, sy.round() as i32)
+    }
+}
+
+/// Holds back the last `len` frames of a stroke (`pen_tail_trim`).
+///
+/// If pressure fell off sharply over the held frames when the pen lifts,
+/// they are the low-pressure hook at the end of the stroke and get dropped.
+/// Frames carrying key events are always flushed so no press is lost.
+struct TailTrim {
+    len: usize,
+    held: VecDeque<(i32, Vec<InputEvent>)>,
+    /// Pressure of the last frame emitted before the held ones.
+    last_pressure: i32,
+}
+
+impl TailTrim {
+    fn new(len: usize) -> Self {
+        Self {
+            len,
+            held: VecDeque::with_capacity(len + 1),
+            last_pressure: 0,
+        }
+    }
+
+    /// Add a frame of an ongoing stroke; returns the frame to emit now, if any.
+    fn push(&mut self, pressure: i32, batch: Vec<InputEvent>) -> Option<Vec<InputEvent>> {
+        if self.len == 0 {
+            return Some(batch);
+        }
+        self.held.push_back((pressure, batch));
+        if self.held.len() <= self.len {
+            return None;
+        }
+        let (pressure, batch) = self.held.pop_front()?;
+        self.last_pressure = pressure;
+        Some(batch)
+    }
+
+    /// The pen lifted: return the held frames that should still be emitted.
+    fn finish_stroke(&mut self) -> Vec<Vec<InputEvent>> {
+        let pressures: Vec<i32> = self.held.iter().map(|(p, _)| *p).collect();
+        let has_keys = self
+            .held
+            .iter()
+            .any(|(_, batch)| batch.iter().any(|e| e.event_type().raw() == EV_KEY));
+        let held: Vec<Vec<InputEvent>> = self.held.drain(..).map(|(_, batch)| batch).collect();
+        let before = std::mem::take(&mut self.last_pressure);
+
+        if !has_keys && is_rapid_release(before, &pressures) {
+            log::trace!("[pen] Dropping {} tail samples", held.len());
+            return Vec::new();
+        }
+        held
+    }
+}
+
+/// Pressure kept falling over `held` and ended below half of `before`.
+fn is_rapid_release(before: i32, held: &[i32]) -> bool {
+    let Some(&last) = held.last() else {
+        return false;
+    };
+    let mut prev = before;
+    for &p in held {
+        if p > prev {
+            return false;
+        }
+        prev = p;
+    }
+    last * 2 < before
+}
+
+/// Raw pen values collected between two SYN_REPORTs.
+///
+/// Position and tilt are reported when either of their axes changed in the
+/// frame, the other one keeping its last value; they are kept out of
+/// `events` so callers can transform them first.
+pub struct PenFrame {
+    pub position: Option<(i32, i32)>,
+    pub tilt: Option<(i32, i32)>,
+    /// All other events of the frame in arrival order, ending with SYN_REPORT.
+    pub events: Vec<InputEvent>,
+}
+
+impl PenFrame {
+    /// Last value reported for an ABS axis in this frame.
+    pub fn abs_value(&self, code: u16) -> Option<i32> {
+        self.events
+            .iter()
+            .rfind(|e| e.event_type().raw() == EV_ABS && e.raw_code() == code)
+            .map(|e| e.raw_value())
+    }
+
+    /// Last value reported for a key in this frame.
+    pub fn key_value(&self, code: u16) -> Option<i32> {
+        self.events
+            .iter()
+            .rfind(|e| e.event_type().raw() == EV_KEY && e.raw_code() == code)
+            .map(|e| e.raw_value())
+    }
+}
+
+/// Groups raw pen events into frames, shared by the forwarder and `dump pen --frames`.
+///
+/// evdev only reports the axes that changed, so the last value of each is
+/// kept across frames.
+#[derive(Default)]
+pub struct PenFrameAssembler {
+    events: Vec<InputEvent>,
+    x: Option<i32>,
+    y: Option<i32>,
+    tilt_x: Option<i32>,
+    tilt_y: Option<i32>,
+    /// Whether the current frame reported a position or tilt axis.
+    moved: bool,
+    tilted: bool,
+}
+
+impl PenFrameAssembler {
+    pub fn new() -> Self {
+        Self {
+            events: Vec::with_capacity(32),
+            ..Self::default()
+        }
+    }
+
+    /// Feed one event. Returns the completed frame on SYN_REPORT.
+    pub fn push(&mut self, ev: InputEvent) -> Option<PenFrame> {
         let ty = ev.event_type().raw();
         let code = ev.raw_code();
         let value = ev.raw_value();
@@ -85,84 +833,659 @@ pub fn run_pen(
         if ty == EV_ABS {
             match code {
                 ABS_X => {
-                    pending_x = Some(value);
-                    continue;
+                    (self.x, self.moved) = (Some(value), true);
+                    return None;
                 }
                 ABS_Y => {
-                    pending_y = Some(value);
-                    continue;
+                    (self.y, self.moved) = (Some(value), true);
+                    return None;
                 }
                 ABS_TILT_X => {
-                    pending_tilt_x = Some(value);
-                    continue;
+                    (self.tilt_x, self.tilted) = (Some(value), true);
+                    return None;
                 }
                 ABS_TILT_Y => {
-                    pending_tilt_y = Some(value);
-                    continue;
+                    (self.tilt_y, self.tilted) = (Some(value), true);
+                    return None;
                 }
                 _ => {}
             }
         }
 
-        batch.push(ev);
+        self.events.push(ev);
 
         if ty != EV_SYN || code != SYN_REPORT {
-            continue;
+            return None;
         }
 
-        // Transform and emit position events
-        if let (Some(x), Some(y)) = (pending_x.take(), pending_y.take()) {
-            let (out_x, out_y) = orientation.transform_pen(
-                x, y,
-                device_profile.pen_x_max,
-                device_profile.pen_y_max,
-            );
-            batch.insert(0, InputEvent::new(evdevil::event::EventType::from_raw(EV_ABS), Abs::X.raw(), out_x));
-            batch.insert(1, InputEvent::new(evdevil::event::EventType::from_raw(EV_ABS), Abs::Y.raw(), out_y));
-        }
+        let position = std::mem::take(&mut self.moved).then(|| self.x.zip(self.y)).flatten();
+        let tilt = std::mem::take(&mut self.tilted).then(|| self.tilt_x.zip(self.tilt_y)).flatten();
 
-        // Transform and emit tilt events
-        if let (Some(tx), Some(ty)) = (pending_tilt_x.take(), pending_tilt_y.take()) {
-            let (out_tx, out_ty) = orientation.transform_tilt(tx, ty);
-            batch.insert(0, InputEvent::new(evdevil::event::EventType::from_raw(EV_ABS), Abs::TILT_X.raw(), out_tx));
-            batch.insert(1, InputEvent::new(evdevil::event::EventType::from_raw(EV_ABS), Abs::TILT_Y.raw(), out_ty));
-        }
+        Some(PenFrame {
+            position,
+            tilt,
+            events: std::mem::replace(&mut self.events, Vec::with_capacity(32)),
+        })
+    }
+}
 
-        let pressure = batch
-            .iter()
-            .rfind(|e| e.event_type().raw() == EV_ABS && e.raw_code() == ABS_PRESSURE)
-            .map(|e| e.raw_value())
-            .unwrap_or(0);
+/// Decoded pen state as seen by the forwarder, updated frame by frame.
+#[derive(Debug, Clone, Copy)]
+pub struct PenState {
+    pub x: i32,
+    pub y: i32,
+    pub pressure: i32,
+    pub distance: i32,
+    pub tilt_x: i32,
+    pub tilt_y: i32,
+    /// Contact as decided by `pen_contact_source`.
+    pub touching: bool,
+    contact: ContactTracker,
+}
 
-        let now_touching = pressure > 0;
-        update_palm_state(&palm, now_touching);
+impl PenState {
+    pub fn new(source: PenContactSource) -> Self {
+        Self {
+            x: 0,
+            y: 0,
+            pressure: 0,
+            distance: 0,
+            tilt_x: 0,
+            tilt_y: 0,
+            touching: false,
+            contact: ContactTracker::new(source),
+        }
+    }
 
-        if now_touching != touch_down {
-            let key_ev = key_event(btn_touch_code, if now_touching { 1 } else { 0 });
-            batch.insert(0, key_ev);
+    pub fn apply(&mut self, frame: &PenFrame) {
+        if let Some((x, y)) = frame.position {
+            self.x = x;
+            self.y = y;
         }
-        touch_down = now_touching;
+        if let Some((tx, ty)) = frame.tilt {
+            self.tilt_x = tx;
+            self.tilt_y = ty;
+        }
+        if let Some(distance) = frame.abs_value(ABS_DISTANCE) {
+            self.distance = distance;
+        }
+        if let Some(pressure) = frame.abs_value(ABS_PRESSURE) {
+            self.pressure = pressure;
+        }
+        self.touching = self.contact.update(frame);
+    }
+}
 
-        if frame_count == 0 {
-            log::info!("Pen events flowing");
+/// What happens to the pen outside the output area (`region_exit`), which
+/// `offset_x`/`offset_y` can push it into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RegionExit {
+    /// Stick to the nearest edge.
+    #[default]
+    Clamp,
+    /// Stick to the edge and lift the pen, so overshooting doesn't draw along the border.
+    Lift,
+    /// Forward the coordinates unclamped and leave it to the consumer.
+    Passthrough,
+}
+
+impl fmt::Display for RegionExit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegionExit::Clamp => write!(f, "clamp"),
+            RegionExit::Lift => write!(f, "lift"),
+            RegionExit::Passthrough => write!(f, "passthrough"),
         }
-        frame_count += 1;
+    }
+}
 
-        uinput.write(&batch)?;
-        batch.clear();
+impl FromStr for RegionExit {
+    type Err = String;
 
-        if frame_count.is_multiple_of(500) {
-            log::debug!("Pen frames forwarded: {}", frame_count);
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "clamp" => Ok(RegionExit::Clamp),
+            "lift" => Ok(RegionExit::Lift),
+            "passthrough" | "pass-through" => Ok(RegionExit::Passthrough),
+            _ => Err(format!(
+                "Invalid region exit mode '{}'. Valid values: clamp, lift, passthrough",
+                s
+            )),
         }
     }
 }
 
+/// Apply `mode` to an output position; the flag says whether to lift the pen.
+/// `region` is the tablet's rectangle, which `fit_screen` puts inside a
+/// larger output range.
+fn exit_region((x, y): (i32, i32), region: ((i32, i32), (i32, i32)), mode: RegionExit) -> ((i32, i32), bool) {
+    let ((x_min, y_min), (x_max, y_max)) = region;
+    let inside = (x_min..=x_max).contains(&x) && (y_min..=y_max).contains(&y);
+    let clamped = (x.clamp(x_min, x_max), y.clamp(y_min, y_max));
+    match mode {
+        _ if inside => ((x, y), false),
+        RegionExit::Clamp => (clamped, false),
+        RegionExit::Lift => (clamped, true),
+        RegionExit::Passthrough => ((x, y), false),
+    }
+}
+
+/// With `pen_button_click`, pass the barrel button to the shared mouse as
+/// its left button and take it out of the pen's frame.
+fn click_mouse(mouse: Option<&SharedMouse>, frame: &mut PenFrame) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let Some(mouse) = mouse else {
+        return Ok(());
+    };
+    if let Some(value) = frame.key_value(BTN_STYLUS) {
+        mouse.button(Key::BTN_LEFT, value != 0)?;
+    }
+    frame.events.retain(|e| e.event_type().raw() != EV_KEY || e.raw_code() != BTN_STYLUS);
+    Ok(())
+}
+
+/// Proximity and distance reports, which `pen_hover = "ignore"` drops.
+fn is_proximity_event(e: &InputEvent) -> bool {
+    match e.event_type().raw() {
+        EV_KEY => e.raw_code() == Key::BTN_TOOL_PEN.raw(),
+        EV_ABS => e.raw_code() == ABS_DISTANCE,
+        _ => false,
+    }
+}
+
+/// `offset_x`/`offset_y` are in touch units (the touch digitizer's own
+/// coordinates, after orientation), so rescale them to the pen's output
+/// range, which spans the same screen with more units.
+fn pen_offset(config: &Config, device: &DeviceProfile) -> (i32, i32) {
+    let orientation = config.pen_orientation;
+    let (pen_x, pen_y) = orientation.pen_output_dimensions(device.pen_x_max, device.pen_y_max);
+    let (touch_x, touch_y) = orientation.touch_output_dimensions(device.touch_x_max, device.touch_y_max);
+    let scale = |offset: i32, pen: i32, touch: i32| (offset as i64 * pen as i64 / touch.max(1) as i64) as i32;
+    (
+        scale(config.offset_x, pen_x, touch_x),
+        scale(config.offset_y, pen_y, touch_y),
+    )
+}
+
+/// A frame without a position counts as not having moved.
+fn is_small_move(last_sent: Option<(i32, i32)>, position: Option<(i32, i32)>, min_delta: i32) -> bool {
+    if min_delta <= 0 {
+        return false;
+    }
+    match (last_sent, position) {
+        (Some(last), Some(position)) => below_min_delta(last, position, min_delta),
+        (Some(_), None) => true,
+        (None, _) => false,
+    }
+}
+
 fn update_palm_state(palm: &Option<SharedPalmState>, now_touching: bool) {
     let Some(palm_state) = palm else { return };
     let Ok(mut state) = palm_state.lock() else { return };
 
+    let now = Instant::now();
+    // Only the lift starts the grace window; hover frames count as activity
+    if state.pen_down && !now_touching {
+        state.last_pen_up = Some(now);
+    }
     state.pen_down = now_touching;
-    if !now_touching {
-        state.last_pen_up = Some(Instant::now());
+    state.last_pen_activity = Some(now);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::RM2;
+    use crate::input::event::raw_event;
+    use crate::input::{RecordingSink, VirtualMouse};
+    use crate::orientation::Orientation;
+
+    fn pen_frame(x: i32, y: i32, pressure: i32) -> Vec<Vec<u8>> {
+        vec![
+            raw_event(EV_ABS, ABS_X, x),
+            raw_event(EV_ABS, ABS_Y, y),
+            raw_event(EV_ABS, ABS_PRESSURE, pressure),
+            raw_event(EV_SYN, SYN_REPORT, 0),
+        ]
+    }
+
+    #[test]
+    fn test_pen_min_position_delta() {
+        let mut stream = vec![raw_event(EV_KEY, Key::BTN_TOOL_PEN.raw(), 1)];
+        stream.extend(pen_frame(1000, 1000, 0));
+        stream.extend(pen_frame(1004, 1000, 0));
+        stream.extend(pen_frame(1004, 1000, 500));
+        stream.extend(pen_frame(1006, 1000, 600));
+        stream.extend(pen_frame(1015, 1000, 600));
+        stream.extend(pen_frame(1016, 1000, 0));
+        let stream = stream.concat();
+
+        let mut config = Config::defaults(&RM2);
+        config.min_position_delta = 10;
+
+        let mut sink = RecordingSink::default();
+        let _ = run_event_loop(&mut stream.as_slice(), &mut sink, &RM2, &config, None, None);
+
+        // Every frame goes out, but the small moves to 1004 and 1006 without their position
+        let touch = |frame: &Vec<InputEvent>| {
+            frame
+                .iter()
+                .find(|e| e.event_type().raw() == EV_KEY && e.raw_code() == BTN_TOUCH)
+                .map(|e| e.raw_value())
+        };
+        let touches: Vec<Option<i32>> = sink.frames.iter().map(touch).collect();
+        assert_eq!(touches, [None, None, Some(1), None, None, Some(0)]);
+        let positioned: Vec<bool> = sink.frames.iter().map(|f| has_code(f, EV_ABS, ABS_X)).collect();
+        assert_eq!(positioned, [true, false, true, false, true, true]);
+        assert!(has_code(&sink.frames[3], EV_ABS, ABS_PRESSURE));
+    }
+
+    fn replay_hover_then_draw(hover: PenHover) -> Vec<Vec<InputEvent>> {
+        let mut stream = vec![raw_event(EV_KEY, Key::BTN_TOOL_PEN.raw(), 1)];
+        stream.extend(pen_frame(1000, 1000, 0));
+        stream.extend(pen_frame(1100, 1000, 0));
+        stream.extend(pen_frame(1200, 1000, 500));
+        stream.extend(pen_frame(1300, 1000, 0));
+        let stream = stream.concat();
+
+        let mut config = Config::defaults(&RM2);
+        config.pen_hover = hover;
+        let mut sink = RecordingSink::default();
+        let _ = run_event_loop(&mut stream.as_slice(), &mut sink, &RM2, &config, None, None);
+        sink.frames
+    }
+
+    fn has_code(frame: &[InputEvent], ty: u16, code: u16) -> bool {
+        frame.iter().any(|e| e.event_type().raw() == ty && e.raw_code() == code)
+    }
+
+    #[test]
+    fn test_pen_hover_modes() {
+        let moving = replay_hover_then_draw(PenHover::Move);
+        assert_eq!(moving.len(), 4);
+        assert!(moving.iter().all(|f| has_code(f, EV_ABS, ABS_X)));
+
+        // Hover frames still go out, without a position
+        let proximity = replay_hover_then_draw(PenHover::ProximityOnly);
+        let positioned: Vec<bool> = proximity.iter().map(|f| has_code(f, EV_ABS, ABS_X)).collect();
+        assert_eq!(positioned, [false, false, true, false]);
+        assert!(has_code(&proximity[0], EV_KEY, Key::BTN_TOOL_PEN.raw()));
+
+        // Only the stroke: the tool comes and goes with the contact
+        let ignored = replay_hover_then_draw(PenHover::Ignore);
+        assert_eq!(ignored.len(), 2);
+        let tool = |frame: &[InputEvent]| {
+            frame
+                .iter()
+                .find(|e| e.event_type().raw() == EV_KEY && e.raw_code() == Key::BTN_TOOL_PEN.raw())
+                .map(|e| e.raw_value())
+        };
+        assert_eq!(tool(&ignored[0]), Some(1));
+        assert_eq!(tool(&ignored[1]), Some(0));
+    }
+
+    #[test]
+    fn test_warmup_keeps_key_changes() {
+        let mut stream = vec![raw_event(EV_KEY, Key::BTN_TOOL_PEN.raw(), 1)];
+        stream.extend(pen_frame(1000, 1000, 0));
+        stream.push(raw_event(EV_KEY, BTN_STYLUS, 1));
+        stream.extend(pen_frame(1100, 1000, 500));
+        stream.extend(pen_frame(1200, 1000, 600));
+        let stream = stream.concat();
+
+        let mut config = Config::defaults(&RM2);
+        config.warmup_discard_ms = 60_000;
+        let mut sink = RecordingSink::default();
+        let _ = run_event_loop(&mut stream.as_slice(), &mut sink, &RM2, &config, None, None);
+
+        // The tool, then the button with the contact; the plain move is dropped
+        assert_eq!(sink.frames.len(), 2);
+        assert!(sink.frames.iter().flatten().all(|e| e.event_type().raw() == EV_KEY));
+        assert!(has_code(&sink.frames[0], EV_KEY, Key::BTN_TOOL_PEN.raw()));
+        assert!(has_code(&sink.frames[1], EV_KEY, BTN_STYLUS));
+        assert!(has_code(&sink.frames[1], EV_KEY, BTN_TOUCH));
+    }
+
+    #[test]
+    fn test_btn_touch_follows_contact_source() {
+        let mut stream = vec![raw_event(EV_KEY, Key::BTN_TOOL_PEN.raw(), 1)];
+        stream.push(raw_event(EV_KEY, BTN_TOUCH, 1));
+        stream.extend(pen_frame(1000, 1000, 0));
+        stream.extend(pen_frame(1100, 1000, 500));
+        stream.push(raw_event(EV_KEY, BTN_TOUCH, 0));
+        stream.extend(pen_frame(1200, 1000, 400));
+        stream.extend(pen_frame(1300, 1000, 0));
+        let stream = stream.concat();
+
+        let touches = |source| {
+            let mut config = Config::defaults(&RM2);
+            config.pen_contact_source = source;
+            let mut sink = RecordingSink::default();
+            let _ = run_event_loop(&mut stream.as_slice(), &mut sink, &RM2, &config, None, None);
+            sink.frames
+                .iter()
+                .map(|frame| {
+                    frame
+                        .iter()
+                        .filter(|e| e.event_type().raw() == EV_KEY && e.raw_code() == BTN_TOUCH)
+                        .map(|e| e.raw_value())
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // One BTN_TOUCH per change of contact, never the device's own when it disagrees
+        let none = Vec::new;
+        assert_eq!(touches(PenContactSource::Pressure), [none(), vec![1], none(), vec![0]]);
+        assert_eq!(touches(PenContactSource::BtnTouch), [vec![1], none(), vec![0], none()]);
+    }
+
+    #[test]
+    fn test_offset_moves_pen_output() {
+        let stream = [vec![raw_event(EV_KEY, Key::BTN_TOOL_PEN.raw(), 1)], pen_frame(5000, 6000, 500)]
+            .concat()
+            .concat();
+        let position = |offset_x: i32, offset_y: i32| {
+            let mut config = Config::defaults(&RM2);
+            (config.offset_x, config.offset_y) = (offset_x, offset_y);
+            let mut sink = RecordingSink::default();
+            let _ = run_event_loop(&mut stream.as_slice(), &mut sink, &RM2, &config, None, None);
+            let value = |code| {
+                let event = sink.frames[0].iter().find(|e| e.event_type().raw() == EV_ABS && e.raw_code() == code);
+                event.unwrap().raw_value()
+            };
+            (value(ABS_X), value(ABS_Y))
+        };
+
+        let config = Config::defaults(&RM2);
+        let (pen_w, pen_h) = config.pen_orientation.pen_output_dimensions(RM2.pen_x_max, RM2.pen_y_max);
+        let (touch_w, touch_h) = config.pen_orientation.touch_output_dimensions(RM2.touch_x_max, RM2.touch_y_max);
+        let (x, y) = position(0, 0);
+        // A tenth of the touch range is a tenth of the pen range
+        let (moved_x, moved_y) = position(touch_w / 10, -touch_h / 10);
+        assert!((moved_x - x - pen_w / 10).abs() <= 10, "{} -> {}", x, moved_x);
+        assert!((moved_y - y + pen_h / 10).abs() <= 10, "{} -> {}", y, moved_y);
+    }
+
+    #[test]
+    fn test_region_exit_modes() {
+        let region = ((0, 0), (1000, 500));
+        assert_eq!(exit_region((400, 200), region, RegionExit::Lift), ((400, 200), false));
+        assert_eq!(exit_region((1200, -10), region, RegionExit::Clamp), ((1000, 0), false));
+        assert_eq!(exit_region((1200, -10), region, RegionExit::Lift), ((1000, 0), true));
+        assert_eq!(exit_region((1200, -10), region, RegionExit::Passthrough), ((1200, -10), false));
+        // fit_screen: the bars beside the tablet are outside it
+        let fitted = screen::fit((1000, 500), Some((1000, 1000))).region();
+        assert_eq!(exit_region((400, 100), fitted, RegionExit::Lift), ((400, 250), true));
+        assert_eq!(exit_region((400, 300), fitted, RegionExit::Lift), ((400, 300), false));
+    }
+
+    #[test]
+    fn test_region_exit_lift_ends_stroke() {
+        let mut config = Config::defaults(&RM2);
+        config.region_exit = RegionExit::Lift;
+        // Shift right by half the output width, so the right half of the tablet falls off it
+        let (touch_w, _) = config.pen_orientation.touch_output_dimensions(RM2.touch_x_max, RM2.touch_y_max);
+        config.offset_x = touch_w / 2;
+
+        let (x_inside, x_outside) = (100, RM2.pen_x_max * 3 / 4);
+        let mut stream = vec![raw_event(EV_KEY, Key::BTN_TOOL_PEN.raw(), 1)];
+        stream.extend(pen_frame(x_inside, 1000, 800));
+        stream.extend(pen_frame(x_outside, 1000, 800));
+        let stream = stream.concat();
+
+        let mut sink = RecordingSink::default();
+        let _ = run_event_loop(&mut stream.as_slice(), &mut sink, &RM2, &config, None, None);
+
+        let value = |frame: &[InputEvent], ty: u16, code: u16| {
+            frame
+                .iter()
+                .find(|e| e.event_type().raw() == ty && e.raw_code() == code)
+                .map(|e| e.raw_value())
+        };
+        assert_eq!(sink.frames.len(), 2);
+        assert_eq!(value(&sink.frames[0], EV_KEY, BTN_TOUCH), Some(1));
+        assert_eq!(value(&sink.frames[1], EV_KEY, BTN_TOUCH), Some(0));
+        assert_eq!(value(&sink.frames[1], EV_ABS, ABS_PRESSURE), Some(0));
+        assert_eq!(value(&sink.frames[1], EV_ABS, ABS_X), Some(RM2.pen_x_max));
+    }
+
+    #[test]
+    fn test_small_move_without_position() {
+        assert!(!is_small_move(Some((0, 0)), None, 0));
+        assert!(is_small_move(Some((0, 0)), None, 5));
+        assert!(!is_small_move(None, Some((1, 1)), 5));
+        assert!(!is_small_move(Some((0, 0)), Some((3, 4)), 5));
+    }
+
+    #[test]
+    fn test_pressure_band_edges() {
+        let band = PressureBand::new(4095, 0, 1023);
+        assert_eq!(band.apply(0), 0);
+        assert_eq!(band.apply(1), 1);
+        assert_eq!(band.apply(4095), 1023);
+        assert_eq!(band.apply(9000), 1023);
+        assert_eq!(band.apply(-3), 0);
+
+        let band = PressureBand::new(4095, 200, 1023);
+        assert_eq!(band.apply(0), 0);
+        assert_eq!(band.apply(1), 200);
+        assert_eq!(band.apply(2048), 611);
+        assert_eq!(band.apply(4095), 1023);
+
+        // The defaults leave pressure untouched
+        let band = PressureBand::new(4095, 0, 4095);
+        assert!([0, 1, 2, 1000, 4094, 4095].iter().all(|&p| band.apply(p) == p));
+    }
+
+    #[test]
+    fn test_smoothing_alpha_interpolation() {
+        let smoothing = PressureSmoothing::new(0.2, 1.0, 4000);
+        assert!((smoothing.alpha(0) - 0.2).abs() < 1e-9);
+        assert!((smoothing.alpha(2000) - 0.6).abs() < 1e-9);
+        assert!((smoothing.alpha(4000) - 1.0).abs() < 1e-9);
+        // Out-of-range pressure is clamped to the configured points
+        assert!((smoothing.alpha(-5) - 0.2).abs() < 1e-9);
+        assert!((smoothing.alpha(9000) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_smoothing_restarts_at_pen_down() {
+        let mut smoothing = PressureSmoothing::new(0.5, 0.5, 4000);
+        assert_eq!(smoothing.apply((100, 100), true), (100, 100));
+        assert_eq!(smoothing.apply((200, 100), true), (150, 100));
+        // Hover passes through and resets the filter
+        assert_eq!(smoothing.apply((500, 500), false), (500, 500));
+        assert_eq!(smoothing.apply((300, 300), true), (300, 300));
+
+        // A reset mid-stroke (e.g. after a config change) starts clean
+        assert_eq!(smoothing.apply((400, 300), true), (350, 300));
+        smoothing.reset();
+        assert_eq!(smoothing.apply((800, 300), true), (800, 300));
+    }
+
+    #[test]
+    fn test_pen_uses_its_own_orientation() {
+        let mut stream = vec![raw_event(EV_KEY, Key::BTN_TOOL_PEN.raw(), 1)];
+        stream.extend(pen_frame(1000, 2000, 0));
+        let stream = stream.concat();
+
+        let mut config = Config::defaults(&RM2);
+        config.pen_orientation = Orientation::Portrait;
+        config.touch_orientation = Orientation::Inverted;
+        let mut sink = RecordingSink::default();
+        let _ = run_event_loop(&mut stream.as_slice(), &mut sink, &RM2, &config, None, None);
+
+        let value = |code: u16| {
+            sink.frames[0]
+                .iter()
+                .find(|e| e.event_type().raw() == EV_ABS && e.raw_code() == code)
+                .map(|e| e.raw_value())
+        };
+        let expected = Orientation::Portrait.transform_pen(1000, 2000, RM2.pen_x_max, RM2.pen_y_max);
+        assert_eq!((value(ABS_X), value(ABS_Y)), (Some(expected.0), Some(expected.1)));
+    }
+
+    #[test]
+    fn test_relative_pen_deltas() {
+        let abs = |code: u16, value: i32| InputEvent::new(EventType::from_raw(EV_ABS), code, value);
+        let mut pen = RelativePen::new(RecordingSink::default(), 10.0);
+
+        pen.write_frame(&[abs(ABS_X, 1000), abs(ABS_Y, 1000)]).unwrap();
+        pen.write_frame(&[abs(ABS_X, 1050), abs(ABS_Y, 980)]).unwrap();
+        // Half a count is carried over instead of lost
+        pen.write_frame(&[abs(ABS_X, 1055)]).unwrap();
+        pen.write_frame(&[key_event(BTN_TOUCH, 1), abs(ABS_X, 1060)]).unwrap();
+        pen.write_frame(&[key_event(Key::BTN_STYLUS.raw(), 1)]).unwrap();
+        // Coming back into range elsewhere doesn't jump
+        pen.write_frame(&[key_event(Key::BTN_TOOL_PEN.raw(), 0), key_event(BTN_TOUCH, 0)]).unwrap();
+        pen.write_frame(&[key_event(Key::BTN_TOOL_PEN.raw(), 1), abs(ABS_X, 9000), abs(ABS_Y, 9000)]).unwrap();
+
+        let frames: Vec<Vec<(u16, u16, i32)>> = pen
+            .sink
+            .frames
+            .iter()
+            .map(|f| f.iter().map(|e| (e.event_type().raw(), e.raw_code(), e.raw_value())).collect())
+            .collect();
+        let (rel_x, rel_y, left, right) = (Rel::X.raw(), Rel::Y.raw(), Key::BTN_LEFT.raw(), Key::BTN_RIGHT.raw());
+        assert_eq!(
+            frames,
+            vec![
+                vec![(EV_REL, rel_x, 5), (EV_REL, rel_y, -2)],
+                vec![(EV_KEY, left, 1), (EV_REL, rel_x, 1)],
+                vec![(EV_KEY, right, 1)],
+                vec![(EV_KEY, left, 0)],
+            ]
+        );
+    }
+
+    /// With `pen_button_click` in relative mode the side button is the
+    /// shared mouse's left click, not the pointer's right click.
+    #[test]
+    fn test_button_click_wins_over_relative_right_click() {
+        let stylus = |value| raw_event(EV_KEY, Key::BTN_STYLUS.raw(), value);
+        let mut stream = vec![raw_event(EV_KEY, Key::BTN_TOOL_PEN.raw(), 1)];
+        stream.extend(pen_frame(1000, 1000, 0));
+        stream.push(stylus(1));
+        stream.extend(pen_frame(1100, 1000, 0));
+        stream.push(stylus(0));
+        stream.extend(pen_frame(1200, 1000, 0));
+        let stream = stream.concat();
+
+        let mut config = Config::defaults(&RM2);
+        config.pen_mode = PenMode::Relative;
+        config.pen_button_click = true;
+        let (mouse, clicks) = VirtualMouse::recording();
+        let mut pen = RelativePen::new(RecordingSink::default(), 10.0);
+        let _ = run_event_loop(&mut stream.as_slice(), &mut pen, &RM2, &config, None, Some(mouse));
+
+        let left = |value| vec![key_event(Key::BTN_LEFT.raw(), value)];
+        assert_eq!(clicks.lock().unwrap().frames, vec![left(1), left(0)]);
+        assert!(pen.sink.frames.concat().iter().all(|e| e.event_type().raw() != EV_KEY));
+        assert!(pen.sink.frames.concat().iter().any(|e| e.event_type().raw() == EV_REL));
+    }
+
+    #[test]
+    fn test_frames_keep_unreported_axes() {
+        let abs = |code: u16, value: i32| InputEvent::new(EventType::from_raw(EV_ABS), code, value);
+        let syn = InputEvent::new(EventType::from_raw(EV_SYN), SYN_REPORT, 0);
+        let mut assembler = PenFrameAssembler::new();
+        let mut state = PenState::new(PenContactSource::Pressure);
+        let mut feed = |events: &[InputEvent]| {
+            let frame = events.iter().find_map(|&e| assembler.push(e)).unwrap();
+            state.apply(&frame);
+            (frame.position, state.pressure)
+        };
+
+        // Only X is reported before Y has ever been: no position yet
+        assert_eq!(feed(&[abs(ABS_X, 100), abs(ABS_PRESSURE, 500), syn]), (None, 500));
+        assert_eq!(feed(&[abs(ABS_Y, 200), syn]), (Some((100, 200)), 500));
+        // A horizontal move at steady pressure carries neither Y nor pressure
+        assert_eq!(feed(&[abs(ABS_X, 150), syn]), (Some((150, 200)), 500));
+        assert_eq!(feed(&[abs(ABS_Y, 250), syn]), (Some((150, 250)), 500));
+        // Nothing moved: no position, and the state keeps the last one
+        assert_eq!(feed(&[abs(ABS_PRESSURE, 0), syn]), (None, 0));
+        assert_eq!((state.x, state.y), (150, 250));
+    }
+
+    #[test]
+    fn test_contact_sources() {
+        let frame = |pressure: i32, btn_touch: Option<i32>| PenFrame {
+            position: None,
+            tilt: None,
+            events: [
+                Some(InputEvent::new(EventType::from_raw(EV_ABS), ABS_PRESSURE, pressure)),
+                btn_touch.map(|v| InputEvent::new(EventType::from_raw(EV_KEY), BTN_TOUCH, v)),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+        };
+        let run = |source, frames: &[PenFrame]| {
+            let mut tracker = ContactTracker::new(source);
+            frames.iter().map(|f| tracker.update(f)).collect::<Vec<_>>()
+        };
+        let frames = [
+            frame(100, None),
+            frame(0, None),
+            // BTN_TOUCH down without pressure, up while pressure is reported
+            frame(0, Some(1)),
+            frame(0, None),
+            frame(200, Some(0)),
+            frame(300, None),
+        ];
+
+        // Auto follows pressure until BTN_TOUCH first shows up, then BTN_TOUCH alone
+        assert_eq!(run(PenContactSource::Auto, &frames), [true, false, true, true, false, false]);
+        assert_eq!(run(PenContactSource::BtnTouch, &frames), [false, false, true, true, false, false]);
+        assert_eq!(run(PenContactSource::Pressure, &frames), [true, false, false, false, true, true]);
+    }
+
+    #[test]
+    fn test_tail_trim_keeps_steady_pressure_samples() {
+        let mut stream = vec![raw_event(EV_KEY, Key::BTN_TOOL_PEN.raw(), 1)];
+        stream.extend(pen_frame(1000, 1000, 1000));
+        for x in [1100, 1200] {
+            stream.push(raw_event(EV_ABS, ABS_X, x));
+            stream.push(raw_event(EV_SYN, SYN_REPORT, 0));
+        }
+        stream.extend(pen_frame(1300, 1000, 0));
+        let stream = stream.concat();
+
+        let mut config = Config::defaults(&RM2);
+        config.pen_tail_trim = 2;
+        let mut sink = RecordingSink::default();
+        let _ = run_event_loop(&mut stream.as_slice(), &mut sink, &RM2, &config, None, None);
+
+        // The held samples kept their pressure, so the lift isn't a rapid release
+        let moves = sink.frames.iter().filter(|f| f.iter().any(|e| e.event_type().raw() == EV_ABS && e.raw_code() == ABS_X));
+        assert_eq!(moves.count(), 4);
+    }
+
+    #[test]
+    fn test_pressure_contact_survives_frames_without_pressure() {
+        let mut stream = vec![raw_event(EV_KEY, Key::BTN_TOOL_PEN.raw(), 1)];
+        stream.extend(pen_frame(1000, 1000, 500));
+        // Moving at steady pressure: evdev leaves ABS_PRESSURE out
+        for x in [1100, 1200] {
+            stream.push(raw_event(EV_ABS, ABS_X, x));
+            stream.push(raw_event(EV_SYN, SYN_REPORT, 0));
+        }
+        stream.extend(pen_frame(1300, 1000, 0));
+        let stream = stream.concat();
+
+        let mut config = Config::defaults(&RM2);
+        config.pen_contact_source = PenContactSource::Pressure;
+        let mut sink = RecordingSink::default();
+        let _ = run_event_loop(&mut stream.as_slice(), &mut sink, &RM2, &config, None, None);
+
+        let btn_touch: Vec<i32> = sink
+            .frames
+            .iter()
+            .flatten()
+            .filter(|e| e.event_type().raw() == EV_KEY && e.raw_code() == BTN_TOUCH)
+            .map(|e| e.raw_value())
+            .collect();
+        assert_eq!(btn_touch, [1, 0], "one stroke, not broken up");
     }
 }
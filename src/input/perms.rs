@@ -0,0 +1,78 @@
+//! Group ownership of the created devices (`device_group`).
+//!
+//! The kernel creates uinput event nodes as root:root 0600 until udev's
+//! rules apply, and a setup without those rules leaves a compositor running
+//! as another user unable to open them. With a group set, each device's
+//! `/dev/input/eventN` is handed to it with mode 0660 once the device is
+//! up. Changing the group needs root (or CAP_CHOWN).
+
+use std::ffi::CString;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+use evdevil::uinput::UinputDevice;
+
+/// Group id for a group name or a numeric gid.
+pub fn resolve_group(name: &str) -> Result<u32, Box<dyn std::error::Error + Send + Sync>> {
+    if let Ok(gid) = name.parse::<u32>() {
+        return Ok(gid);
+    }
+    let unknown = || format!("Unknown device_group '{}'", name);
+    let c_name = CString::new(name).map_err(|_| unknown())?;
+
+    let mut group: libc::group = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    let mut result: *mut libc::group = std::ptr::null_mut();
+    let rc = unsafe { libc::getgrnam_r(c_name.as_ptr(), &mut group, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if rc != 0 || result.is_null() {
+        return Err(unknown().into());
+    }
+    Ok(group.gr_gid)
+}
+
+/// Give the event node of `device` to `group` (mode 0660), if one is set.
+pub fn share(device: &UinputDevice, group: Option<&str>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let Some(group) = group else {
+        return Ok(());
+    };
+    let gid = resolve_group(group)?;
+    let node = event_node(device)?;
+
+    std::os::unix::fs::chown(&node, None, Some(gid)).map_err(|e| {
+        format!(
+            "Failed to give {} to group {}: {} (device_group needs rm-pad to run as root)",
+            node.display(),
+            group,
+            e
+        )
+    })?;
+    std::fs::set_permissions(&node, std::fs::Permissions::from_mode(0o660))
+        .map_err(|e| format!("Failed to make {} group-accessible: {}", node.display(), e))?;
+
+    log::info!("{} is now accessible to group {}", node.display(), group);
+    Ok(())
+}
+
+/// `/dev/input/eventN` of a uinput device, found through its sysfs entry.
+fn event_node(device: &UinputDevice) -> Result<PathBuf, Box<dyn std::error::Error + Send + Sync>> {
+    let sysfs = PathBuf::from("/sys/devices/virtual/input").join(device.sysname()?);
+    for entry in std::fs::read_dir(&sysfs)? {
+        let name = entry?.file_name();
+        if name.to_string_lossy().starts_with("event") {
+            return Ok(PathBuf::from("/dev/input").join(name));
+        }
+    }
+    Err(format!("No event node under {}", sysfs.display()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_group() {
+        assert_eq!(resolve_group("0").unwrap(), 0);
+        assert_eq!(resolve_group("root").unwrap(), 0);
+        assert!(resolve_group("no-such-group-rm-pad").is_err());
+    }
+}
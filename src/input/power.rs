@@ -0,0 +1,111 @@
+//! Follow the tablet's sleep state from its power button (`pause_on_sleep`).
+//!
+//! A press of the power button puts the tablet to sleep and the next one
+//! wakes it. While it is asleep the streams go quiet or drop, and
+//! reconnecting every couple of seconds only fills the log with errors, so
+//! the reconnect loop waits for wake-up instead (retrying now and then, in
+//! case a press was missed). The power button itself is never grabbed, and
+//! its stream connecting again also counts as the tablet being awake.
+
+use std::io::Read;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use evdevil::event::Key;
+
+use crate::config::Config;
+use crate::device::DeviceProfile;
+use crate::ssh;
+
+use super::event::{parse_input_event, read_event, EV_KEY};
+use super::stop::StopFlag;
+
+/// Whether the tablet is asleep, shared by every stream.
+#[derive(Clone, Default)]
+pub struct TabletSleep(Arc<AtomicBool>);
+
+impl TabletSleep {
+    pub fn is_asleep(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn set_asleep(&self, asleep: bool) {
+        if self.0.swap(asleep, Ordering::Relaxed) != asleep {
+            log::info!("{}", if asleep { "Tablet went to sleep" } else { "Tablet woke up" });
+        }
+    }
+
+    /// Wait while the tablet sleeps, for at most `max`. Returns whether it was stopped.
+    pub fn wait_while_asleep(&self, stop: &StopFlag, max: Duration) -> bool {
+        let end = Instant::now() + max;
+        while self.is_asleep() && Instant::now() < end {
+            if stop.sleep(Duration::from_millis(250)) {
+                return true;
+            }
+        }
+        stop.is_set()
+    }
+}
+
+pub fn run_power(
+    config: &Config,
+    device_profile: &DeviceProfile,
+    device_path: &str,
+    sleep: &TabletSleep,
+    stop: &StopFlag,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (cleanup, channel) = ssh::open_input_stream(device_path, config, false)?;
+    let mut channel = stop.reader(&cleanup, channel, None, None);
+    sleep.set_asleep(false);
+
+    let result = run_event_loop(&mut channel, device_profile, sleep);
+    ssh::check_stream_end(channel.get_mut(), &cleanup, config, false, result)
+}
+
+/// Toggle `sleep` on every power button press from `channel`.
+fn run_event_loop(
+    channel: &mut impl Read,
+    device: &DeviceProfile,
+    sleep: &TabletSleep,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut buf = vec![0u8; device.input_event_size];
+
+    loop {
+        read_event(channel, &mut buf)?;
+
+        let Some(ev) = parse_input_event(&buf) else {
+            continue;
+        };
+        if ev.event_type().raw() == EV_KEY && ev.raw_code() == Key::KEY_POWER.raw() && ev.raw_value() == 1 {
+            sleep.set_asleep(!sleep.is_asleep());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::RM2;
+    use crate::input::event::{raw_event, EV_SYN, SYN_REPORT};
+
+    #[test]
+    fn test_power_presses_toggle_sleep() {
+        let power = Key::KEY_POWER.raw();
+        let press = |value| [raw_event(EV_KEY, power, value), raw_event(EV_SYN, SYN_REPORT, 0)].concat();
+        let run = |presses: &[i32]| {
+            let stream: Vec<u8> = presses.iter().flat_map(|&v| press(v)).collect();
+            let sleep = TabletSleep::default();
+            let _ = run_event_loop(&mut stream.as_slice(), &RM2, &sleep);
+            sleep.is_asleep()
+        };
+
+        assert!(run(&[1, 0]));
+        // Releases and autorepeat don't count
+        assert!(run(&[1, 2, 2, 0]));
+        assert!(!run(&[1, 0, 1, 0]));
+
+        let stop = StopFlag::default();
+        assert!(!TabletSleep::default().wait_while_asleep(&stop, Duration::from_secs(60)));
+    }
+}
@@ -0,0 +1,116 @@
+//! Notice when the pen goes past the profile's range (`pen_auto_range`).
+//!
+//! The Paper Pro's pen ranges are estimates. Over the first `RANGE_WINDOW`
+//! of pen input, `RangeMonitor` counts the frames whose raw position lies
+//! more than `RANGE_MARGIN_PERCENT` past the profile's maximum. Once
+//! `OVER_FRAMES` of them have come in, the pen loop warns, and with
+//! `pen_auto_range` ends with `RangeExpanded` so the output is set up again
+//! with the observed maxima. The stream from the tablet stays open.
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::device::DeviceProfile;
+
+/// How long after the first pen frame positions are checked.
+const RANGE_WINDOW: Duration = Duration::from_secs(5);
+
+/// How far past the maximum a position has to be to count, in percent of it.
+const RANGE_MARGIN_PERCENT: i32 = 5;
+
+/// Frames past the margin it takes to call the range wrong.
+const OVER_FRAMES: u32 = 10;
+
+pub struct RangeMonitor {
+    limit: (i32, i32),
+    profile_max: (i32, i32),
+    seen_max: (i32, i32),
+    start: Option<Duration>,
+    over: u32,
+    done: bool,
+}
+
+impl RangeMonitor {
+    pub fn new(device: &DeviceProfile) -> Self {
+        let limit = |max: i32| max.saturating_add(max / 100 * RANGE_MARGIN_PERCENT);
+        Self {
+            limit: (limit(device.pen_x_max), limit(device.pen_y_max)),
+            profile_max: (device.pen_x_max, device.pen_y_max),
+            seen_max: (device.pen_x_max, device.pen_y_max),
+            start: None,
+            over: 0,
+            done: false,
+        }
+    }
+
+    /// Record a raw pen position at event time `time`. Returns the observed
+    /// maxima, once, when they are clearly past the profile's.
+    pub fn observe(&mut self, (x, y): (i32, i32), time: Duration) -> Option<(i32, i32)> {
+        if self.done {
+            return None;
+        }
+        let start = *self.start.get_or_insert(time);
+        if time.saturating_sub(start) > RANGE_WINDOW {
+            self.done = true;
+            return None;
+        }
+
+        self.seen_max = (self.seen_max.0.max(x), self.seen_max.1.max(y));
+        if x > self.limit.0 || y > self.limit.1 {
+            self.over += 1;
+        }
+        if self.over < OVER_FRAMES {
+            return None;
+        }
+        self.done = true;
+        Some(self.seen_max)
+    }
+
+    pub fn profile_max(&self) -> (i32, i32) {
+        self.profile_max
+    }
+}
+
+/// Ends the pen loop when `pen_auto_range` takes the observed maxima.
+#[derive(Debug)]
+pub struct RangeExpanded {
+    pub x_max: i32,
+    pub y_max: i32,
+}
+
+impl fmt::Display for RangeExpanded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Pen range expanded to {}x{}", self.x_max, self.y_max)
+    }
+}
+
+impl std::error::Error for RangeExpanded {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::RMPP;
+
+    #[test]
+    fn test_range_monitor_needs_consistent_overshoot() {
+        let (x_max, y_max) = (RMPP.pen_x_max, RMPP.pen_y_max);
+        let at = Duration::from_millis;
+
+        // Slightly past the maximum is within the margin
+        let mut monitor = RangeMonitor::new(&RMPP);
+        assert!((0..50).all(|i| monitor.observe((x_max + 10, y_max), at(i * 10)).is_none()));
+
+        let mut monitor = RangeMonitor::new(&RMPP);
+        let far = (x_max + x_max / 5, y_max);
+        for i in 0..OVER_FRAMES - 1 {
+            assert_eq!(monitor.observe(far, at(i as u64 * 10)), None);
+        }
+        assert_eq!(monitor.observe((x_max / 2, y_max + y_max / 10), at(100)), Some((far.0, y_max + y_max / 10)));
+        assert_eq!(monitor.observe(far, at(110)), None, "only reported once");
+
+        // Only the first seconds count
+        let mut monitor = RangeMonitor::new(&RMPP);
+        monitor.observe((0, 0), at(0));
+        assert!((0..20).all(|i| monitor.observe(far, at(6000 + i * 10)).is_none()));
+    }
+}
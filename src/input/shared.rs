@@ -0,0 +1,244 @@
+//! Pen and touch on one evdev node (`pen_device == touch_device`).
+//!
+//! Some firmware interleaves both on a single device. Two streams would grab
+//! it twice, so a single stream is opened instead and its events are split
+//! between the pen and touch loops: multi-touch axes go to touch, everything
+//! else to the pen. Each loop gets a SYN_REPORT only for frames it received
+//! events in, so pen frames don't make the touchpad repeat itself.
+
+use std::io::{self, Read};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::device::DeviceProfile;
+use crate::palm::SharedPalmState;
+use crate::ssh;
+
+use super::event::{parse_input_event, read_event, ABS_MT_DISTANCE, ABS_MT_SLOT, EV_ABS, EV_SYN};
+use super::mouse::SharedMouse;
+use super::stop::StopFlag;
+use super::{pen, touch};
+
+pub fn run_shared(
+    config: &Config,
+    device_profile: &DeviceProfile,
+    grab: bool,
+    palm: Option<SharedPalmState>,
+    mouse: Option<SharedMouse>,
+    stop: &StopFlag,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (cleanup, channel) = ssh::open_input_stream(&config.pen_device, config, grab)?;
+    let hover = config.touch_hover.then(|| touch::probe_hover(&cleanup, config, device_profile)).flatten();
+    let mut channel = stop.reader(&cleanup, channel, None, config.idle_disconnect_secs.map(Duration::from_secs));
+    log::info!("Pen and touch share {}, demultiplexing one stream", cleanup.device_path());
+
+    let (pen_tx, pen_rx) = mpsc::channel();
+    let (touch_tx, touch_rx) = mpsc::channel();
+    let touch_palm = palm.clone();
+    let touch_mouse = mouse.clone();
+
+    let result = thread::scope(|scope| {
+        let pen = thread::Builder::new()
+            .name("pen".into())
+            .spawn_scoped(scope, move || pen::forward(&mut EventPipe::new(pen_rx, None), device_profile, config, palm, mouse))?;
+        let touch = thread::Builder::new()
+            .name("touch".into())
+            .spawn_scoped(scope, move || {
+                touch::forward(&mut EventPipe::new(touch_rx, touch::lift_poll(config)), device_profile, config, touch_palm, touch_mouse, hover)
+            })?;
+
+        // Ends when the stream fails or a loop gives up; dropping the
+        // senders then stops the other loop too.
+        let demux = demultiplex(&mut channel, device_profile, pen_tx, touch_tx);
+
+        let pen = pen.join().unwrap_or_else(|_| Err("pen loop panicked".into()));
+        let touch = touch.join().unwrap_or_else(|_| Err("touch loop panicked".into()));
+        // A loop's own error (e.g. uinput setup) explains more than the
+        // resulting send failure; otherwise report the stream's error.
+        match demux {
+            Err(e) if e.downcast_ref::<mpsc::SendError<Vec<u8>>>().is_some() => pen.and(touch),
+            demux => demux,
+        }
+    });
+
+    ssh::check_stream_end(channel.get_mut(), &cleanup, config, grab, result)
+}
+
+fn demultiplex(
+    channel: &mut impl Read,
+    device: &DeviceProfile,
+    pen: Sender<Vec<u8>>,
+    touch: Sender<Vec<u8>>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut buf = vec![0u8; device.input_event_size];
+    let (mut pen_pending, mut touch_pending) = (false, false);
+
+    loop {
+        read_event(channel, &mut buf)?;
+
+        let Some(ev) = parse_input_event(&buf) else {
+            continue;
+        };
+
+        match route(ev.event_type().raw(), ev.raw_code()) {
+            Route::Touch => {
+                touch.send(buf.clone())?;
+                touch_pending = true;
+            }
+            Route::Pen => {
+                pen.send(buf.clone())?;
+                pen_pending = true;
+            }
+            Route::Both => {
+                if pen_pending {
+                    pen.send(buf.clone())?;
+                }
+                if touch_pending {
+                    touch.send(buf.clone())?;
+                }
+                (pen_pending, touch_pending) = (false, false);
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Route {
+    Pen,
+    Touch,
+    /// SYN events end the current frame on both sides.
+    Both,
+}
+
+fn route(ty: u16, code: u16) -> Route {
+    match ty {
+        EV_SYN => Route::Both,
+        EV_ABS if (ABS_MT_SLOT..=ABS_MT_DISTANCE).contains(&code) => Route::Touch,
+        _ => Route::Pen,
+    }
+}
+
+/// Raw events handed over from the demultiplexer, read like a stream.
+/// Once the sender is gone, reads hit end-of-file. With a `timeout`, a read
+/// that waits that long fails with `TimedOut`, like the stream's own reads.
+struct EventPipe {
+    rx: Receiver<Vec<u8>>,
+    timeout: Option<Duration>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl EventPipe {
+    fn new(rx: Receiver<Vec<u8>>, timeout: Option<Duration>) -> Self {
+        Self {
+            rx,
+            timeout,
+            chunk: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl Read for EventPipe {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.chunk.len() {
+            let received = match self.timeout {
+                Some(timeout) => self.rx.recv_timeout(timeout),
+                None => self.rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match received {
+                Ok(chunk) => (self.chunk, self.pos) = (chunk, 0),
+                Err(RecvTimeoutError::Timeout) => return Err(io::ErrorKind::TimedOut.into()),
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            }
+        }
+        let n = out.len().min(self.chunk.len() - self.pos);
+        out[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::RM2;
+    use crate::input::event::{raw_event, ABS_MT_TRACKING_ID};
+    use crate::input::RecordingSink;
+
+    #[test]
+    fn test_demultiplex_splits_frames() {
+        let stream: Vec<u8> = [
+            raw_event(EV_ABS, 0x00, 100), // pen ABS_X
+            raw_event(EV_SYN, 0, 0),
+            raw_event(EV_ABS, 0x35, 700), // ABS_MT_POSITION_X
+            raw_event(EV_ABS, 0x00, 110),
+            raw_event(EV_SYN, 0, 0),
+        ]
+        .concat();
+
+        let (pen_tx, pen_rx) = mpsc::channel();
+        let (touch_tx, touch_rx) = mpsc::channel();
+        let result = demultiplex(&mut stream.as_slice(), &RM2, pen_tx, touch_tx);
+        assert!(result.is_err(), "ends at end of stream");
+
+        let codes = |rx: Receiver<Vec<u8>>| -> Vec<(u16, u16)> {
+            rx.iter()
+                .filter_map(|buf| parse_input_event(&buf))
+                .map(|e| (e.event_type().raw(), e.raw_code()))
+                .collect()
+        };
+        assert_eq!(codes(pen_rx), [(EV_ABS, 0x00), (EV_SYN, 0), (EV_ABS, 0x00), (EV_SYN, 0)]);
+        assert_eq!(codes(touch_rx), [(EV_ABS, 0x35), (EV_SYN, 0)]);
+    }
+
+    #[test]
+    fn test_event_pipe_reads_across_chunks() {
+        let (tx, rx) = mpsc::channel();
+        tx.send(vec![1, 2, 3]).unwrap();
+        tx.send(vec![4, 5]).unwrap();
+        drop(tx);
+
+        let mut pipe = EventPipe::new(rx, None);
+        let mut buf = [0u8; 5];
+        pipe.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3, 4, 5]);
+        assert_eq!(pipe.read_exact(&mut buf).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_event_pipe_flushes_debounced_lift() {
+        let mut config = Config::defaults(&RM2);
+        config.touch_lift_debounce_ms = 20;
+        let (tx, rx) = mpsc::channel();
+        for event in [
+            raw_event(EV_ABS, ABS_MT_SLOT, 0),
+            raw_event(EV_ABS, ABS_MT_TRACKING_ID, 1),
+            raw_event(EV_ABS, 0x35, 700), // ABS_MT_POSITION_X
+            raw_event(EV_ABS, 0x36, 900), // ABS_MT_POSITION_Y
+            raw_event(EV_SYN, 0, 0),
+            raw_event(EV_ABS, ABS_MT_TRACKING_ID, -1),
+            raw_event(EV_SYN, 0, 0),
+        ] {
+            tx.send(event).unwrap();
+        }
+
+        // No event follows the lift while the sender is up, so only a timeout can report it
+        let frames = thread::scope(|scope| {
+            let touch = scope.spawn(|| {
+                let mut sink = RecordingSink::default();
+                let mut pipe = EventPipe::new(rx, touch::lift_poll(&config));
+                let _ = touch::run_event_loop(&mut pipe, &mut sink, &RM2, &config, None, false, None);
+                sink.frames
+            });
+            thread::sleep(Duration::from_millis(200));
+            drop(tx);
+            touch.join().unwrap()
+        });
+
+        let lifts = frames.concat().into_iter().filter(|e| e.raw_code() == ABS_MT_TRACKING_ID && e.raw_value() == -1);
+        assert_eq!(lifts.count(), 1);
+    }
+}
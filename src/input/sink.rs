@@ -0,0 +1,451 @@
+//! Output side of the input loops.
+//!
+//! The loops hand each decoded frame to an `EventSink`. In normal operation
+//! that's the uinput device; replays and tests use `RecordingSink` instead.
+//! With `tee_output`, the device is wrapped in a `TeeSink` that also writes
+//! every frame to a `FrameLog`. Only the pen and touch devices are teed:
+//! gesture key presses and the clicks sent to the shared mouse go straight
+//! to their own devices. The loops themselves wrap their sink in
+//! `SkipRepeats`, which drops frames that change nothing with
+//! `skip_repeated_frames`. Uinput devices are wrapped in `Repairing`, which
+//! recreates the device when a write to it fails instead of ending the
+//! stream (and with it the connection to the tablet).
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use evdevil::event::{EventType, InputEvent};
+use evdevil::uinput::UinputDevice;
+
+use crate::status::StreamStatus;
+
+use super::event::{ABS_MT_SLOT, ABS_MT_TRACKING_ID, EV_ABS, EV_KEY};
+
+pub trait EventSink {
+    /// Write one frame of events. The sink terminates it with SYN_REPORT.
+    fn write_frame(&mut self, events: &[InputEvent]) -> io::Result<()>;
+}
+
+impl EventSink for UinputDevice {
+    fn write_frame(&mut self, events: &[InputEvent]) -> io::Result<()> {
+        self.write(events)
+    }
+}
+
+/// Keeps every frame in memory.
+#[derive(Default)]
+pub struct RecordingSink {
+    pub frames: Vec<Vec<InputEvent>>,
+}
+
+impl EventSink for RecordingSink {
+    fn write_frame(&mut self, events: &[InputEvent]) -> io::Result<()> {
+        self.frames.push(events.to_vec());
+        Ok(())
+    }
+}
+
+impl<S: EventSink + ?Sized> EventSink for &mut S {
+    fn write_frame(&mut self, events: &[InputEvent]) -> io::Result<()> {
+        (**self).write_frame(events)
+    }
+}
+
+/// Times a `Repairing` sink recreates its device for one frame before giving up.
+const REPAIR_ATTEMPTS: u32 = 3;
+
+/// Wait before the first attempt; each further one waits that much longer.
+const REPAIR_DELAY: Duration = Duration::from_millis(100);
+
+/// Recreates `sink` with `make` when a write to it fails and writes the
+/// frame again to the new one. Only when that fails `REPAIR_ATTEMPTS`
+/// times in a row is the error passed on.
+///
+/// The loops only send changes (tracking ids when a finger lands, tool keys
+/// on proximity), so a fresh device first gets a frame that replays every
+/// key and axis written so far. That also leaves it in the state a
+/// `SkipRepeats` above has cached, so its cache stays valid.
+pub struct Repairing<S, F> {
+    sink: S,
+    make: F,
+    name: &'static str,
+    state: DeviceState,
+}
+
+impl<S, F> Repairing<S, F>
+where
+    S: EventSink,
+    F: FnMut() -> Result<S, Box<dyn std::error::Error + Send + Sync>>,
+{
+    /// `name` is the stream in the log, "pen" or "touch".
+    pub fn new(sink: S, name: &'static str, make: F) -> Self {
+        Self { sink, make, name, state: DeviceState::default() }
+    }
+
+    /// Replace the device and bring it to the current state, then write `events`.
+    fn recreate(&mut self, events: &[InputEvent]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.sink = (self.make)()?;
+        let replay = self.state.replay();
+        if !replay.is_empty() {
+            self.sink.write_frame(&replay)?;
+        }
+        self.sink.write_frame(events)?;
+        Ok(())
+    }
+}
+
+impl<S, F> EventSink for Repairing<S, F>
+where
+    S: EventSink,
+    F: FnMut() -> Result<S, Box<dyn std::error::Error + Send + Sync>>,
+{
+    fn write_frame(&mut self, events: &[InputEvent]) -> io::Result<()> {
+        let mut error = match self.sink.write_frame(events) {
+            Ok(()) => {
+                self.state.update(events);
+                return Ok(());
+            }
+            Err(e) => e,
+        };
+
+        for attempt in 1..=REPAIR_ATTEMPTS {
+            log::warn!(
+                "[{}] Writing to the uinput device failed ({}), recreating it (attempt {}/{})",
+                self.name, error, attempt, REPAIR_ATTEMPTS
+            );
+            std::thread::sleep(REPAIR_DELAY * attempt);
+            match self.recreate(events) {
+                Ok(()) => {
+                    log::info!("[{}] Recreated the uinput device", self.name);
+                    self.state.update(events);
+                    return Ok(());
+                }
+                Err(e) => error = io::Error::other(e),
+            }
+        }
+        Err(error)
+    }
+}
+
+/// Last value written per key and axis, per slot for multi-touch axes.
+#[derive(Default)]
+struct DeviceState {
+    /// Keyed by (type, code, slot); slot is -1 outside multi-touch axes.
+    values: BTreeMap<(u16, u16, i32), i32>,
+    slot: i32,
+}
+
+impl DeviceState {
+    /// Whether the frame would change anything recorded. Events other than
+    /// keys and axes always count as a change.
+    fn changes(&self, events: &[InputEvent]) -> bool {
+        let mut slot = self.slot;
+        events.iter().any(|e| {
+            let (ty, code, value) = (e.event_type().raw(), e.raw_code(), e.raw_value());
+            match ty {
+                EV_ABS if code == ABS_MT_SLOT => {
+                    slot = value;
+                    false
+                }
+                EV_ABS | EV_KEY => {
+                    let slot = if ty == EV_ABS && code > ABS_MT_SLOT { slot } else { -1 };
+                    self.values.get(&(ty, code, slot)) != Some(&value)
+                }
+                _ => true,
+            }
+        })
+    }
+
+    /// Record the values of a frame the device has been sent.
+    fn update(&mut self, events: &[InputEvent]) {
+        for e in events {
+            let (ty, code, value) = (e.event_type().raw(), e.raw_code(), e.raw_value());
+            match ty {
+                EV_ABS if code == ABS_MT_SLOT => self.slot = value,
+                EV_ABS | EV_KEY => {
+                    let slot = if ty == EV_ABS && code > ABS_MT_SLOT { self.slot } else { -1 };
+                    self.values.insert((ty, code, slot), value);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// One frame that sets every recorded value, ending on the current slot.
+    fn replay(&self) -> Vec<InputEvent> {
+        let event = |ty: u16, code: u16, value: i32| InputEvent::new(EventType::from_raw(ty), code, value);
+        let mut frame: Vec<InputEvent> = self
+            .values
+            .iter()
+            .filter(|((_, _, slot), _)| *slot < 0)
+            .map(|(&(ty, code, _), &value)| event(ty, code, value))
+            .collect();
+
+        let mut slots: BTreeMap<i32, Vec<(u16, i32)>> = BTreeMap::new();
+        for (&(_, code, slot), &value) in self.values.iter().filter(|((_, _, slot), _)| *slot >= 0) {
+            slots.entry(slot).or_default().push((code, value));
+        }
+        if slots.is_empty() {
+            return frame;
+        }
+        for (slot, mut values) in slots {
+            frame.push(event(EV_ABS, ABS_MT_SLOT, slot));
+            // The tracking id first, so the kernel takes the rest as that contact's
+            values.sort_by_key(|&(code, _)| code != ABS_MT_TRACKING_ID);
+            frame.extend(values.into_iter().map(|(code, value)| event(EV_ABS, code, value)));
+        }
+        frame.push(event(EV_ABS, ABS_MT_SLOT, self.slot));
+        frame
+    }
+}
+
+/// Drops frames that change nothing (`skip_repeated_frames`): every axis
+/// and key in them already has that value, per slot for multi-touch axes.
+/// Other event types always count as a change. Passes everything through
+/// when not `enabled`. Counts forwarded and skipped frames in `status`.
+///
+/// Only frames that reach `sink` are recorded, so a skipped frame's
+/// `ABS_MT_SLOT` doesn't move the slot the device is on.
+pub struct SkipRepeats<S> {
+    sink: S,
+    enabled: bool,
+    state: DeviceState,
+    status: &'static StreamStatus,
+}
+
+impl<S: EventSink> SkipRepeats<S> {
+    pub fn new(sink: S, enabled: bool, status: &'static StreamStatus) -> Self {
+        Self { sink, enabled, state: DeviceState::default(), status }
+    }
+}
+
+impl<S: EventSink> EventSink for SkipRepeats<S> {
+    fn write_frame(&mut self, events: &[InputEvent]) -> io::Result<()> {
+        if self.enabled && !self.state.changes(events) {
+            self.status.skipped();
+            return Ok(());
+        }
+        self.sink.write_frame(events)?;
+        self.state.update(events);
+        self.status.forwarded();
+        Ok(())
+    }
+}
+
+/// `None` discards every frame, for optional outputs.
+impl<S: EventSink> EventSink for Option<S> {
+    fn write_frame(&mut self, events: &[InputEvent]) -> io::Result<()> {
+        match self {
+            Some(sink) => sink.write_frame(events),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Hands every frame to both sinks, `primary` first.
+pub struct TeeSink<A, B> {
+    pub primary: A,
+    pub secondary: B,
+}
+
+impl<A: EventSink, B: EventSink> EventSink for TeeSink<A, B> {
+    fn write_frame(&mut self, events: &[InputEvent]) -> io::Result<()> {
+        self.primary.write_frame(events)?;
+        self.secondary.write_frame(events)
+    }
+}
+
+/// Appends frames to a file (`tee_output`), one JSON object per line:
+/// `{"ms":<unix time>,"stream":"pen","events":[[type,code,value],...]}`.
+///
+/// Pen and touch each open their own handle on the same file; every line
+/// is a single append, so lines from both streams don't interleave.
+///
+/// A failed write (full disk, file removed from a network share) is logged
+/// once and stops the log; the frames keep going to the device.
+pub struct FrameLog {
+    file: Option<File>,
+    stream: &'static str,
+}
+
+impl FrameLog {
+    /// Open `path` for `stream` ("pen" or "touch"), if a path is set.
+    pub fn open(path: Option<&Path>, stream: &'static str) -> Result<Option<Self>, Box<dyn std::error::Error + Send + Sync>> {
+        let Some(path) = path else {
+            return Ok(None);
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Failed to open tee output {}: {}", path.display(), e))?;
+        log::info!("Writing forwarded {} frames to {}", stream, path.display());
+        Ok(Some(Self { file: Some(file), stream }))
+    }
+}
+
+impl EventSink for FrameLog {
+    fn write_frame(&mut self, events: &[InputEvent]) -> io::Result<()> {
+        let Some(file) = &mut self.file else {
+            return Ok(());
+        };
+        let ms = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+        let line = frame_line(ms, self.stream, events);
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            log::warn!("[{}] Failed to write tee output, no longer writing it: {}", self.stream, e);
+            self.file = None;
+        }
+        Ok(())
+    }
+}
+
+fn frame_line(ms: u64, stream: &str, events: &[InputEvent]) -> String {
+    let events: Vec<(u16, u16, i32)> = events
+        .iter()
+        .map(|e| (e.event_type().raw(), e.raw_code(), e.raw_value()))
+        .collect();
+    let line = serde_json::json!({ "ms": ms, "stream": stream, "events": events });
+    format!("{}\n", line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use evdevil::event::{Abs, AbsEvent, Key, KeyEvent, KeyState};
+
+    /// Fails every write if `broken`, keeps the frames written otherwise.
+    #[derive(Default)]
+    struct Flaky {
+        broken: bool,
+        frames: Vec<Vec<InputEvent>>,
+    }
+
+    impl EventSink for Flaky {
+        fn write_frame(&mut self, events: &[InputEvent]) -> io::Result<()> {
+            if self.broken {
+                return Err(io::Error::other("device gone"));
+            }
+            self.frames.push(events.to_vec());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_tee_sink_duplicates_frames() {
+        let frames: Vec<Vec<InputEvent>> = vec![
+            vec![AbsEvent::new(Abs::X, 100).into(), KeyEvent::new(Key::BTN_TOUCH, KeyState::PRESSED).into()],
+            vec![AbsEvent::new(Abs::X, 120).into()],
+        ];
+        let mut tee = TeeSink {
+            primary: RecordingSink::default(),
+            secondary: Some(RecordingSink::default()),
+        };
+        for frame in &frames {
+            tee.write_frame(frame).unwrap();
+        }
+        assert_eq!(tee.primary.frames, frames);
+        assert_eq!(tee.secondary.unwrap().frames, frames);
+
+        assert_eq!(
+            frame_line(5, "pen", &frames[1]),
+            "{\"events\":[[3,0,120]],\"ms\":5,\"stream\":\"pen\"}\n"
+        );
+    }
+
+    #[test]
+    fn test_failed_tee_output_keeps_forwarding() {
+        let full = FrameLog {
+            file: Some(OpenOptions::new().append(true).open("/dev/full").unwrap()),
+            stream: "pen",
+        };
+        let mut tee = TeeSink {
+            primary: RecordingSink::default(),
+            secondary: full,
+        };
+        let frame: Vec<InputEvent> = vec![AbsEvent::new(Abs::X, 100).into()];
+        tee.write_frame(&frame).unwrap();
+        assert!(tee.secondary.file.is_none());
+        tee.write_frame(&frame).unwrap();
+        assert_eq!(tee.primary.frames.len(), 2);
+    }
+
+    #[test]
+    fn test_skip_repeats_counts_frames() {
+        let status: &'static StreamStatus = Box::leak(Box::new(StreamStatus::new()));
+        let mut sink = SkipRepeats::new(RecordingSink::default(), true, status);
+        let frame: Vec<InputEvent> = vec![AbsEvent::new(Abs::X, 100).into()];
+        sink.write_frame(&frame).unwrap();
+        sink.write_frame(&frame).unwrap();
+        sink.write_frame(&[AbsEvent::new(Abs::X, 120).into()]).unwrap();
+
+        assert_eq!(sink.sink.frames.len(), 2);
+        let counts = status.frame_counts();
+        assert_eq!((counts.forwarded, counts.skipped), (2, 1));
+    }
+
+    #[test]
+    fn test_skipped_frame_keeps_device_slot() {
+        let status: &'static StreamStatus = Box::leak(Box::new(StreamStatus::new()));
+        let mut sink = SkipRepeats::new(RecordingSink::default(), true, status);
+        let slot = |slot: i32| InputEvent::from(AbsEvent::new(Abs::MT_SLOT, slot));
+        let x = |x: i32| InputEvent::from(AbsEvent::new(Abs::MT_POSITION_X, x));
+        sink.write_frame(&[slot(0), x(100), slot(1), x(200)]).unwrap();
+        // Changes nothing, so the device never sees it switch to slot 0
+        sink.write_frame(&[slot(0), x(100)]).unwrap();
+        // Without a slot this is for slot 1, where it is a change
+        sink.write_frame(&[x(100)]).unwrap();
+
+        assert_eq!(sink.sink.frames.len(), 2);
+        assert_eq!(sink.sink.frames[1], [x(100)]);
+    }
+
+    #[test]
+    fn test_repairing_recreates_failed_device() {
+        let frame: Vec<InputEvent> = vec![AbsEvent::new(Abs::X, 100).into()];
+
+        let made = std::cell::Cell::new(0);
+        let broken = || Flaky { broken: true, ..Flaky::default() };
+        let mut sink = Repairing::new(broken(), "test", || {
+            made.set(made.get() + 1);
+            Ok(Flaky::default())
+        });
+        sink.write_frame(&frame).unwrap();
+        sink.write_frame(&frame).unwrap();
+        assert_eq!((sink.sink.frames.len(), made.get()), (2, 1));
+
+        // A device that can't be brought back ends the stream
+        let mut sink = Repairing::new(broken(), "test", || Ok(broken()));
+        assert!(sink.write_frame(&frame).is_err());
+    }
+
+    #[test]
+    fn test_repairing_replays_state_mid_contact() {
+        let down: Vec<InputEvent> = vec![
+            AbsEvent::new(Abs::MT_SLOT, 1).into(),
+            AbsEvent::new(Abs::MT_TRACKING_ID, 7).into(),
+            AbsEvent::new(Abs::MT_POSITION_X, 100).into(),
+            KeyEvent::new(Key::BTN_TOUCH, KeyState::PRESSED).into(),
+            KeyEvent::new(Key::BTN_TOOL_FINGER, KeyState::PRESSED).into(),
+        ];
+        let moved: Vec<InputEvent> = vec![AbsEvent::new(Abs::MT_POSITION_X, 120).into()];
+
+        let mut sink = Repairing::new(Flaky::default(), "test", || Ok(Flaky::default()));
+        sink.write_frame(&down).unwrap();
+        sink.sink.broken = true;
+        sink.write_frame(&moved).unwrap();
+
+        // The new device gets the finger and its keys back before the move
+        let replay: Vec<InputEvent> = vec![
+            KeyEvent::new(Key::BTN_TOOL_FINGER, KeyState::PRESSED).into(),
+            KeyEvent::new(Key::BTN_TOUCH, KeyState::PRESSED).into(),
+            AbsEvent::new(Abs::MT_SLOT, 1).into(),
+            AbsEvent::new(Abs::MT_TRACKING_ID, 7).into(),
+            AbsEvent::new(Abs::MT_POSITION_X, 100).into(),
+            AbsEvent::new(Abs::MT_SLOT, 1).into(),
+        ];
+        assert_eq!(sink.sink.frames, [replay, moved]);
+    }
+}
@@ -0,0 +1,241 @@
+//! Cooperative stop for the input streams.
+//!
+//! Every stream thread gets a clone of one `StopFlag`. Reads from the
+//! tablet go through `StoppableReader`, which wakes up every `STOP_POLL`
+//! while waiting for data and fails with `Stopped` once the flag is set,
+//! so even a stream waiting on an idle pen ends within that interval. The
+//! reconnect loop then ends the stream instead of reconnecting.
+//!
+//! The streams sharing a flag also share the time of their last input, so
+//! `idle_disconnect_secs` only runs out once none of them got any.
+//!
+//! The first SIGINT/SIGTERM sets the flag; a second one exits at once.
+
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::daemon;
+use crate::ssh::GrabCleanup;
+
+use super::event::IdleDisconnect;
+
+/// Longest a blocked read waits before checking the flag.
+const STOP_POLL: Duration = Duration::from_millis(250);
+
+/// Flag set from the signal handler.
+static SIGNAL_FLAG: OnceLock<StopFlag> = OnceLock::new();
+
+#[derive(Clone)]
+pub struct StopFlag {
+    stopped: Arc<AtomicBool>,
+    /// When any stream sharing the flag last read input.
+    last_input: Arc<Mutex<Instant>>,
+}
+
+impl Default for StopFlag {
+    fn default() -> Self {
+        Self {
+            stopped: Arc::default(),
+            last_input: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+}
+
+impl StopFlag {
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+
+    fn input_seen(&self) {
+        if let Ok(mut last) = self.last_input.lock() {
+            *last = Instant::now();
+        }
+    }
+
+    /// Time since any stream sharing the flag last read input.
+    fn idle_for(&self) -> Duration {
+        self.last_input.lock().map_or(Duration::ZERO, |last| last.elapsed())
+    }
+
+    /// Sleep for `duration`, or until stopped. Returns whether it was stopped.
+    pub fn sleep(&self, duration: Duration) -> bool {
+        let end = Instant::now() + duration;
+        while !self.is_set() {
+            let left = end.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return false;
+            }
+            std::thread::sleep(left.min(STOP_POLL));
+        }
+        true
+    }
+
+    /// Wrap a stream's channel so reads fail with `Stopped` once the flag is
+    /// set, and with `IdleDisconnect` once no stream sharing the flag has
+    /// read input for `idle` (`None`: never). With `poll`, a read that waited
+    /// that long times out, so the loop can act on time passing.
+    pub fn reader(
+        &self,
+        cleanup: &GrabCleanup,
+        channel: ssh2::Channel,
+        poll: Option<Duration>,
+        idle: Option<Duration>,
+    ) -> StoppableReader<ssh2::Channel> {
+        let wait = [poll, idle].into_iter().flatten().fold(STOP_POLL, Duration::min);
+        cleanup.set_read_timeout(Some(wait));
+        StoppableReader {
+            inner: channel,
+            stop: self.clone(),
+            poll,
+            idle,
+        }
+    }
+}
+
+/// Returned by reads once the stream was told to stop.
+#[derive(Debug)]
+pub struct Stopped;
+
+impl fmt::Display for Stopped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Stopped")
+    }
+}
+
+impl std::error::Error for Stopped {}
+
+pub struct StoppableReader<R> {
+    inner: R,
+    stop: StopFlag,
+    poll: Option<Duration>,
+    idle: Option<Duration>,
+}
+
+impl<R> StoppableReader<R> {
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+}
+
+impl<R: Read> Read for StoppableReader<R> {
+    /// `inner` times out every `STOP_POLL` at most; those timeouts are only
+    /// passed on once `poll` has run out.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let start = Instant::now();
+        loop {
+            if self.stop.is_set() {
+                return Err(io::Error::other(Stopped));
+            }
+            match self.inner.read(buf) {
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                    if self.idle.is_some_and(|t| self.stop.idle_for() >= t) {
+                        return Err(io::Error::other(IdleDisconnect));
+                    }
+                    if self.poll.is_some_and(|t| start.elapsed() >= t) {
+                        return Err(e);
+                    }
+                }
+                Ok(n) => {
+                    if n > 0 {
+                        self.stop.input_seen();
+                    }
+                    return Ok(n);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Set `flag` on the first SIGINT or SIGTERM, and exit on the second.
+pub fn stop_on_signals(flag: &StopFlag) {
+    if SIGNAL_FLAG.set(flag.clone()).is_err() {
+        return;
+    }
+    let handler = handle_stop_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGTERM, handler);
+        libc::signal(libc::SIGINT, handler);
+    }
+}
+
+extern "C" fn handle_stop_signal(_sig: libc::c_int) {
+    // Only atomics and async-signal-safe calls here.
+    match SIGNAL_FLAG.get() {
+        Some(flag) if !flag.is_set() => flag.stop(),
+        _ => daemon::exit_now(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Never has data; sets the flag on its third read.
+    struct Stalled {
+        stop: StopFlag,
+        reads: usize,
+    }
+
+    impl Read for Stalled {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            self.reads += 1;
+            if self.reads == 3 {
+                self.stop.stop();
+            }
+            Err(io::ErrorKind::TimedOut.into())
+        }
+    }
+
+    #[test]
+    fn test_stop_interrupts_blocked_read() {
+        let stop = StopFlag::default();
+        let stalled = Stalled { stop: stop.clone(), reads: 0 };
+        let mut reader = StoppableReader { inner: stalled, stop: stop.clone(), poll: None, idle: None };
+
+        let e = reader.read(&mut [0u8; 16]).unwrap_err();
+        assert!(e.get_ref().is_some_and(|inner| inner.is::<Stopped>()));
+        assert_eq!(reader.inner.reads, 3);
+
+        // An elapsed poll is passed on as before
+        let stalled = Stalled { stop: StopFlag::default(), reads: 0 };
+        let mut reader = StoppableReader { inner: stalled, stop: StopFlag::default(), poll: Some(Duration::ZERO), idle: None };
+        assert_eq!(reader.read(&mut [0u8; 16]).unwrap_err().kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn test_idle_counts_input_on_every_stream() {
+        let idle = Some(Duration::from_secs(60));
+        let stop = StopFlag::default();
+        *stop.last_input.lock().unwrap() = Instant::now() - Duration::from_secs(120);
+        let is_idle = |e: io::Error| e.get_ref().is_some_and(|inner| inner.is::<IdleDisconnect>());
+
+        let stalled = Stalled { stop: StopFlag::default(), reads: 0 };
+        let mut reader = StoppableReader { inner: stalled, stop: stop.clone(), poll: None, idle };
+        assert!(is_idle(reader.read(&mut [0u8; 16]).unwrap_err()));
+
+        // Input on another stream keeps this one waiting until it's stopped
+        let mut other = StoppableReader { inner: &[1u8; 16][..], stop: stop.clone(), poll: None, idle };
+        assert_eq!(other.read(&mut [0u8; 16]).unwrap(), 16);
+        let stalled = Stalled { stop: stop.clone(), reads: 0 };
+        let mut reader = StoppableReader { inner: stalled, stop, poll: None, idle };
+        let e = reader.read(&mut [0u8; 16]).unwrap_err();
+        assert!(e.get_ref().is_some_and(|inner| inner.is::<Stopped>()));
+        assert_eq!(reader.inner.reads, 3);
+    }
+
+    #[test]
+    fn test_sleep_returns_once_stopped() {
+        let stop = StopFlag::default();
+        assert!(!stop.sleep(Duration::ZERO));
+        stop.stop();
+        assert!(stop.sleep(Duration::from_secs(60)));
+    }
+}
@@ -0,0 +1,124 @@
+//! Shared event budget for pen and touch (`max_event_rate`).
+//!
+//! A token bucket refilled at the configured events per second. Only plain
+//! motion frames are ever dropped; contact, button and finger count changes
+//! always go out but still use up budget. Touch gives way first: it only
+//! sends while the bucket is more than half full, leaving the rest to the
+//! pen. Frames that are dropped are simply skipped, so the next one carries
+//! the newest position.
+
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+static BUDGET: OnceLock<Mutex<TokenBucket>> = OnceLock::new();
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stream {
+    Pen,
+    Touch,
+}
+
+/// Cap pen and touch together at `rate` events per second. Call once, before the loops start.
+pub fn limit_event_rate(rate: u32) {
+    log::info!("Limiting forwarded input to {} events/s", rate);
+    let _ = BUDGET.set(Mutex::new(TokenBucket::new(rate, Instant::now())));
+}
+
+/// Whether a motion frame of `events` events fits the budget (and take it
+/// if so). Without `max_event_rate` everything fits.
+pub fn admit(stream: Stream, events: usize) -> bool {
+    let Some(budget) = BUDGET.get() else {
+        return true;
+    };
+    budget
+        .lock()
+        .map(|mut b| b.admit(stream, events as f64, Instant::now()))
+        .unwrap_or(true)
+}
+
+/// Account for a frame that is sent regardless of the budget.
+pub fn charge(events: usize) {
+    if let Some(Ok(mut budget)) = BUDGET.get().map(Mutex::lock) {
+        budget.charge(events as f64, Instant::now());
+    }
+}
+
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    /// Bursts of up to a fifth of a second's budget.
+    fn new(rate: u32, now: Instant) -> Self {
+        let capacity = (rate as f64 / 5.0).max(1.0);
+        Self {
+            rate: rate as f64,
+            capacity,
+            tokens: capacity,
+            refilled: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.refilled = now;
+    }
+
+    fn admit(&mut self, stream: Stream, events: f64, now: Instant) -> bool {
+        self.refill(now);
+        let reserve = match stream {
+            Stream::Pen => 0.0,
+            Stream::Touch => self.capacity / 2.0,
+        };
+        if self.tokens - events < reserve {
+            return false;
+        }
+        self.tokens -= events;
+        true
+    }
+
+    /// Forced frames can overdraw the bucket by up to one burst.
+    fn charge(&mut self, events: f64, now: Instant) {
+        self.refill(now);
+        self.tokens = (self.tokens - events).max(-self.capacity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_token_bucket_refills_at_rate() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1000, start);
+
+        // A full burst of 200, then nothing until it refills
+        assert!(bucket.admit(Stream::Pen, 200.0, start));
+        assert!(!bucket.admit(Stream::Pen, 10.0, start));
+        assert!(bucket.admit(Stream::Pen, 10.0, start + Duration::from_millis(10)));
+        assert!(!bucket.admit(Stream::Pen, 10.0, start + Duration::from_millis(10)));
+
+        // Forced frames overdraw, delaying the next motion frames
+        bucket.charge(50.0, start + Duration::from_millis(10));
+        assert!(!bucket.admit(Stream::Pen, 10.0, start + Duration::from_millis(50)));
+        assert!(bucket.admit(Stream::Pen, 10.0, start + Duration::from_millis(70)));
+    }
+
+    #[test]
+    fn test_touch_gives_way_to_pen() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1000, start);
+
+        assert!(bucket.admit(Stream::Touch, 90.0, start));
+        // At half the burst left, touch has to wait while the pen still fits
+        assert!(!bucket.admit(Stream::Touch, 20.0, start));
+        assert!(bucket.admit(Stream::Pen, 100.0, start));
+        assert!(!bucket.admit(Stream::Pen, 20.0, start));
+    }
+}
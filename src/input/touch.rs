@@ -1,23 +1,48 @@
+use std::fmt;
 use std::io::Read;
-use std::time::Duration;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
-use evdevil::event::{Abs, Key, KeyEvent, KeyState};
+use evdevil::event::{Abs, AbsEvent, InputEvent, Key, KeyEvent, KeyState};
 use evdevil::uinput::{AbsSetup, UinputDevice};
-use evdevil::{AbsInfo, InputProp, Slot};
+use evdevil::{AbsInfo, InputProp};
+use serde::Deserialize;
 
 use crate::config::Config;
 use crate::device::DeviceProfile;
-use crate::orientation::Orientation;
+use crate::caps;
+use crate::orientation::{apply_offset, Orientation};
 use crate::palm::SharedPalmState;
+use crate::screen;
 use crate::ssh;
+use crate::status;
 
 use super::event::{
-    parse_input_event, ABS_MT_POSITION_X, ABS_MT_POSITION_Y, ABS_MT_SLOT, ABS_MT_TRACKING_ID,
-    EV_ABS, EV_KEY, EV_SYN, SYN_REPORT,
+    below_min_delta, parse_timed_event, try_read_event, IdleDisconnect, Warmup, ABS_MT_DISTANCE, ABS_MT_POSITION_X, ABS_MT_POSITION_Y, ABS_MT_SLOT,
+    ABS_MT_TOUCH_MAJOR, ABS_MT_TRACKING_ID, EV_ABS, EV_KEY, EV_SYN, SYN_REPORT,
 };
+use super::existing;
+use super::gate::TouchGate;
+use super::gesture::{self, GestureOutput, Swipes};
+use super::mouse::SharedMouse;
+use super::pace::Pacer;
+use super::perms;
+use super::touch_pen::{self, TouchPen};
+use super::sink::{EventSink, FrameLog, Repairing, SkipRepeats, TeeSink};
+use super::stop::StopFlag;
+use super::throttle::{self, Stream};
+
+const TOUCH_DEVICE_NAME: &str = "reMarkable Touch";
 
 const MT_SLOTS: usize = 16;
 
+/// Largest tracking id handed out before starting again from 1.
+const TRACKING_ID_MAX: i32 = i32::MAX;
+
+/// ABS_MT_TOOL_TYPE values (`palm_cancel`).
+const MT_TOOL_FINGER: i32 = 0x00;
+const MT_TOOL_PALM: i32 = 0x02;
+
 struct SlotState {
     x: [Option<i32>; MT_SLOTS],
     y: [Option<i32>; MT_SLOTS],
@@ -25,6 +50,23 @@ struct SlotState {
     last_y: [Option<i32>; MT_SLOTS],
     active: [bool; MT_SLOTS],
     tracking_id: [Option<i32>; MT_SLOTS],
+    distance: [Option<i32>; MT_SLOTS],
+    /// Event time each active contact first appeared.
+    landed: [Option<Duration>; MT_SLOTS],
+    /// Contacts kept from the output for now (`gesture_hold_ms`).
+    held: [bool; MT_SLOTS],
+    /// Event time a forwarded contact lifted on the tablet.
+    lifted: [Option<Duration>; MT_SLOTS],
+    /// Lifted, but still reported as down (`touch_lift_debounce_ms`).
+    lift_pending: [bool; MT_SLOTS],
+    /// Order in which the contacts landed, for `PrimaryContact::FirstDown`.
+    landing: [u64; MT_SLOTS],
+    landings: u64,
+    /// Landing number of the contact that came down on an empty surface,
+    /// for `PrimaryContact::FirstOnly`.
+    first_landing: Option<u64>,
+    /// Contact size (ABS_MT_TOUCH_MAJOR), if the tablet reports it.
+    major: [Option<i32>; MT_SLOTS],
 }
 
 impl SlotState {
@@ -36,6 +78,29 @@ impl SlotState {
             last_y: [None; MT_SLOTS],
             active: [false; MT_SLOTS],
             tracking_id: [None; MT_SLOTS],
+            distance: [None; MT_SLOTS],
+            landed: [None; MT_SLOTS],
+            held: [false; MT_SLOTS],
+            lifted: [None; MT_SLOTS],
+            lift_pending: [false; MT_SLOTS],
+            landing: [0; MT_SLOTS],
+            landings: 0,
+            first_landing: None,
+            major: [None; MT_SLOTS],
+        }
+    }
+
+    /// Mark `slot` as down. A contact re-registering during its lift
+    /// debounce keeps its place in the landing order.
+    fn land(&mut self, slot: usize) {
+        let alone = !(0..MT_SLOTS).any(|s| s != slot && (self.active[s] || self.lift_pending[s]));
+        self.active[slot] = true;
+        if !self.lift_pending[slot] {
+            self.landings += 1;
+            self.landing[slot] = self.landings;
+            if alone {
+                self.first_landing = Some(self.landings);
+            }
         }
     }
 
@@ -44,12 +109,107 @@ impl SlotState {
         self.y[slot] = None;
         self.last_x[slot] = None;
         self.last_y[slot] = None;
+        self.distance[slot] = None;
+        self.major[slot] = None;
     }
 
     fn active_count(&self) -> i32 {
         self.active.iter().filter(|&&a| a).count() as i32
     }
 
+    /// Active and not held back, or lifted but not reported yet: part of the output.
+    fn is_visible(&self, slot: usize) -> bool {
+        (self.active[slot] && !self.held[slot]) || self.lift_pending[slot]
+    }
+
+    /// Tracking id for a new contact: the one after `last`, wrapping from
+    /// `max` back to 1 and skipping ids still assigned to a slot. Never 0
+    /// or negative, since -1 means "lifted" to consumers.
+    fn next_tracking_id(&self, last: i32, max: i32) -> i32 {
+        let mut id = last;
+        loop {
+            id = if id <= 0 || id >= max { 1 } else { id + 1 };
+            if !self.tracking_id.contains(&Some(id)) {
+                return id;
+            }
+        }
+    }
+
+    fn visible_count(&self) -> i32 {
+        (0..MT_SLOTS).filter(|&s| self.is_visible(s)).count() as i32
+    }
+
+    /// A slot is hovering when it reports a non-zero ABS_MT_DISTANCE.
+    fn is_hovering(&self, slot: usize) -> bool {
+        self.is_visible(slot) && self.distance[slot].is_some_and(|d| d > 0)
+    }
+
+    /// Visible slots that are actually in contact (not hovering).
+    fn touching_count(&self) -> i32 {
+        (0..MT_SLOTS).filter(|&s| self.is_visible(s) && !self.is_hovering(s)).count() as i32
+    }
+
+    /// Hold back a contact that lands while others are already forwarded
+    /// until it has stayed down for `hold`, so a brushing extra finger
+    /// doesn't change the finger count of a gesture in progress. A held
+    /// contact that lifts early is never forwarded.
+    fn update_held(&mut self, now: Duration, hold: Duration) {
+        let forwarded = self.tracking_id.iter().any(Option::is_some);
+        for slot in 0..MT_SLOTS {
+            if !self.active[slot] {
+                self.landed[slot] = None;
+                self.held[slot] = false;
+                continue;
+            }
+            let landed = *self.landed[slot].get_or_insert(now);
+            self.held[slot] = !hold.is_zero()
+                && forwarded
+                && self.tracking_id[slot].is_none()
+                && now.saturating_sub(landed) < hold;
+        }
+    }
+
+    /// Keep a forwarded contact that lifted down for `debounce`, so one that
+    /// re-registers in the same slot right away keeps its tracking id and
+    /// the consumer sees one continuous contact. Returns whether a pending
+    /// lift has just run out and needs a frame to be reported.
+    fn update_lifts(&mut self, now: Duration, debounce: Duration) -> bool {
+        let mut expired = false;
+        for slot in 0..MT_SLOTS {
+            if debounce.is_zero() || self.active[slot] || self.tracking_id[slot].is_none() {
+                self.lifted[slot] = None;
+                self.lift_pending[slot] = false;
+                continue;
+            }
+            let lifted = *self.lifted[slot].get_or_insert(now);
+            let pending = now.saturating_sub(lifted) < debounce;
+            expired |= self.lift_pending[slot] && !pending;
+            self.lift_pending[slot] = pending;
+        }
+        expired
+    }
+
+    /// Mean output position of the visible contacts.
+    fn centre(&self, device: &DeviceProfile, orientation: Orientation) -> Option<(i32, i32)> {
+        let points: Vec<(i32, i32)> = (0..MT_SLOTS)
+            .filter(|&s| self.is_visible(s))
+            .filter_map(|s| self.get_position(s))
+            .map(|(x, y)| {
+                orientation.transform_touch(
+                    x.clamp(0, device.touch_x_max),
+                    y.clamp(0, device.touch_y_max),
+                    device.touch_x_max,
+                    device.touch_y_max,
+                )
+            })
+            .collect();
+        let n = points.len() as i32;
+        (n > 0).then(|| {
+            let (sx, sy) = points.iter().fold((0, 0), |(sx, sy), &(x, y)| (sx + x, sy + y));
+            (sx / n, sy / n)
+        })
+    }
+
     fn get_position(&self, slot: usize) -> Option<(i32, i32)> {
         match (self.x[slot], self.y[slot]) {
             (Some(x), Some(y)) => Some((x, y)),
@@ -60,10 +220,44 @@ impl SlotState {
         }
     }
 
-    fn get_primary_position(&self, device: &DeviceProfile, orientation: Orientation) -> Option<(i32, i32)> {
-        (0..MT_SLOTS)
-            .find(|&s| self.active[s])
-            .and_then(|s| self.x[s].zip(self.y[s]))
+    /// The visible contact that drives the legacy axes and touch-as-pen.
+    fn primary_slot(&self, policy: PrimaryContact) -> Option<usize> {
+        let first = self.ranked(policy).first().copied();
+        match policy {
+            // Once the first finger lifts, nobody takes over until all are up
+            PrimaryContact::FirstOnly => first.filter(|&s| Some(self.landing[s]) == self.first_landing),
+            _ => first,
+        }
+    }
+
+    /// Visible slots, most primary first.
+    fn ranked(&self, policy: PrimaryContact) -> Vec<usize> {
+        let mut visible: Vec<usize> = (0..MT_SLOTS).filter(|&s| self.is_visible(s)).collect();
+        match policy {
+            PrimaryContact::LowestSlot => {}
+            PrimaryContact::FirstDown | PrimaryContact::FirstOnly => visible.sort_by_key(|&s| self.landing[s]),
+            // Without size reports every contact ties, leaving the lowest slot
+            PrimaryContact::Largest => visible.sort_by_key(|&s| std::cmp::Reverse(self.major[s].unwrap_or(0))),
+        }
+        visible
+    }
+
+    /// Visible slots beyond the first `max` ranked (`max_fingers`).
+    ///
+    /// Forwarded contacts rank ahead of the rest until they lift, so a
+    /// contact that grows bigger (`largest`) doesn't swap one out mid-gesture.
+    fn over_limit(&self, max: Option<u32>, policy: PrimaryContact) -> Vec<usize> {
+        let Some(max) = max else {
+            return Vec::new();
+        };
+        let (kept, waiting): (Vec<usize>, Vec<usize>) =
+            self.ranked(policy).into_iter().partition(|&s| self.tracking_id[s].is_some());
+        kept.into_iter().chain(waiting).skip(max as usize).collect()
+    }
+
+    fn get_primary_position(&self, device: &DeviceProfile, orientation: Orientation, policy: PrimaryContact) -> Option<(i32, i32)> {
+        self.primary_slot(policy)
+            .and_then(|s| self.last_x[s].zip(self.last_y[s]))
             .map(|(ax, ay)| {
                 orientation.transform_touch(
                     ax.clamp(0, device.touch_x_max),
@@ -91,11 +285,146 @@ impl FrameState {
     }
 }
 
-fn create_touchpad_device(device: &DeviceProfile, orientation: Orientation) -> Result<UinputDevice, Box<dyn std::error::Error + Send + Sync>> {
-    let (out_x_max, out_y_max) = orientation.touch_output_dimensions(device.touch_x_max, device.touch_y_max);
-    let resolution = device.touch_resolution;
+/// Touch axis resolution (units/mm), which libinput uses for the physical size.
+///
+/// `touch_width_mm` wins over `touch_resolution`, which wins over the profile.
+fn touch_resolution(config: &Config, device: &DeviceProfile) -> i32 {
+    if let Some(width_mm) = config.touch_width_mm {
+        let (out_x_max, _) = config
+            .touch_orientation
+            .touch_output_dimensions(device.touch_x_max, device.touch_y_max);
+        return ((out_x_max as f64 / width_mm).round() as i32).max(1);
+    }
+    config.touch_resolution.unwrap_or(device.touch_resolution)
+}
+
+const TOUCH_KEYS: [Key; 6] = [
+    Key::BTN_LEFT,
+    Key::BTN_TOUCH,
+    Key::BTN_TOOL_FINGER,
+    Key::BTN_TOOL_DOUBLETAP,
+    Key::BTN_TOOL_TRIPLETAP,
+    Key::BTN_TOOL_QUADTAP,
+];
+
+/// Touchscreens only report contact. Leaving out BTN_TOOL_FINGER matters:
+/// udev classifies a device with it as a touchpad.
+const TOUCHSCREEN_KEYS: [Key; 1] = [Key::BTN_TOUCH];
+
+/// Which contact counts as the primary one (`touch_primary`), reported on
+/// the legacy ABS_X/ABS_Y and forwarded by `touch_as_pen`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PrimaryContact {
+    /// The contact in the lowest slot; can jump to another finger when the
+    /// first one lifts while a later one in a lower slot is down.
+    #[default]
+    LowestSlot,
+    /// The earliest contact still down.
+    FirstDown,
+    /// The finger that touched down first, until every finger has lifted.
+    /// Fingers added meanwhile only count for gestures and never move the
+    /// cursor, even after the first one lifts.
+    FirstOnly,
+    /// The biggest contact (ABS_MT_TOUCH_MAJOR).
+    Largest,
+}
+
+impl fmt::Display for PrimaryContact {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PrimaryContact::LowestSlot => write!(f, "lowest-slot"),
+            PrimaryContact::FirstDown => write!(f, "first-down"),
+            PrimaryContact::FirstOnly => write!(f, "first-only"),
+            PrimaryContact::Largest => write!(f, "largest"),
+        }
+    }
+}
+
+impl FromStr for PrimaryContact {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "lowest-slot" => Ok(PrimaryContact::LowestSlot),
+            "first-down" => Ok(PrimaryContact::FirstDown),
+            "first-only" => Ok(PrimaryContact::FirstOnly),
+            "largest" => Ok(PrimaryContact::Largest),
+            _ => Err(format!(
+                "Invalid primary contact policy '{}'. Valid values: lowest-slot, first-down, first-only, largest",
+                s
+            )),
+        }
+    }
+}
+
+/// What kind of device the touch stream shows up as (`touch_mode`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TouchMode {
+    /// Clickpad that moves the cursor relative to where it is.
+    #[default]
+    Touchpad,
+    /// Direct-touch screen: contacts land where they are on the tablet,
+    /// mapped onto the screen, with multi-touch kept.
+    Touchscreen,
+}
 
-    let axes = [
+impl TouchMode {
+    fn props(self) -> &'static [InputProp] {
+        match self {
+            TouchMode::Touchpad => &[InputProp::POINTER, InputProp::BUTTONPAD],
+            TouchMode::Touchscreen => &[InputProp::DIRECT],
+        }
+    }
+
+    /// The tool keys the loop sends for other modes are dropped by the
+    /// kernel, since the device doesn't declare them.
+    fn keys(self) -> &'static [Key] {
+        match self {
+            TouchMode::Touchpad => &TOUCH_KEYS,
+            TouchMode::Touchscreen => &TOUCHSCREEN_KEYS,
+        }
+    }
+}
+
+impl fmt::Display for TouchMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TouchMode::Touchpad => write!(f, "touchpad"),
+            TouchMode::Touchscreen => write!(f, "touchscreen"),
+        }
+    }
+}
+
+impl FromStr for TouchMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "touchpad" => Ok(TouchMode::Touchpad),
+            "touchscreen" => Ok(TouchMode::Touchscreen),
+            _ => Err(format!("Invalid touch mode '{}'. Valid values: touchpad, touchscreen", s)),
+        }
+    }
+}
+
+/// Output range of the touch device and the tablet's offset inside it.
+///
+/// `fit_screen` only changes a touchscreen: a touchpad moves the cursor
+/// relative to where it is, so the shape of its area doesn't matter.
+fn touch_area(config: &Config, device: &DeviceProfile) -> Result<screen::Fit, Box<dyn std::error::Error + Send + Sync>> {
+    let tablet = config.touch_orientation.touch_output_dimensions(device.touch_x_max, device.touch_y_max);
+    let size = match config.touch_mode {
+        TouchMode::Touchscreen => screen::size(config)?,
+        TouchMode::Touchpad => None,
+    };
+    Ok(screen::fit(tablet, size))
+}
+
+/// `hover` is the source's ABS_MT_DISTANCE maximum if hover is forwarded.
+fn touchpad_axes(config: &Config, (out_x_max, out_y_max): (i32, i32), resolution: i32, hover: Option<i32>) -> Vec<AbsSetup> {
+    let mut axes = vec![
         AbsSetup::new(Abs::X, AbsInfo::new(0, out_x_max).with_resolution(resolution)),
         AbsSetup::new(Abs::Y, AbsInfo::new(0, out_y_max).with_resolution(resolution)),
         AbsSetup::new(Abs::MT_SLOT, AbsInfo::new(0, (MT_SLOTS - 1) as i32)),
@@ -103,19 +432,27 @@ fn create_touchpad_device(device: &DeviceProfile, orientation: Orientation) -> R
         AbsSetup::new(Abs::MT_POSITION_X, AbsInfo::new(0, out_x_max).with_resolution(resolution)),
         AbsSetup::new(Abs::MT_POSITION_Y, AbsInfo::new(0, out_y_max).with_resolution(resolution)),
     ];
+    if let Some(distance_max) = hover {
+        axes.push(AbsSetup::new(Abs::MT_DISTANCE, AbsInfo::new(0, distance_max)));
+    }
+    if config.palm_cancel {
+        axes.push(AbsSetup::new(Abs::MT_TOOL_TYPE, AbsInfo::new(MT_TOOL_FINGER, MT_TOOL_PALM)));
+    }
+    axes
+}
 
+fn create_touchpad_device(
+    config: &Config,
+    out_max: (i32, i32),
+    resolution: i32,
+    hover: Option<i32>,
+) -> Result<UinputDevice, Box<dyn std::error::Error + Send + Sync>> {
+    let mode = config.touch_mode;
     let device = UinputDevice::builder()?
-        .with_props([InputProp::POINTER, InputProp::BUTTONPAD])?
-        .with_abs_axes(axes)?
-        .with_keys([
-            Key::BTN_LEFT,
-            Key::BTN_TOUCH,
-            Key::BTN_TOOL_FINGER,
-            Key::BTN_TOOL_DOUBLETAP,
-            Key::BTN_TOOL_TRIPLETAP,
-            Key::BTN_TOOL_QUADTAP,
-        ])?
-        .build("reMarkable Touch")?;
+        .with_props(mode.props().iter().copied())?
+        .with_abs_axes(touchpad_axes(config, out_max, resolution, hover))?
+        .with_keys(mode.keys().iter().copied())?
+        .build(TOUCH_DEVICE_NAME)?;
 
     Ok(device)
 }
@@ -123,48 +460,186 @@ fn create_touchpad_device(device: &DeviceProfile, orientation: Orientation) -> R
 pub fn run_touch(
     config: &Config,
     device_profile: &DeviceProfile,
+    grab: bool,
     palm: Option<SharedPalmState>,
+    mouse: Option<SharedMouse>,
+    stop: &StopFlag,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let (_cleanup, mut channel) =
-        ssh::open_input_stream(&config.touch_device, config, config.grab_input)?;
+    let (cleanup, channel) = ssh::open_input_stream(&config.touch_device, config, grab)?;
+    let hover = config.touch_hover.then(|| probe_hover(&cleanup, config, device_profile)).flatten();
 
-    log::info!("Creating touch uinput device");
-    let uinput = create_touchpad_device(device_profile, config.orientation)?;
+    let mut channel = stop.reader(&cleanup, channel, lift_poll(config), config.idle_disconnect_secs.map(Duration::from_secs));
+    let result = forward(&mut channel, device_profile, config, palm, mouse, hover);
+    ssh::check_stream_end(channel.get_mut(), &cleanup, config, grab, result)
+}
+
+/// How long a touch read may wait before timing out. With a lift debounce
+/// the loop wakes up on its own to report lifts; otherwise reads block.
+pub(super) fn lift_poll(config: &Config) -> Option<Duration> {
+    (config.touch_lift_debounce_ms > 0).then(|| Duration::from_millis(config.touch_lift_debounce_ms))
+}
+
+/// Set up the configured output and run the event loop into it.
+///
+/// `mouse` takes the clicks of gesture bindings; `hover` is the source's
+/// ABS_MT_DISTANCE maximum if hover is forwarded, see `probe_hover`.
+pub fn forward(
+    channel: &mut impl Read,
+    device_profile: &DeviceProfile,
+    config: &Config,
+    palm: Option<SharedPalmState>,
+    mouse: Option<SharedMouse>,
+    hover: Option<i32>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let resolution = touch_resolution(config, device_profile);
+    let tee = FrameLog::open(config.tee_output.as_deref(), "touch")?;
+
+    if config.touch_as_pen {
+        log::info!("[touch] Creating touch-as-pen uinput device");
+        let uinput = touch_pen::create_device(device_profile, config.touch_orientation, resolution)?;
+        std::thread::sleep(Duration::from_secs(1));
+        perms::share(&uinput, config.device_group.as_deref())?;
+        log::info!("[touch] Forwarding started (as pen, primary contact only)");
+        let uinput = Repairing::new(uinput, "touch", || {
+            let uinput = touch_pen::create_device(device_profile, config.touch_orientation, resolution)?;
+            perms::share(&uinput, config.device_group.as_deref())?;
+            Ok(uinput)
+        });
+        let mut sink = TeeSink { primary: uinput, secondary: tee };
+        return run_event_loop(channel, &mut sink, device_profile, config, palm, false, None);
+    }
+
+    let out_max = touch_area(config, device_profile)?.max;
+    if config.use_existing_device {
+        let axes = touchpad_axes(config, out_max, resolution, hover);
+        let target = existing::open(config.existing_touch_device.as_deref(), TOUCH_DEVICE_NAME, &axes, config.touch_mode.keys())?;
+        let mut gestures = create_gesture_output(config, mouse)?;
+        log::info!("[touch] Forwarding started");
+        let keys = gestures.as_mut().map(|g| g as &mut dyn EventSink);
+        let mut sink = TeeSink { primary: target, secondary: tee };
+        return run_event_loop(channel, &mut sink, device_profile, config, palm, hover.is_some(), keys);
+    }
+
+    log::info!("[touch] Creating {} uinput device (resolution {} units/mm)", config.touch_mode, resolution);
+    let uinput = create_touchpad_device(config, out_max, resolution, hover)?;
 
     if let Ok(name) = uinput.sysname() {
-        log::info!("Touch device ready: /sys/devices/virtual/input/{}", name.to_string_lossy());
+        log::info!("[touch] Device ready: /sys/devices/virtual/input/{}", name.to_string_lossy());
     }
 
+    let mut gestures = create_gesture_output(config, mouse)?;
+
     std::thread::sleep(Duration::from_secs(1));
-    log::info!("Touch forwarding started");
+    perms::share(&uinput, config.device_group.as_deref())?;
+    if let Some(keyboard) = gestures.as_ref().and_then(|g| g.keyboard.as_ref()) {
+        perms::share(keyboard, config.device_group.as_deref())?;
+    }
+    log::info!("[touch] Forwarding started");
 
-    run_event_loop(&mut channel, &uinput, device_profile, config.orientation, palm, config.palm_grace_ms)
+    let keys = gestures.as_mut().map(|g| g as &mut dyn EventSink);
+    let uinput = Repairing::new(uinput, "touch", || {
+        let uinput = create_touchpad_device(config, out_max, resolution, hover)?;
+        perms::share(&uinput, config.device_group.as_deref())?;
+        Ok(uinput)
+    });
+    let mut sink = TeeSink { primary: uinput, secondary: tee };
+    run_event_loop(channel, &mut sink, device_profile, config, palm, hover.is_some(), keys)
 }
 
-fn run_event_loop(
+type GestureSinks = GestureOutput<UinputDevice, SharedMouse>;
+
+fn create_gesture_output(config: &Config, mouse: Option<SharedMouse>) -> Result<Option<GestureSinks>, Box<dyn std::error::Error + Send + Sync>> {
+    if config.gesture_keys.is_empty() {
+        return Ok(None);
+    }
+    let bindings = gesture::parse_bindings(&config.gesture_keys)?;
+    log::info!("[touch] Creating gesture keyboard uinput device ({} bindings)", bindings.len());
+    let keyboard = gesture::create_keyboard(&bindings)?;
+    Ok(Some(GestureOutput { keyboard, mouse }))
+}
+
+/// The touch device's ABS_MT_DISTANCE maximum, or `None` if it doesn't
+/// report hover.
+///
+/// The probe runs commands on the stream's session, so call it before
+/// `StopFlag::reader` shortens the session timeout to the stream's poll
+/// interval, which is too short for them.
+pub(super) fn probe_hover(cleanup: &ssh::GrabCleanup, config: &Config, device: &DeviceProfile) -> Option<i32> {
+    let word_bits = caps::word_bits(device.input_event_size);
+    let probed = caps::read_abs_axes(cleanup.session(), cleanup.device_path(), word_bits).and_then(|axes| {
+        if !axes.contains(&ABS_MT_DISTANCE) {
+            return Ok(None);
+        }
+        caps::read_abs_max(cleanup.session(), cleanup.device_path(), ABS_MT_DISTANCE, config).map(Some)
+    });
+    match probed {
+        Ok(Some(distance_max)) => {
+            log::info!("[touch] Device reports hover, forwarding ABS_MT_DISTANCE (0-{})", distance_max);
+            Some(distance_max)
+        }
+        Ok(None) => {
+            log::info!("[touch] Device does not report ABS_MT_DISTANCE, hover disabled");
+            None
+        }
+        Err(e) => {
+            log::warn!("[touch] Failed to probe capabilities, hover disabled: {}", e);
+            None
+        }
+    }
+}
+
+/// Decode touch events from `channel` and emit them to `sink` until the stream ends.
+pub fn run_event_loop(
     channel: &mut impl Read,
-    uinput: &UinputDevice,
+    sink: &mut impl EventSink,
     device: &DeviceProfile,
-    orientation: Orientation,
+    config: &Config,
     palm: Option<SharedPalmState>,
-    grace_ms: u64,
+    hover: bool,
+    mut keys: Option<&mut dyn EventSink>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let sink = &mut SkipRepeats::new(sink, config.skip_repeated_frames, &status::TOUCH);
+    let area = touch_area(config, device)?;
     let mut buf = vec![0u8; device.input_event_size];
     let mut slots = SlotState::new();
     let mut frame = FrameState::new();
     let mut next_tracking_id: i32 = 0;
+    let mut last_touching: i32 = 0;
+    let mut pacer = Pacer::new(config.pace);
+    let mut warmup = Warmup::new(config.warmup_discard_ms);
+    let mut as_pen = config.touch_as_pen.then(TouchPen::default);
+    let mut swipes = match keys {
+        Some(_) => Some(Swipes::new(gesture::parse_bindings(&config.gesture_keys)?)),
+        None => None,
+    };
+    let hold = Duration::from_millis(config.gesture_hold_ms);
+    let gate = config.touch_gate_key.as_deref().map(TouchGate::new).transpose()?;
+    let lift_debounce = Duration::from_millis(config.touch_lift_debounce_ms);
+    let (mut last_read, mut last_time) = (Instant::now(), Duration::ZERO);
     let mut frame_count: u64 = 0;
 
     loop {
-        channel.read_exact(&mut buf)?;
+        if !try_read_event(channel, &mut buf)? {
+            // Timeouts are only this short with touch_lift_debounce_ms
+            if lift_debounce.is_zero() {
+                return Err(IdleDisconnect.into());
+            }
+            if slots.update_lifts(last_time + last_read.elapsed(), lift_debounce) {
+                emit_touch_frame(sink, &mut slots, &mut next_tracking_id, device, config, &area, hover)?;
+                last_touching = if hover { slots.touching_count() } else { slots.visible_count() };
+            }
+            continue;
+        }
+        last_read = Instant::now();
 
-        let Some(ev) = parse_input_event(&buf) else {
+        let Some(timed) = parse_timed_event(&buf) else {
             continue;
         };
+        last_time = timed.time;
 
-        let ty = ev.event_type().raw();
-        let code = ev.raw_code();
-        let value = ev.raw_value();
+        let ty = timed.event.event_type().raw();
+        let code = timed.event.raw_code();
+        let value = timed.event.raw_value();
 
         if ty == EV_KEY {
             continue;
@@ -181,19 +656,94 @@ fn run_event_loop(
         resolve_pending_positions(&mut slots, &frame);
         frame.pending_positions.clear();
 
-        let contact_count = slots.active_count();
+        // Slots stay up to date through the warm-up; contacts still down get
+        // their tracking IDs with the first emitted frame.
+        if warmup.discard(Instant::now()) {
+            continue;
+        }
+
+        pacer.wait(timed.time);
+
+        slots.update_held(timed.time, hold);
+        slots.update_lifts(timed.time, lift_debounce);
+        let contact_count = slots.visible_count();
 
-        if should_suppress_palm(&palm, grace_ms) {
-            emit_palm_suppression(uinput, &mut slots)?;
+        // touch_gate_key released: drop touch exactly like a palm
+        let gated = gate.as_ref().is_some_and(|g| !g.is_held());
+        if gated || should_suppress_palm(&palm, config.palm_grace_ms, config.palm_grace_from_activity) {
+            match &mut as_pen {
+                Some(pen) => pen.emit(sink, None)?,
+                None => emit_palm_suppression(sink, &mut slots, config.palm_cancel)?,
+            }
             log_frame_progress(&mut frame_count, 0, true);
             continue;
         }
 
-        emit_touch_frame(uinput, &mut slots, &mut next_tracking_id, device, orientation)?;
+        if let Some(pen) = &mut as_pen {
+            let out_max = config
+                .touch_orientation
+                .touch_output_dimensions(device.touch_x_max, device.touch_y_max);
+            let position = primary_output_position(&slots, device, config.touch_orientation, config.touch_primary)
+                .map(|p| apply_offset(p, (config.offset_x, config.offset_y), ((0, 0), out_max)));
+            pen.emit(sink, position)?;
+            log_frame_progress(&mut frame_count, contact_count, false);
+            continue;
+        }
+
+        // A bound gesture leaves the touchpad and becomes a key combo
+        if let (Some(swipes), Some(keys)) = (&mut swipes, &mut keys) {
+            let centre = slots.centre(device, config.touch_orientation);
+            if let Some(combo) = swipes.update(contact_count, centre) {
+                log::debug!("[touch] {}-finger swipe: {:?}", contact_count, combo);
+                for frame in gesture::combo_frames(combo) {
+                    keys.write_frame(&frame)?;
+                }
+            }
+            if swipes.in_gesture() {
+                emit_palm_suppression(sink, &mut slots, false)?;
+                last_touching = 0;
+                continue;
+            }
+        }
+
+        // Thin out small moves (min_position_delta) and keep to max_event_rate, but never
+        // fingers landing, lifting or hovering
+        let touching = if hover { slots.touching_count() } else { contact_count };
+        if touching == last_touching && is_small_move(&slots, config.min_position_delta) {
+            continue;
+        }
+        // About what emit_touch_frame sends: slot, x and y per finger, then the tool keys
+        let events = 3 * contact_count as usize + 6;
+        if touching != last_touching {
+            throttle::charge(events);
+        } else if !throttle::admit(Stream::Touch, events) {
+            status::TOUCH.throttled();
+            continue;
+        }
+        last_touching = touching;
+
+        emit_touch_frame(sink, &mut slots, &mut next_tracking_id, device, config, &area, hover)?;
         log_frame_progress(&mut frame_count, contact_count, false);
     }
 }
 
+/// Current position of the first active contact, in output coordinates.
+fn primary_output_position(
+    slots: &SlotState,
+    device: &DeviceProfile,
+    orientation: Orientation,
+    policy: PrimaryContact,
+) -> Option<(i32, i32)> {
+    let slot = slots.primary_slot(policy)?;
+    let (x, y) = slots.get_position(slot)?;
+    Some(orientation.transform_touch(
+        x.clamp(0, device.touch_x_max),
+        y.clamp(0, device.touch_y_max),
+        device.touch_x_max,
+        device.touch_y_max,
+    ))
+}
+
 fn process_abs_event(slots: &mut SlotState, frame: &mut FrameState, code: u16, value: i32) {
     match code {
         ABS_MT_SLOT => {
@@ -204,8 +754,8 @@ fn process_abs_event(slots: &mut SlotState, frame: &mut FrameState, code: u16, v
             if value >= 0 {
                 if !slots.active[slot] {
                     frame.contact_count += 1;
+                    slots.land(slot);
                 }
-                slots.active[slot] = true;
             } else {
                 if slots.active[slot] {
                     frame.contact_count = frame.contact_count.saturating_sub(1);
@@ -228,6 +778,12 @@ fn process_abs_event(slots: &mut SlotState, frame: &mut FrameState, code: u16, v
                 frame.pending_positions.push((x, value));
             }
         }
+        ABS_MT_DISTANCE => {
+            slots.distance[frame.current_slot] = Some(value);
+        }
+        ABS_MT_TOUCH_MAJOR => {
+            slots.major[frame.current_slot] = Some(value);
+        }
         _ => {}
     }
 }
@@ -236,7 +792,7 @@ fn activate_slot_if_needed(slots: &mut SlotState, frame: &mut FrameState, slot:
     if slots.active[slot] {
         return;
     }
-    slots.active[slot] = true;
+    slots.land(slot);
     frame.contact_count += 1;
 }
 
@@ -262,7 +818,9 @@ fn resolve_pending_positions(slots: &mut SlotState, frame: &FrameState) {
     }
 }
 
-fn should_suppress_palm(palm: &Option<SharedPalmState>, grace_ms: u64) -> bool {
+/// With `from_activity`, the grace window runs from the later of pen-up and
+/// the last pen frame, so a hovering pen keeps touch suppressed.
+fn should_suppress_palm(palm: &Option<SharedPalmState>, grace_ms: u64, from_activity: bool) -> bool {
     let Some(palm_state) = palm else { return false };
     let Ok(state) = palm_state.lock() else { return false };
 
@@ -270,116 +828,221 @@ fn should_suppress_palm(palm: &Option<SharedPalmState>, grace_ms: u64) -> bool {
         return true;
     }
 
-    state
-        .last_pen_up
+    let start = if from_activity {
+        state.last_pen_up.max(state.last_pen_activity)
+    } else {
+        state.last_pen_up
+    };
+    start
         .map(|t| t.elapsed().as_millis() < grace_ms as u128)
         .unwrap_or(false)
 }
 
+/// Lift every forwarded contact and release all tool keys in one frame.
+///
+/// The legacy ABS_X/ABS_Y are left at their last value on purpose: with
+/// BTN_TOUCH released, libinput (and the kernel's own pointer emulation)
+/// ignores the absolute position, and moving it to a "neutral" corner would
+/// only make consumers that do track it jump there.
+///
+/// With `cancel` (`palm_cancel`), the contacts are first turned into palms
+/// (ABS_MT_TOOL_TYPE = MT_TOOL_PALM) in a frame of their own, the way the
+/// kernel's multi-touch protocol asks drivers to report a rejected contact.
+/// libinput then lifts them as palms rather than fingers: touchpad taps and
+/// gestures in progress are dropped instead of completed, and a touchscreen
+/// sends a touch cancel instead of a touch up.
 fn emit_palm_suppression(
-    uinput: &UinputDevice,
+    sink: &mut impl EventSink,
     slots: &mut SlotState,
+    cancel: bool,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut writer = uinput.writer();
+    let tracked = || (0..MT_SLOTS).filter(|&slot| slots.tracking_id[slot].is_some());
+    if cancel && tracked().next().is_some() {
+        let palms: Vec<InputEvent> = tracked()
+            .flat_map(|slot| [abs_event(Abs::MT_SLOT, slot as i32), abs_event(Abs::MT_TOOL_TYPE, MT_TOOL_PALM)])
+            .collect();
+        sink.write_frame(&palms)?;
+    }
+
+    let mut events = Vec::with_capacity(2 * MT_SLOTS + 5);
 
     for slot in 0..MT_SLOTS {
         if slots.tracking_id[slot].is_none() {
             continue;
         }
 
-        let slot_writer = writer.slot(Slot::from(slot as u16))?;
-        writer = slot_writer
-            .write(&[evdevil::event::AbsEvent::new(Abs::MT_TRACKING_ID, -1).into()])?
-            .finish_slot()?;
+        events.push(abs_event(Abs::MT_SLOT, slot as i32));
+        events.push(abs_event(Abs::MT_TRACKING_ID, -1));
         slots.tracking_id[slot] = None;
     }
 
-    let key_events = release_all_tool_keys();
-    writer = writer.write(&key_events)?;
-    writer.finish()?;
+    events.extend(release_all_tool_keys());
+    sink.write_frame(&events)?;
 
     Ok(())
 }
 
 fn emit_touch_frame(
-    uinput: &UinputDevice,
+    sink: &mut impl EventSink,
     slots: &mut SlotState,
     next_tracking_id: &mut i32,
     device: &DeviceProfile,
-    orientation: Orientation,
+    config: &Config,
+    area: &screen::Fit,
+    hover: bool,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut writer = uinput.writer();
-    let contact_count = slots.active_count();
-    let (out_x_max, out_y_max) = orientation.touch_output_dimensions(device.touch_x_max, device.touch_y_max);
+    let orientation = config.touch_orientation;
+    let mut events = Vec::with_capacity(5 * MT_SLOTS + 7);
+    let contact_count = if hover { slots.touching_count() } else { slots.visible_count() };
+    let hovering = hover && contact_count == 0 && slots.visible_count() > 0;
+    let contact_count = config.max_fingers.map_or(contact_count, |max| contact_count.min(max as i32));
+    let over_limit = slots.over_limit(config.max_fingers, config.touch_primary);
+    let offset = (config.offset_x + area.offset.0, config.offset_y + area.offset.1);
+    let region = area.region();
 
     for slot in 0..MT_SLOTS {
+        if slots.held[slot] {
+            continue;
+        }
+        // Over max_fingers: not forwarded, and retired if it was
+        if over_limit.contains(&slot) {
+            if slots.tracking_id[slot].take().is_some() {
+                events.push(abs_event(Abs::MT_SLOT, slot as i32));
+                events.push(abs_event(Abs::MT_TRACKING_ID, -1));
+            }
+            continue;
+        }
         if slots.active[slot] {
             let is_new = slots.tracking_id[slot].is_none();
             if is_new {
-                *next_tracking_id = next_tracking_id.wrapping_add(1);
+                *next_tracking_id = slots.next_tracking_id(*next_tracking_id, TRACKING_ID_MAX);
                 slots.tracking_id[slot] = Some(*next_tracking_id);
             }
 
-            let Some((ax, ay)) = slots.get_position(slot) else {
+            let Some((mut ax, mut ay)) = slots.get_position(slot) else {
                 continue;
             };
 
-            let (out_x, out_y) = orientation.transform_touch(
-                ax.clamp(0, device.touch_x_max),
-                ay.clamp(0, device.touch_y_max),
-                device.touch_x_max,
-                device.touch_y_max,
+            // Hold a resting contact still until it leaves the deadzone
+            if let (Some(lx), Some(ly)) = (slots.last_x[slot], slots.last_y[slot]) {
+                if !is_new && within_deadzone((ax - lx, ay - ly), config.touch_motion_deadzone) {
+                    (ax, ay) = (lx, ly);
+                }
+            }
+
+            let to_output = |x: i32, y: i32| {
+                let transformed = orientation.transform_touch(
+                    x.clamp(0, device.touch_x_max),
+                    y.clamp(0, device.touch_y_max),
+                    device.touch_x_max,
+                    device.touch_y_max,
+                );
+                apply_offset(transformed, offset, region)
+            };
+            let (out_x, out_y) = to_output(ax, ay);
+            // The macro checks the level first, so this formats nothing unless tracing
+            log::trace!(
+                "[touch] slot {} id {} raw ({}, {}) -> ({}, {})",
+                slot,
+                slots.tracking_id[slot].unwrap_or(-1),
+                ax,
+                ay,
+                out_x,
+                out_y
             );
-            let out_x = out_x.clamp(0, out_x_max);
-            let out_y = out_y.clamp(0, out_y_max);
+
+            // touch_changed_only: skip the axes the consumer already has
+            let previous = match (slots.last_x[slot], slots.last_y[slot]) {
+                (Some(lx), Some(ly)) if config.touch_changed_only && !is_new => Some(to_output(lx, ly)),
+                _ => None,
+            };
             slots.last_x[slot] = Some(ax);
             slots.last_y[slot] = Some(ay);
 
-            let slot_writer = writer.slot(Slot::from(slot as u16))?;
+            let send_x = previous.is_none_or(|(px, _)| px != out_x);
+            let send_y = previous.is_none_or(|(_, py)| py != out_y);
+            if !send_x && !send_y && !hover {
+                continue;
+            }
+
+            events.push(abs_event(Abs::MT_SLOT, slot as i32));
             if is_new {
                 let id = slots.tracking_id[slot].unwrap();
-                writer = slot_writer
-                    .write(&[
-                        evdevil::event::AbsEvent::new(Abs::MT_TRACKING_ID, id).into(),
-                        evdevil::event::AbsEvent::new(Abs::MT_POSITION_X, out_x).into(),
-                        evdevil::event::AbsEvent::new(Abs::MT_POSITION_Y, out_y).into(),
-                    ])?
-                    .finish_slot()?;
-            } else {
-                writer = slot_writer
-                    .write(&[
-                        evdevil::event::AbsEvent::new(Abs::MT_POSITION_X, out_x).into(),
-                        evdevil::event::AbsEvent::new(Abs::MT_POSITION_Y, out_y).into(),
-                    ])?
-                    .finish_slot()?;
+                events.push(abs_event(Abs::MT_TRACKING_ID, id));
+                // The slot may still be a palm from the last suppression
+                if config.palm_cancel {
+                    events.push(abs_event(Abs::MT_TOOL_TYPE, MT_TOOL_FINGER));
+                }
+            }
+            if send_x {
+                events.push(abs_event(Abs::MT_POSITION_X, out_x));
+            }
+            if send_y {
+                events.push(abs_event(Abs::MT_POSITION_Y, out_y));
             }
-        } else if slots.tracking_id[slot].is_some() {
-            let slot_writer = writer.slot(Slot::from(slot as u16))?;
-            writer = slot_writer
-                .write(&[evdevil::event::AbsEvent::new(Abs::MT_TRACKING_ID, -1).into()])?
-                .finish_slot()?;
+
+            if hover {
+                let distance = slots.distance[slot].unwrap_or(0).max(0);
+                events.push(abs_event(Abs::MT_DISTANCE, distance));
+            }
+        } else if slots.tracking_id[slot].is_some() && !slots.lift_pending[slot] {
+            events.push(abs_event(Abs::MT_SLOT, slot as i32));
+            events.push(abs_event(Abs::MT_TRACKING_ID, -1));
             slots.tracking_id[slot] = None;
         }
     }
 
-    if let Some((out_x, out_y)) = slots.get_primary_position(device, orientation) {
-        writer = writer.write(&[
-            evdevil::event::AbsEvent::new(Abs::X, out_x).into(),
-            evdevil::event::AbsEvent::new(Abs::Y, out_y).into(),
-        ])?;
+    if !config.touch_mt_only {
+        if let Some(primary) = slots.get_primary_position(device, orientation, config.touch_primary) {
+            let (out_x, out_y) = apply_offset(primary, offset, region);
+            events.push(abs_event(Abs::X, out_x));
+            events.push(abs_event(Abs::Y, out_y));
+        }
     }
 
-    let key_events = build_tool_key_events(contact_count);
-    writer = writer.write(&key_events)?;
-    writer.finish()?;
+    events.extend(build_tool_key_events(contact_count, hovering));
+    sink.write_frame(&events)?;
 
     Ok(())
 }
 
-fn build_tool_key_events(contact_count: i32) -> Vec<evdevil::event::InputEvent> {
+/// Whether every contact that was already forwarded is still down and moved
+/// less than `min_delta` since then.
+fn is_small_move(slots: &SlotState, min_delta: i32) -> bool {
+    if min_delta <= 0 {
+        return false;
+    }
+    (0..MT_SLOTS).all(|slot| {
+        if slots.active[slot] != slots.tracking_id[slot].is_some() {
+            return false;
+        }
+        if !slots.active[slot] {
+            return true;
+        }
+        match (slots.last_x[slot].zip(slots.last_y[slot]), slots.get_position(slot)) {
+            (Some(last), Some(position)) => below_min_delta(last, position, min_delta),
+            _ => false,
+        }
+    })
+}
+
+/// Whether a movement of `(dx, dy)` device units stays inside `deadzone`.
+fn within_deadzone((dx, dy): (i32, i32), deadzone: i32) -> bool {
+    let (dx, dy) = (dx as i64, dy as i64);
+    dx * dx + dy * dy <= (deadzone as i64) * (deadzone as i64)
+}
+
+fn abs_event(axis: Abs, value: i32) -> InputEvent {
+    AbsEvent::new(axis, value).into()
+}
+
+/// Tool keys for the current contact count. A hovering finger with no
+/// contacts reports `BTN_TOOL_FINGER` without `BTN_TOUCH`.
+fn build_tool_key_events(contact_count: i32, hovering: bool) -> Vec<evdevil::event::InputEvent> {
     let finger_down = contact_count > 0;
 
     let tool_key = match contact_count {
+        0 if hovering => Some(Key::BTN_TOOL_FINGER),
         0 => None,
         1 => Some(Key::BTN_TOOL_FINGER),
         2 => Some(Key::BTN_TOOL_DOUBLETAP),
@@ -425,15 +1088,790 @@ fn release_all_tool_keys() -> Vec<evdevil::event::InputEvent> {
 
 fn log_frame_progress(frame_count: &mut u64, contact_count: i32, suppressed: bool) {
     if *frame_count == 0 {
-        log::info!("Touch events flowing");
+        log::info!("[touch] Events flowing");
     }
     *frame_count += 1;
+    status::TOUCH.frame(contact_count, suppressed);
 
     if (*frame_count).is_multiple_of(500) {
+        let counts = status::TOUCH.frame_counts();
         if suppressed {
-            log::debug!("Touch frames: {} (palm suppressed)", frame_count);
+            log::debug!("[touch] Frames: {} ({}; palm suppressed)", frame_count, counts);
         } else {
-            log::debug!("Touch frames: {}, contacts: {}", frame_count, contact_count);
+            log::debug!("[touch] Frames: {} ({}), contacts: {}", frame_count, counts, contact_count);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::RM2;
+    use crate::input::event::{raw_event, ABS_PRESSURE, ABS_X, ABS_Y};
+    use crate::input::pen;
+    use crate::input::RecordingSink;
+    use crate::palm::PalmState;
+    use std::sync::{Arc, Mutex};
+
+    fn replay_tap(config: &Config) -> Vec<Vec<InputEvent>> {
+        let stream: Vec<u8> = [
+            raw_event(EV_ABS, ABS_MT_SLOT, 0),
+            raw_event(EV_ABS, ABS_MT_TRACKING_ID, 1),
+            raw_event(EV_ABS, ABS_MT_POSITION_X, 700),
+            raw_event(EV_ABS, ABS_MT_POSITION_Y, 900),
+            raw_event(EV_SYN, SYN_REPORT, 0),
+            raw_event(EV_ABS, ABS_MT_TRACKING_ID, -1),
+            raw_event(EV_SYN, SYN_REPORT, 0),
+        ]
+        .concat();
+
+        let mut sink = RecordingSink::default();
+        let result = run_event_loop(&mut stream.as_slice(), &mut sink, &RM2, config, None, false, None);
+        assert!(result.is_err(), "loop only ends at end of stream");
+        sink.frames
+    }
+
+    fn has_axis(frames: &[Vec<InputEvent>], axis: Abs) -> bool {
+        frames
+            .iter()
+            .flatten()
+            .any(|e| e.event_type().raw() == EV_ABS && e.raw_code() == axis.raw())
+    }
+
+    fn positions(frames: &[Vec<InputEvent>]) -> Vec<(i32, i32)> {
+        let value = |frame: &[InputEvent], axis: Abs| {
+            frame
+                .iter()
+                .find(|e| e.event_type().raw() == EV_ABS && e.raw_code() == axis.raw())
+                .map(|e| e.raw_value())
+        };
+        frames
+            .iter()
+            .filter_map(|f| value(f, Abs::MT_POSITION_X).zip(value(f, Abs::MT_POSITION_Y)))
+            .collect()
+    }
+
+    #[test]
+    fn test_within_deadzone() {
+        assert!(within_deadzone((0, 0), 0));
+        assert!(!within_deadzone((1, 0), 0));
+        assert!(within_deadzone((3, 4), 5));
+        assert!(!within_deadzone((4, 4), 5));
+    }
+
+    #[test]
+    fn test_touch_motion_deadzone() {
+        let mut stream = vec![
+            raw_event(EV_ABS, ABS_MT_SLOT, 0),
+            raw_event(EV_ABS, ABS_MT_TRACKING_ID, 1),
+            raw_event(EV_ABS, ABS_MT_POSITION_X, 700),
+            raw_event(EV_ABS, ABS_MT_POSITION_Y, 900),
+            raw_event(EV_SYN, SYN_REPORT, 0),
+        ];
+        for x in [702, 698, 720] {
+            stream.push(raw_event(EV_ABS, ABS_MT_POSITION_X, x));
+            stream.push(raw_event(EV_SYN, SYN_REPORT, 0));
+        }
+        let stream = stream.concat();
+
+        let mut config = Config::defaults(&RM2);
+        config.touch_motion_deadzone = 5;
+
+        let mut sink = RecordingSink::default();
+        let _ = run_event_loop(&mut stream.as_slice(), &mut sink, &RM2, &config, None, false, None);
+
+        // Jitter of 2 units is held at the touch-down position; 20 units gets through
+        let emitted = positions(&sink.frames);
+        assert_eq!(emitted.len(), 4);
+        assert!(emitted[1..3].iter().all(|&p| p == emitted[0]));
+        assert_ne!(emitted[3], emitted[0]);
+    }
+
+    #[test]
+    fn test_touch_min_position_delta() {
+        let mut stream = vec![
+            raw_event(EV_ABS, ABS_MT_SLOT, 0),
+            raw_event(EV_ABS, ABS_MT_TRACKING_ID, 1),
+            raw_event(EV_ABS, ABS_MT_POSITION_X, 700),
+            raw_event(EV_ABS, ABS_MT_POSITION_Y, 900),
+            raw_event(EV_SYN, SYN_REPORT, 0),
+        ];
+        for x in [703, 706, 711, 712] {
+            stream.push(raw_event(EV_ABS, ABS_MT_POSITION_X, x));
+            stream.push(raw_event(EV_SYN, SYN_REPORT, 0));
+        }
+        stream.push(raw_event(EV_ABS, ABS_MT_TRACKING_ID, -1));
+        stream.push(raw_event(EV_SYN, SYN_REPORT, 0));
+        let stream = stream.concat();
+
+        let mut config = Config::defaults(&RM2);
+        config.min_position_delta = 10;
+
+        let mut sink = RecordingSink::default();
+        let _ = run_event_loop(&mut stream.as_slice(), &mut sink, &RM2, &config, None, false, None);
+
+        // Touch-down, the move to 711 (11 units from 700), and the lift
+        assert_eq!(sink.frames.len(), 3);
+        assert_eq!(positions(&sink.frames).len(), 2);
+        let lifted = |e: &InputEvent| e.raw_code() == ABS_MT_TRACKING_ID && e.raw_value() == -1;
+        assert!(sink.frames[2].iter().any(lifted));
+    }
+
+    #[test]
+    fn test_touch_uses_its_own_orientation() {
+        let mut config = Config::defaults(&RM2);
+        config.pen_orientation = Orientation::Portrait;
+        for orientation in [Orientation::LandscapeLeft, Orientation::Inverted] {
+            config.touch_orientation = orientation;
+            let expected = orientation.transform_touch(700, 900, RM2.touch_x_max, RM2.touch_y_max);
+            assert_eq!(positions(&replay_tap(&config)), [expected]);
+        }
+    }
+
+    #[test]
+    fn test_offset_applied_after_orientation() {
+        let mut config = Config::defaults(&RM2);
+        config.touch_orientation = Orientation::LandscapeRight;
+        let (x, y) = config.touch_orientation.transform_touch(700, 900, RM2.touch_x_max, RM2.touch_y_max);
+        let (x_max, y_max) = config.touch_orientation.touch_output_dimensions(RM2.touch_x_max, RM2.touch_y_max);
+
+        config.offset_x = 30;
+        config.offset_y = -20;
+        assert_eq!(positions(&replay_tap(&config)), [(x + 30, y - 20)]);
+
+        // Pushed past the edges, the position sticks to them
+        config.offset_x = x_max;
+        config.offset_y = -y_max;
+        assert_eq!(positions(&replay_tap(&config)), [(x_max, 0)]);
+    }
+
+    #[test]
+    fn test_fit_screen_moves_touchscreen_only() {
+        let mut config = Config::defaults(&RM2);
+        config.fit_screen = true;
+        let (x_max, y_max) = config.touch_orientation.touch_output_dimensions(RM2.touch_x_max, RM2.touch_y_max);
+        config.screen_size = Some(format!("{}x{}", x_max * 2, y_max));
+        let (x, y) = config.touch_orientation.transform_touch(700, 900, RM2.touch_x_max, RM2.touch_y_max);
+
+        config.touch_mode = TouchMode::Touchpad;
+        assert_eq!(positions(&replay_tap(&config)), [(x, y)]);
+
+        // The tablet sits in the middle of the screen; offsets stop at its edges
+        config.touch_mode = TouchMode::Touchscreen;
+        let area = touch_area(&config, &RM2).unwrap();
+        assert_eq!(positions(&replay_tap(&config)), [(x + area.offset.0, y)]);
+        config.offset_x = -x_max;
+        assert_eq!(positions(&replay_tap(&config)), [(area.offset.0, y)]);
+    }
+
+    #[test]
+    fn test_touch_as_pen() {
+        let mut config = Config::defaults(&RM2);
+        config.touch_as_pen = true;
+        let frames = replay_tap(&config);
+
+        let key = |frame: &[InputEvent], key: Key| {
+            frame
+                .iter()
+                .find(|e| e.event_type().raw() == EV_KEY && e.raw_code() == key.raw())
+                .map(|e| e.raw_value())
+        };
+        assert_eq!(frames.len(), 2);
+        assert_eq!(key(&frames[0], Key::BTN_TOOL_PEN), Some(1));
+        assert!(has_axis(&frames[..1], Abs::X) && has_axis(&frames[..1], Abs::PRESSURE));
+        assert_eq!(key(&frames[1], Key::BTN_TOUCH), Some(0));
+        assert!(!has_axis(&frames, Abs::MT_POSITION_X));
+    }
+
+    #[test]
+    fn test_touch_mt_only_skips_legacy_axes() {
+        let mut config = Config::defaults(&RM2);
+        let frames = replay_tap(&config);
+        assert!(has_axis(&frames, Abs::X) && has_axis(&frames, Abs::Y));
+
+        config.touch_mt_only = true;
+        let frames = replay_tap(&config);
+        assert!(!has_axis(&frames, Abs::X) && !has_axis(&frames, Abs::Y));
+        assert!(has_axis(&frames, Abs::MT_POSITION_X) && has_axis(&frames, Abs::MT_POSITION_Y));
+    }
+
+    #[test]
+    fn test_palm_grace_from_activity() {
+        let pen_frame = |pressure: i32| {
+            [
+                raw_event(EV_ABS, ABS_X, 1000),
+                raw_event(EV_ABS, ABS_Y, 1000),
+                raw_event(EV_ABS, ABS_PRESSURE, pressure),
+                raw_event(EV_SYN, SYN_REPORT, 0),
+            ]
+            .concat()
+        };
+        let config = Config::defaults(&RM2);
+        let palm = Some(Arc::new(Mutex::new(PalmState::new())));
+        let run_pen = |stream: Vec<u8>| {
+            let mut sink = RecordingSink::default();
+            let _ = pen::run_event_loop(&mut stream.as_slice(), &mut sink, &RM2, &config, palm.clone(), None);
+        };
+
+        // Touch and lift, then hover once the lift's grace window is over
+        run_pen([raw_event(EV_KEY, Key::BTN_TOOL_PEN.raw(), 1), pen_frame(500), pen_frame(0)].concat());
+        std::thread::sleep(Duration::from_millis(100));
+        run_pen(pen_frame(0));
+
+        assert!(!should_suppress_palm(&palm, 50, false));
+        assert!(should_suppress_palm(&palm, 50, true));
+    }
+
+    /// Per-slot (tracking id, x, y) as a consumer sees it after each frame.
+    fn mt_states(frames: &[Vec<InputEvent>]) -> Vec<Vec<Option<(i32, i32, i32)>>> {
+        let mut state = vec![None; MT_SLOTS];
+        let mut slot = 0;
+        frames
+            .iter()
+            .map(|frame| {
+                for e in frame.iter().filter(|e| e.event_type().raw() == EV_ABS) {
+                    let value = e.raw_value();
+                    let current: &mut Option<(i32, i32, i32)> = &mut state[slot];
+                    match e.raw_code() {
+                        ABS_MT_SLOT => slot = value as usize,
+                        ABS_MT_TRACKING_ID if value < 0 => *current = None,
+                        ABS_MT_TRACKING_ID => *current = Some((value, 0, 0)),
+                        ABS_MT_POSITION_X => current.as_mut().unwrap().1 = value,
+                        ABS_MT_POSITION_Y => current.as_mut().unwrap().2 = value,
+                        _ => {}
+                    }
+                }
+                state.clone()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_touch_changed_only_keeps_scroll_state() {
+        let abs = |code, value| raw_event(EV_ABS, code, value);
+        let syn = || raw_event(EV_SYN, SYN_REPORT, 0);
+        // Like evdev, after the landing only the axes that changed are reported
+        let stream: Vec<u8> = [
+            abs(ABS_MT_SLOT, 0),
+            abs(ABS_MT_TRACKING_ID, 1),
+            abs(ABS_MT_POSITION_X, 600),
+            abs(ABS_MT_POSITION_Y, 800),
+            abs(ABS_MT_SLOT, 1),
+            abs(ABS_MT_TRACKING_ID, 2),
+            abs(ABS_MT_POSITION_X, 800),
+            abs(ABS_MT_POSITION_Y, 810),
+            syn(),
+            abs(ABS_MT_SLOT, 0),
+            abs(ABS_MT_POSITION_Y, 840),
+            abs(ABS_MT_SLOT, 1),
+            abs(ABS_MT_POSITION_Y, 850),
+            syn(),
+            abs(ABS_MT_POSITION_Y, 890),
+            syn(),
+            abs(ABS_MT_SLOT, 0),
+            abs(ABS_MT_POSITION_X, 610),
+            abs(ABS_MT_POSITION_Y, 880),
+            syn(),
+            abs(ABS_MT_TRACKING_ID, -1),
+            syn(),
+            abs(ABS_MT_SLOT, 1),
+            abs(ABS_MT_TRACKING_ID, -1),
+            syn(),
+        ]
+        .concat();
+        let replay = |config: &Config| {
+            let mut sink = RecordingSink::default();
+            let result = run_event_loop(&mut stream.as_slice(), &mut sink, &RM2, config, None, false, None);
+            assert!(result.is_err(), "loop only ends at end of stream");
+            sink.frames
+        };
+
+        let full = replay(&Config::defaults(&RM2));
+        let mut config = Config::defaults(&RM2);
+        config.touch_changed_only = true;
+        let changed = replay(&config);
+
+        // Same fingers at the same places after every frame, two of them during the scroll
+        assert_eq!(mt_states(&full), mt_states(&changed));
+        assert!(mt_states(&changed).iter().any(|s| s.iter().flatten().count() == 2));
+        let count = |frames: &[Vec<InputEvent>]| frames.iter().map(Vec::len).sum::<usize>();
+        assert!(count(&changed) < count(&full));
+    }
+
+    /// `raw_event` stamped with an event time of `ms`.
+    fn event_at(ms: u32, ty: u16, code: u16, value: i32) -> Vec<u8> {
+        let mut buf = raw_event(ty, code, value);
+        buf[0..4].copy_from_slice(&(ms / 1000).to_le_bytes());
+        buf[4..8].copy_from_slice(&(ms % 1000 * 1000).to_le_bytes());
+        buf
+    }
+
+    fn has_key(frame: &[InputEvent], key: Key, value: i32) -> bool {
+        frame
+            .iter()
+            .any(|e| e.event_type().raw() == EV_KEY && e.raw_code() == key.raw() && e.raw_value() == value)
+    }
+
+    #[test]
+    fn test_gesture_hold_hides_brushing_finger() {
+        let finger = |ms, slot, id| {
+            [
+                event_at(ms, EV_ABS, ABS_MT_SLOT, slot),
+                event_at(ms, EV_ABS, ABS_MT_TRACKING_ID, id),
+                event_at(ms, EV_ABS, ABS_MT_POSITION_X, 700 + 100 * slot),
+                event_at(ms, EV_ABS, ABS_MT_POSITION_Y, 900),
+                event_at(ms, EV_SYN, SYN_REPORT, 0),
+            ]
+            .concat()
+        };
+        let lift = |ms, slot| {
+            [
+                event_at(ms, EV_ABS, ABS_MT_SLOT, slot),
+                event_at(ms, EV_ABS, ABS_MT_TRACKING_ID, -1),
+                event_at(ms, EV_SYN, SYN_REPORT, 0),
+            ]
+            .concat()
+        };
+        let sync = |ms| event_at(ms, EV_SYN, SYN_REPORT, 0);
+        let stream: Vec<u8> = [finger(0, 0, 1), finger(10, 1, 2), lift(40, 1), finger(200, 1, 3), sync(250), sync(320)].concat();
+
+        let mut config = Config::defaults(&RM2);
+        config.gesture_hold_ms = 100;
+        let mut sink = RecordingSink::default();
+        let _ = run_event_loop(&mut stream.as_slice(), &mut sink, &RM2, &config, None, false, None);
+
+        let doubletap: Vec<bool> = sink.frames.iter().map(|f| has_key(f, Key::BTN_TOOL_DOUBLETAP, 1)).collect();
+        assert_eq!(doubletap, vec![false, false, false, false, false, true]);
+    }
+
+    #[test]
+    fn test_touch_lift_debounce() {
+        let finger = |ms, id| {
+            [
+                event_at(ms, EV_ABS, ABS_MT_SLOT, 0),
+                event_at(ms, EV_ABS, ABS_MT_TRACKING_ID, id),
+                event_at(ms, EV_ABS, ABS_MT_POSITION_X, 700 + ms as i32),
+                event_at(ms, EV_ABS, ABS_MT_POSITION_Y, 900),
+                event_at(ms, EV_SYN, SYN_REPORT, 0),
+            ]
+            .concat()
+        };
+        let lift = |ms| [event_at(ms, EV_ABS, ABS_MT_TRACKING_ID, -1), event_at(ms, EV_SYN, SYN_REPORT, 0)].concat();
+        let sync = |ms| event_at(ms, EV_SYN, SYN_REPORT, 0);
+        // A 20 ms dropout mid-drag, then a real lift
+        let stream: Vec<u8> = [finger(0, 1), lift(20), finger(40, 2), lift(100), sync(150), sync(300)].concat();
+
+        let mut config = Config::defaults(&RM2);
+        config.touch_lift_debounce_ms = 100;
+        let mut sink = RecordingSink::default();
+        let _ = run_event_loop(&mut stream.as_slice(), &mut sink, &RM2, &config, None, false, None);
+
+        let ids: Vec<Option<i32>> = mt_states(&sink.frames).iter().map(|s| s[0].map(|(id, _, _)| id)).collect();
+        let first = ids[0];
+        assert!(first.is_some());
+        assert_eq!(ids, vec![first, first, first, first, first, None], "bridged into one contact, retired after 100 ms");
+        let released: Vec<bool> = sink.frames.iter().map(|f| has_key(f, Key::BTN_TOUCH, 0)).collect();
+        assert_eq!(released, vec![false, false, false, false, false, true]);
+    }
+
+    #[test]
+    fn test_gesture_keys_take_over_bound_swipe() {
+        let frame = |x: i32| {
+            let mut events = Vec::new();
+            for slot in 0..3 {
+                events.push(raw_event(EV_ABS, ABS_MT_SLOT, slot));
+                events.push(raw_event(EV_ABS, ABS_MT_TRACKING_ID, slot + 1));
+                events.push(raw_event(EV_ABS, ABS_MT_POSITION_X, x + 100 * slot));
+                events.push(raw_event(EV_ABS, ABS_MT_POSITION_Y, 900));
+            }
+            events.push(raw_event(EV_SYN, SYN_REPORT, 0));
+            events.concat()
+        };
+        let stream: Vec<u8> = [frame(500), frame(600), frame(700), frame(800)].concat();
+
+        let mut config = Config::defaults(&RM2);
+        config.touch_orientation = Orientation::Portrait;
+        config.gesture_keys.insert("3-right".into(), "KEY_LEFTMETA+KEY_PAGEDOWN".into());
+        let mut sink = RecordingSink::default();
+        let mut keys = RecordingSink::default();
+        let _ = run_event_loop(&mut stream.as_slice(), &mut sink, &RM2, &config, None, false, Some(&mut keys));
+
+        assert!(positions(&sink.frames).is_empty(), "bound fingers never reach the touchpad");
+        assert_eq!(keys.frames.len(), 2, "one press and one release frame");
+        assert!(has_key(&keys.frames[0], Key::KEY_PAGEDOWN, 1) && has_key(&keys.frames[1], Key::KEY_LEFTMETA, 0));
+    }
+
+    #[test]
+    fn test_palm_suppression_lifts_everything() {
+        let config = Config::defaults(&RM2);
+        let area = touch_area(&config, &RM2).unwrap();
+        let mut slots = SlotState::new();
+        for (slot, x) in [(0, 700), (3, 1200)] {
+            slots.active[slot] = true;
+            slots.x[slot] = Some(x);
+            slots.y[slot] = Some(900);
+        }
+        let mut sink = RecordingSink::default();
+        let mut next_tracking_id = 0;
+        emit_touch_frame(&mut sink, &mut slots, &mut next_tracking_id, &RM2, &config, &area, false).unwrap();
+        emit_palm_suppression(&mut sink, &mut slots, false).unwrap();
+
+        let frame = &sink.frames[1];
+        let released: Vec<i32> = frame
+            .windows(2)
+            .filter(|w| w[0].raw_code() == ABS_MT_SLOT && w[1].raw_code() == ABS_MT_TRACKING_ID && w[1].raw_value() == -1)
+            .map(|w| w[0].raw_value())
+            .collect();
+        assert_eq!(released, vec![0, 3]);
+        for key in [Key::BTN_TOUCH, Key::BTN_TOOL_FINGER, Key::BTN_TOOL_DOUBLETAP, Key::BTN_TOOL_TRIPLETAP, Key::BTN_TOOL_QUADTAP] {
+            assert!(has_key(frame, key, 0), "{:?} released", key);
+        }
+        assert!(!has_axis(&sink.frames[1..], Abs::X) && !has_axis(&sink.frames[1..], Abs::Y));
+        assert!(slots.tracking_id.iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn test_tracking_ids_stay_positive() {
+        let mut slots = SlotState::new();
+        assert_eq!(slots.next_tracking_id(0, TRACKING_ID_MAX), 1);
+        assert_eq!(slots.next_tracking_id(TRACKING_ID_MAX, TRACKING_ID_MAX), 1);
+
+        // A long-held contact keeps its id while the others wrap around it
+        slots.tracking_id[2] = Some(2);
+        let mut last = 0;
+        for _ in 0..20 {
+            last = slots.next_tracking_id(last, 4);
+            assert!((1..=4).contains(&last) && last != 2, "got {}", last);
+            slots.tracking_id[0] = Some(last);
+        }
+        slots.tracking_id[1] = Some(3);
+        slots.tracking_id[0] = Some(4);
+        assert_eq!(slots.next_tracking_id(4, 4), 1);
+    }
+
+    #[test]
+    fn test_touchscreen_mode_capabilities() {
+        // udev's input_id: DIRECT with BTN_TOUCH is a touchscreen, BTN_TOOL_FINGER without DIRECT a touchpad
+        let screen = TouchMode::Touchscreen;
+        assert!(screen.props().contains(&InputProp::DIRECT) && !screen.props().contains(&InputProp::BUTTONPAD));
+        assert_eq!(screen.keys(), &[Key::BTN_TOUCH]);
+
+        let pad = TouchMode::Touchpad;
+        assert!(!pad.props().contains(&InputProp::DIRECT));
+        assert!(pad.keys().contains(&Key::BTN_TOOL_FINGER));
+
+        assert_eq!("touchscreen".parse::<TouchMode>(), Ok(TouchMode::Touchscreen));
+        assert!("screen".parse::<TouchMode>().is_err());
+    }
+
+    #[test]
+    fn test_hover_axis_takes_the_source_range() {
+        let config = Config::defaults(&RM2);
+        let out_max = touch_area(&config, &RM2).unwrap().max;
+        let distance = |hover| {
+            touchpad_axes(&config, out_max, 9, hover)
+                .iter()
+                .find(|a| a.abs() == Abs::MT_DISTANCE)
+                .map(|a| a.abs_info().maximum())
+        };
+        assert_eq!(distance(Some(63)), Some(63));
+        assert_eq!(distance(None), None);
+    }
+
+    /// What libinput needs to take the device as a touchpad or touchscreen
+    /// instead of ignoring it or guessing: resolutions on the position axes
+    /// (it measures gestures in mm), a slot per finger the tool keys can
+    /// count, and a clickpad with a single button.
+    #[test]
+    fn test_touch_device_meets_libinput_requirements() {
+        for mode in [TouchMode::Touchpad, TouchMode::Touchscreen] {
+            let mut config = Config::defaults(&RM2);
+            config.touch_mode = mode;
+            let axes = touchpad_axes(&config, touch_area(&config, &RM2).unwrap().max, touch_resolution(&config, &RM2), None);
+            let info = |abs: Abs| axes.iter().find(|a| a.abs() == abs).map(|a| *a.abs_info());
+
+            for abs in [Abs::X, Abs::Y, Abs::MT_POSITION_X, Abs::MT_POSITION_Y] {
+                let info = info(abs).unwrap();
+                assert!(info.maximum() > 0 && info.resolution() > 0, "{:?} in {}", abs, mode);
+            }
+            let slots = info(Abs::MT_SLOT).unwrap().maximum() + 1;
+            let fingers = [
+                Key::BTN_TOOL_FINGER,
+                Key::BTN_TOOL_DOUBLETAP,
+                Key::BTN_TOOL_TRIPLETAP,
+                Key::BTN_TOOL_QUADTAP,
+            ]
+            .iter()
+            .filter(|key| mode.keys().contains(key))
+            .count();
+            assert!(slots as usize >= fingers);
+            assert!(info(Abs::MT_TRACKING_ID).is_some());
+            assert!(mode.keys().contains(&Key::BTN_TOUCH));
+        }
+
+        let pad = TouchMode::Touchpad;
+        assert!(pad.props().contains(&InputProp::POINTER) && pad.props().contains(&InputProp::BUTTONPAD));
+        assert!(pad.keys().contains(&Key::BTN_LEFT) && !pad.keys().contains(&Key::BTN_RIGHT));
+        assert!(pad.keys().contains(&Key::BTN_TOOL_QUADTAP));
+    }
+
+    #[test]
+    fn test_primary_contact_policies() {
+        let mut slots = SlotState::new();
+        let mut frame = FrameState::new();
+        // Slot 3 lands first and is small; slot 1 lands second and is big
+        for (slot, major) in [(3, 10), (1, 40)] {
+            process_abs_event(&mut slots, &mut frame, ABS_MT_SLOT, slot);
+            process_abs_event(&mut slots, &mut frame, ABS_MT_TRACKING_ID, slot + 10);
+            process_abs_event(&mut slots, &mut frame, ABS_MT_TOUCH_MAJOR, major);
         }
+
+        assert_eq!(slots.primary_slot(PrimaryContact::LowestSlot), Some(1));
+        assert_eq!(slots.primary_slot(PrimaryContact::FirstDown), Some(3));
+        assert_eq!(slots.primary_slot(PrimaryContact::Largest), Some(1));
+
+        // A third finger lands in slot 0: first-down stays on slot 3
+        process_abs_event(&mut slots, &mut frame, ABS_MT_SLOT, 0);
+        process_abs_event(&mut slots, &mut frame, ABS_MT_TRACKING_ID, 20);
+        assert_eq!(slots.primary_slot(PrimaryContact::LowestSlot), Some(0));
+        assert_eq!(slots.primary_slot(PrimaryContact::FirstDown), Some(3));
+
+        // The first finger lifts: first-down moves on to the next oldest
+        process_abs_event(&mut slots, &mut frame, ABS_MT_SLOT, 3);
+        process_abs_event(&mut slots, &mut frame, ABS_MT_TRACKING_ID, -1);
+        assert_eq!(slots.primary_slot(PrimaryContact::FirstDown), Some(1));
+
+        // Without size reports, largest falls back to the lowest slot
+        let mut slots = SlotState::new();
+        slots.land(5);
+        slots.land(2);
+        assert_eq!(slots.primary_slot(PrimaryContact::Largest), Some(2));
+    }
+
+    #[test]
+    fn test_skip_repeated_frames() {
+        let stream: Vec<u8> = [
+            raw_event(EV_ABS, ABS_MT_SLOT, 0),
+            raw_event(EV_ABS, ABS_MT_TRACKING_ID, 7),
+            raw_event(EV_ABS, ABS_MT_POSITION_X, 500),
+            raw_event(EV_ABS, ABS_MT_POSITION_Y, 600),
+            raw_event(EV_SYN, SYN_REPORT, 0),
+            // Empty reports repeat the frame
+            raw_event(EV_SYN, SYN_REPORT, 0),
+            raw_event(EV_SYN, SYN_REPORT, 0),
+            raw_event(EV_ABS, ABS_MT_POSITION_X, 520),
+            raw_event(EV_SYN, SYN_REPORT, 0),
+        ]
+        .concat();
+        let replay = |config: &Config| {
+            let mut sink = RecordingSink::default();
+            let _ = run_event_loop(&mut stream.as_slice(), &mut sink, &RM2, config, None, false, None);
+            sink.frames
+        };
+
+        let all = replay(&Config::defaults(&RM2));
+        let mut config = Config::defaults(&RM2);
+        config.skip_repeated_frames = true;
+        let skipped = replay(&config);
+
+        assert_eq!(all.len(), 4);
+        assert_eq!(skipped.len(), 2);
+        assert_eq!(mt_states(&skipped).last(), mt_states(&all).last());
+    }
+
+    /// Replays `stream`, setting the pen down or up once `switches[i].0` bytes have been read.
+    struct PenSwitches<'a> {
+        stream: &'a [u8],
+        read: usize,
+        switches: Vec<(usize, bool)>,
+        palm: SharedPalmState,
+    }
+
+    impl Read for PenSwitches<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            for &(at, down) in &self.switches {
+                if at == self.read {
+                    self.palm.lock().unwrap().pen_down = down;
+                }
+            }
+            let next = self.switches.iter().map(|&(at, _)| at).find(|&at| at > self.read);
+            let len = buf.len().min(next.unwrap_or(usize::MAX) - self.read);
+            let n = (&self.stream[self.read..]).read(&mut buf[..len])?;
+            self.read += n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_palm_cancel_marks_palms_before_lifting() {
+        let frames: [Vec<u8>; 4] = [
+            [
+                raw_event(EV_ABS, ABS_MT_SLOT, 0),
+                raw_event(EV_ABS, ABS_MT_TRACKING_ID, 1),
+                raw_event(EV_ABS, ABS_MT_POSITION_X, 700),
+                raw_event(EV_ABS, ABS_MT_POSITION_Y, 900),
+                raw_event(EV_SYN, SYN_REPORT, 0),
+            ]
+            .concat(),
+            // The pen comes down while the finger moves
+            [raw_event(EV_ABS, ABS_MT_POSITION_X, 720), raw_event(EV_SYN, SYN_REPORT, 0)].concat(),
+            [raw_event(EV_ABS, ABS_MT_TRACKING_ID, -1), raw_event(EV_SYN, SYN_REPORT, 0)].concat(),
+            [
+                raw_event(EV_ABS, ABS_MT_TRACKING_ID, 2),
+                raw_event(EV_ABS, ABS_MT_POSITION_X, 800),
+                raw_event(EV_ABS, ABS_MT_POSITION_Y, 900),
+                raw_event(EV_SYN, SYN_REPORT, 0),
+            ]
+            .concat(),
+        ];
+        let stream = frames.concat();
+        let pen_down_at = frames[0].len();
+        let pen_up_at = pen_down_at + frames[1].len();
+
+        let replay = |palm_cancel: bool| {
+            let mut config = Config::defaults(&RM2);
+            config.palm_cancel = palm_cancel;
+            config.palm_grace_ms = 0;
+            let palm: SharedPalmState = Arc::new(Mutex::new(PalmState::default()));
+            let mut reader = PenSwitches {
+                stream: &stream,
+                read: 0,
+                switches: vec![(pen_down_at, true), (pen_up_at, false)],
+                palm: palm.clone(),
+            };
+            let mut sink = RecordingSink::default();
+            let _ = run_event_loop(&mut reader, &mut sink, &RM2, &config, Some(palm), false, None);
+            sink.frames
+        };
+        let tool_types = |frame: &[InputEvent]| -> Vec<i32> {
+            frame
+                .iter()
+                .filter(|e| e.event_type().raw() == EV_ABS && e.raw_code() == Abs::MT_TOOL_TYPE.raw())
+                .map(|e| e.raw_value())
+                .collect()
+        };
+        let lifts = |frame: &[InputEvent]| {
+            frame.iter().any(|e| e.raw_code() == ABS_MT_TRACKING_ID && e.raw_value() == -1)
+        };
+
+        let plain = replay(false);
+        assert!(plain.iter().all(|f| tool_types(f).is_empty()));
+
+        let cancelled = replay(true);
+        let palm = cancelled.iter().position(|f| tool_types(f) == [MT_TOOL_PALM]).expect("palm frame");
+        assert!(!lifts(&cancelled[palm]) && lifts(&cancelled[palm + 1]));
+        assert!(has_key(&cancelled[palm + 1], Key::BTN_TOUCH, 0));
+        // The next contact in that slot is a finger again
+        let landed = cancelled.iter().rposition(|f| positions(std::slice::from_ref(f)).len() == 1).unwrap();
+        assert!(landed > palm + 1 && tool_types(&cancelled[landed]) == [MT_TOOL_FINGER]);
+        // Without the palm frame, the output is the same
+        let mut without: Vec<_> = cancelled.iter().filter(|f| tool_types(f) != [MT_TOOL_PALM]).cloned().collect();
+        without.iter_mut().for_each(|f| f.retain(|e| e.raw_code() != Abs::MT_TOOL_TYPE.raw()));
+        assert_eq!(without, plain);
+    }
+
+    #[test]
+    fn test_max_fingers_keeps_first_contacts() {
+        let mut config = Config::defaults(&RM2);
+        config.max_fingers = Some(2);
+        config.touch_primary = PrimaryContact::FirstDown;
+        let area = touch_area(&config, &RM2).unwrap();
+        let mut slots = SlotState::new();
+        for slot in [3, 0, 5] {
+            slots.land(slot);
+            slots.x[slot] = Some(700);
+            slots.y[slot] = Some(900);
+        }
+        let mut sink = RecordingSink::default();
+        let mut next_tracking_id = 0;
+        emit_touch_frame(&mut sink, &mut slots, &mut next_tracking_id, &RM2, &config, &area, false).unwrap();
+
+        let forwarded = |slots: &SlotState| (0..MT_SLOTS).filter(|&s| slots.tracking_id[s].is_some()).collect::<Vec<_>>();
+        assert_eq!(forwarded(&slots), vec![0, 3]);
+        assert!(has_key(&sink.frames[0], Key::BTN_TOOL_DOUBLETAP, 1) && has_key(&sink.frames[0], Key::BTN_TOOL_TRIPLETAP, 0));
+
+        // The first finger lifts: the third one takes its place
+        slots.active[3] = false;
+        emit_touch_frame(&mut sink, &mut slots, &mut next_tracking_id, &RM2, &config, &area, false).unwrap();
+        assert_eq!(forwarded(&slots), vec![0, 5]);
+        assert!(has_key(&sink.frames[1], Key::BTN_TOOL_DOUBLETAP, 1));
+
+        // Lowering the limit retires the newer one
+        config.max_fingers = Some(1);
+        emit_touch_frame(&mut sink, &mut slots, &mut next_tracking_id, &RM2, &config, &area, false).unwrap();
+        assert_eq!(forwarded(&slots), vec![0]);
+        assert!(has_key(&sink.frames[2], Key::BTN_TOOL_FINGER, 1) && has_key(&sink.frames[2], Key::BTN_TOOL_DOUBLETAP, 0));
+    }
+
+    #[test]
+    fn test_max_fingers_keeps_largest_until_lift() {
+        let mut config = Config::defaults(&RM2);
+        config.max_fingers = Some(1);
+        config.touch_primary = PrimaryContact::Largest;
+        let area = touch_area(&config, &RM2).unwrap();
+        let mut slots = SlotState::new();
+        for (slot, major) in [(0, 30), (1, 10)] {
+            slots.land(slot);
+            slots.x[slot] = Some(700);
+            slots.y[slot] = Some(900);
+            slots.major[slot] = Some(major);
+        }
+        let mut sink = RecordingSink::default();
+        let mut next_tracking_id = 0;
+        let forwarded = |slots: &SlotState| (0..MT_SLOTS).filter(|&s| slots.tracking_id[s].is_some()).collect::<Vec<_>>();
+        emit_touch_frame(&mut sink, &mut slots, &mut next_tracking_id, &RM2, &config, &area, false).unwrap();
+        assert_eq!(forwarded(&slots), vec![0]);
+
+        // The other contact grows bigger: the forwarded one stays
+        slots.major[0] = Some(12);
+        slots.major[1] = Some(40);
+        emit_touch_frame(&mut sink, &mut slots, &mut next_tracking_id, &RM2, &config, &area, false).unwrap();
+        assert_eq!(forwarded(&slots), vec![0]);
+
+        // Once it lifts, the other one takes over
+        slots.active[0] = false;
+        emit_touch_frame(&mut sink, &mut slots, &mut next_tracking_id, &RM2, &config, &area, false).unwrap();
+        assert_eq!(forwarded(&slots), vec![1]);
+    }
+
+    #[test]
+    fn test_first_only_ignores_other_fingers() {
+        let finger = |slot, id| [raw_event(EV_ABS, ABS_MT_SLOT, slot), raw_event(EV_ABS, ABS_MT_TRACKING_ID, id)].concat();
+        let at = |x, y| [raw_event(EV_ABS, ABS_MT_POSITION_X, x), raw_event(EV_ABS, ABS_MT_POSITION_Y, y)].concat();
+        let syn = || raw_event(EV_SYN, SYN_REPORT, 0);
+        let stream: Vec<u8> = [
+            [finger(1, 10), at(500, 600), syn()].concat(),
+            // A second finger lands in a lower slot, moves and lifts
+            [finger(0, 11), at(900, 900), syn()].concat(),
+            [at(950, 950), syn()].concat(),
+            [finger(0, -1), syn()].concat(),
+            // A third one lands, then the first finger lifts while it moves
+            [finger(2, 12), at(1200, 300), syn()].concat(),
+            [finger(1, -1), syn()].concat(),
+            [finger(2, 12), at(1250, 350), syn()].concat(),
+            [finger(2, -1), syn()].concat(),
+            // Down again on an empty surface: that finger is the cursor now
+            [finger(0, 13), at(1250, 350), syn()].concat(),
+        ]
+        .concat();
+        let legacy = |policy| {
+            let mut config = Config::defaults(&RM2);
+            config.touch_primary = policy;
+            let mut sink = RecordingSink::default();
+            let _ = run_event_loop(&mut stream.as_slice(), &mut sink, &RM2, &config, None, false, None);
+            let value = |frame: &[InputEvent], axis: Abs| {
+                frame.iter().find(|e| e.event_type().raw() == EV_ABS && e.raw_code() == axis.raw()).map(|e| e.raw_value())
+            };
+            let mut positions: Vec<_> = sink.frames.iter().filter_map(|f| value(f, Abs::X).zip(value(f, Abs::Y))).collect();
+            positions.dedup();
+            positions
+        };
+
+        let orientation = Config::defaults(&RM2).touch_orientation;
+        let out = |x, y| orientation.transform_touch(x, y, RM2.touch_x_max, RM2.touch_y_max);
+        assert_eq!(legacy(PrimaryContact::FirstOnly), vec![out(500, 600), out(1250, 350)]);
+        // Other policies hand the cursor to another finger
+        assert!(legacy(PrimaryContact::FirstDown).len() > 2);
+        assert!(legacy(PrimaryContact::LowestSlot).len() > 2);
     }
 }
@@ -0,0 +1,80 @@
+//! Forward touch as a stylus (`touch_as_pen`), for apps that ignore touch.
+//!
+//! Only the primary (first active) contact is forwarded, as pen motion on
+//! a separate tablet-like device with a fixed pressure while the finger is
+//! down. Further fingers are ignored, so there are no gestures.
+
+use evdevil::event::{Abs, EventType, InputEvent, Key};
+use evdevil::uinput::{AbsSetup, UinputDevice};
+use evdevil::{AbsInfo, InputProp};
+
+use crate::device::DeviceProfile;
+use crate::orientation::Orientation;
+
+use super::event::{key_event, EV_ABS};
+use super::sink::EventSink;
+
+const TOUCH_PEN_DEVICE_NAME: &str = "reMarkable Touch (pen)";
+
+const TOUCH_PEN_PRESSURE_MAX: i32 = 1024;
+/// Pressure reported while a finger is down; half way so apps apply their default width.
+const TOUCH_PEN_PRESSURE: i32 = TOUCH_PEN_PRESSURE_MAX / 2;
+
+const TOUCH_PEN_KEYS: [Key; 2] = [Key::BTN_TOOL_PEN, Key::BTN_TOUCH];
+
+pub fn create_device(
+    device: &DeviceProfile,
+    orientation: Orientation,
+    resolution: i32,
+) -> Result<UinputDevice, Box<dyn std::error::Error + Send + Sync>> {
+    let (out_x_max, out_y_max) = orientation.touch_output_dimensions(device.touch_x_max, device.touch_y_max);
+    let device = UinputDevice::builder()?
+        .with_props([InputProp::DIRECT])?
+        .with_abs_axes([
+            AbsSetup::new(Abs::X, AbsInfo::new(0, out_x_max).with_resolution(resolution)),
+            AbsSetup::new(Abs::Y, AbsInfo::new(0, out_y_max).with_resolution(resolution)),
+            AbsSetup::new(Abs::PRESSURE, AbsInfo::new(0, TOUCH_PEN_PRESSURE_MAX)),
+        ])?
+        .with_keys(TOUCH_PEN_KEYS)?
+        .build(TOUCH_PEN_DEVICE_NAME)?;
+
+    Ok(device)
+}
+
+/// Turns the primary contact position into pen frames.
+#[derive(Default)]
+pub struct TouchPen {
+    down: bool,
+}
+
+impl TouchPen {
+    /// Emit one frame for the primary contact at `position` (output
+    /// coordinates), or lift the pen if there is none.
+    pub fn emit(&mut self, sink: &mut impl EventSink, position: Option<(i32, i32)>) -> std::io::Result<()> {
+        let abs = |axis: Abs, value: i32| InputEvent::new(EventType::from_raw(EV_ABS), axis.raw(), value);
+
+        match position {
+            Some((x, y)) => {
+                let mut events = Vec::with_capacity(5);
+                if !self.down {
+                    events.push(key_event(Key::BTN_TOOL_PEN.raw(), 1));
+                    events.push(key_event(Key::BTN_TOUCH.raw(), 1));
+                }
+                events.push(abs(Abs::X, x));
+                events.push(abs(Abs::Y, y));
+                events.push(abs(Abs::PRESSURE, TOUCH_PEN_PRESSURE));
+                self.down = true;
+                sink.write_frame(&events)
+            }
+            None if self.down => {
+                self.down = false;
+                sink.write_frame(&[
+                    abs(Abs::PRESSURE, 0),
+                    key_event(Key::BTN_TOUCH.raw(), 0),
+                    key_event(Key::BTN_TOOL_PEN.raw(), 0),
+                ])
+            }
+            None => Ok(()),
+        }
+    }
+}
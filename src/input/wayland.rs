@@ -0,0 +1,170 @@
+//! Wayland virtual pointer output (`output = "wayland"`).
+//!
+//! Injects the pen as an absolute pointer through the wlroots
+//! `zwlr_virtual_pointer_manager_v1` protocol, so no uinput access is
+//! needed. Only position and the left button (pen contact) are forwarded;
+//! pressure and tilt are lost. Needs a compositor that offers the protocol
+//! (Sway, Hyprland, river and other wlroots-based ones; not GNOME or KDE)
+//! and the `wayland` cargo feature.
+
+pub use imp::VirtualPointer;
+
+#[cfg(not(feature = "wayland"))]
+mod imp {
+    use std::io;
+
+    use evdevil::event::InputEvent;
+
+    use super::super::sink::EventSink;
+
+    /// Without the feature, `connect` always fails, so no value ever exists.
+    pub enum VirtualPointer {}
+
+    impl VirtualPointer {
+        pub fn connect(_x_extent: i32, _y_extent: i32) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+            Err("rm-pad was built without Wayland support (rebuild with `--features wayland`)".into())
+        }
+    }
+
+    impl EventSink for VirtualPointer {
+        fn write_frame(&mut self, _events: &[InputEvent]) -> io::Result<()> {
+            match *self {}
+        }
+    }
+}
+
+#[cfg(feature = "wayland")]
+mod imp {
+    use std::io;
+    use std::time::Instant;
+
+    use evdevil::event::InputEvent;
+    use wayland_client::protocol::wl_pointer::ButtonState;
+    use wayland_client::protocol::wl_registry::{self, WlRegistry};
+    use wayland_client::protocol::wl_seat::WlSeat;
+    use wayland_client::{delegate_noop, Connection, Dispatch, EventQueue, QueueHandle};
+    use wayland_protocols_wlr::virtual_pointer::v1::client::zwlr_virtual_pointer_manager_v1::ZwlrVirtualPointerManagerV1;
+    use wayland_protocols_wlr::virtual_pointer::v1::client::zwlr_virtual_pointer_v1::ZwlrVirtualPointerV1;
+
+    use super::super::event::{ABS_X, ABS_Y, EV_ABS, EV_KEY};
+    use super::super::sink::EventSink;
+
+    const BTN_LEFT: u32 = 0x110;
+    const BTN_TOUCH: u16 = 0x14a;
+
+    #[derive(Default)]
+    struct Globals {
+        manager: Option<ZwlrVirtualPointerManagerV1>,
+        seat: Option<WlSeat>,
+    }
+
+    impl Dispatch<WlRegistry, ()> for Globals {
+        fn event(
+            state: &mut Self,
+            registry: &WlRegistry,
+            event: wl_registry::Event,
+            _: &(),
+            _: &Connection,
+            qh: &QueueHandle<Self>,
+        ) {
+            let wl_registry::Event::Global { name, interface, .. } = event else {
+                return;
+            };
+            match interface.as_str() {
+                "zwlr_virtual_pointer_manager_v1" => state.manager = Some(registry.bind(name, 1, qh, ())),
+                "wl_seat" if state.seat.is_none() => state.seat = Some(registry.bind(name, 1, qh, ())),
+                _ => {}
+            }
+        }
+    }
+
+    delegate_noop!(Globals: ignore WlSeat);
+    delegate_noop!(Globals: ZwlrVirtualPointerManagerV1);
+    delegate_noop!(Globals: ZwlrVirtualPointerV1);
+
+    pub struct VirtualPointer {
+        connection: Connection,
+        _queue: EventQueue<Globals>,
+        pointer: ZwlrVirtualPointerV1,
+        x_extent: u32,
+        y_extent: u32,
+        x: u32,
+        y: u32,
+        pressed: bool,
+        start: Instant,
+    }
+
+    impl VirtualPointer {
+        /// Connect to the compositor from `WAYLAND_DISPLAY`. Positions sent
+        /// later are in `0..x_extent` / `0..y_extent`, mapped onto the whole
+        /// output layout.
+        pub fn connect(x_extent: i32, y_extent: i32) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+            let connection = Connection::connect_to_env()
+                .map_err(|e| format!("Failed to connect to the Wayland compositor: {}", e))?;
+            let mut queue = connection.new_event_queue();
+            let qh = queue.handle();
+            connection.display().get_registry(&qh, ());
+
+            let mut globals = Globals::default();
+            queue.roundtrip(&mut globals)?;
+
+            let Some(manager) = &globals.manager else {
+                return Err("The compositor does not support zwlr_virtual_pointer_manager_v1".into());
+            };
+            let pointer = manager.create_virtual_pointer(globals.seat.as_ref(), &qh, ());
+            connection.flush()?;
+            log::info!("Wayland virtual pointer ready");
+
+            Ok(Self {
+                connection,
+                _queue: queue,
+                pointer,
+                x_extent: x_extent.max(1) as u32,
+                y_extent: y_extent.max(1) as u32,
+                x: 0,
+                y: 0,
+                pressed: false,
+                start: Instant::now(),
+            })
+        }
+    }
+
+    impl EventSink for VirtualPointer {
+        fn write_frame(&mut self, events: &[InputEvent]) -> io::Result<()> {
+            let time = self.start.elapsed().as_millis() as u32;
+            let mut moved = false;
+            let mut pressed = self.pressed;
+
+            for ev in events {
+                let value = ev.raw_value();
+                match (ev.event_type().raw(), ev.raw_code()) {
+                    (EV_ABS, ABS_X) => {
+                        self.x = value.max(0) as u32;
+                        moved = true;
+                    }
+                    (EV_ABS, ABS_Y) => {
+                        self.y = value.max(0) as u32;
+                        moved = true;
+                    }
+                    (EV_KEY, BTN_TOUCH) => pressed = value != 0,
+                    _ => {}
+                }
+            }
+
+            if !moved && pressed == self.pressed {
+                return Ok(());
+            }
+            if moved {
+                self.pointer
+                    .motion_absolute(time, self.x, self.y, self.x_extent, self.y_extent);
+            }
+            if pressed != self.pressed {
+                let state = if pressed { ButtonState::Pressed } else { ButtonState::Released };
+                self.pointer.button(time, BTN_LEFT, state);
+                self.pressed = pressed;
+            }
+            self.pointer.frame();
+            self.connection.flush().map_err(io::Error::other)
+        }
+    }
+}
@@ -1,37 +1,119 @@
+mod audit;
+mod bench;
+mod calibrate;
+mod caps;
 mod config;
+mod corpus;
+mod daemon;
 mod device;
 mod dump;
 mod grab;
+mod health;
+mod hooks;
 mod input;
+mod notify;
 mod orientation;
+mod orientation_test;
 mod palm;
+mod remote;
+mod replay;
+mod screen;
+mod secret;
+mod setup;
 mod ssh;
+mod status;
 
+use std::io::IsTerminal;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use clap::Parser;
 
 use config::{Cli, Command, Config};
 use device::DeviceProfile;
+use input::{IdleDisconnect, SharedMouse, StopFlag, TabletSleep, VirtualMouse};
 use palm::{PalmState, SharedPalmState};
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
-    
-    init_logging(cli.command.is_some());
-    
+
+    // Fork before any connection or thread exists so everything runs in the child.
+    let _pidfile = if cli.daemonize {
+        daemon::daemonize(cli.pidfile.as_deref(), cli.log_file.as_deref())?
+    } else {
+        None
+    };
+
+    // The dashboard needs a terminal and the foreground; otherwise just log
+    let tui = cli.tui && cli.command.is_none() && !cli.daemonize && std::io::stdout().is_terminal();
+    init_logging(cli.command.is_some() || tui, tui);
+    if cli.tui && !tui {
+        log::warn!("--tui needs an interactive terminal and no subcommand, logging instead");
+    }
+
+    // Commands that don't talk to the tablet or need the config
+    if let Some(Command::CheckCorpus { dir, update }) = &cli.command {
+        return check_corpus(dir, *update);
+    }
+    if let Some(Command::Completions { shell }) = cli.command {
+        print_completions(shell);
+        return Ok(());
+    }
+
+    // The keyring entry is named in the config
+    let base_config = Config::load(&cli, DeviceProfile::current());
+    if let Some(Command::SetPassword) = cli.command {
+        return set_keyring_password(&base_config);
+    }
+    if let Some(Command::Replay { file, speed, kind }) = &cli.command {
+        let (device, kind) = replay::recording_kind(file, base_config.device_model.as_deref(), kind.as_deref())?;
+        return replay::run(file, *speed, device, kind, &Config::load(&cli, device));
+    }
+
     // Detect device via SSH (required)
-    let config_for_detection = Config::load(&cli, DeviceProfile::current());
+    let mut config_for_detection = base_config;
+    if let Err(msg) = config_for_detection.check_host() {
+        eprintln!("Error: {}", msg);
+        daemon::exit(1);
+    }
+
+    // Only commands that connect get here, before any of them runs a command on the tablet
+    if let Some(path) = &config_for_detection.audit_log {
+        audit::open(path, &config_for_detection.host)?;
+    }
+
+    // Sets up the key that detection below would need
+    if let Some(Command::Setup) = cli.command {
+        return setup::run(&config_for_detection);
+    }
+    config_for_detection.resolve_keyring()?;
+    if config_for_detection.host_defaulted {
+        log::info!(
+            "No host specified (--host, RMPAD_HOST or host in the config), using the USB address {}",
+            config_for_detection.host
+        );
+    }
+
+    // Held until exit; a second forwarding instance for the same host refuses to start.
+    let _instance_lock = if cli.command.is_none() && !cli.force {
+        Some(lock_instance(&config_for_detection.host)?)
+    } else {
+        None
+    };
     let session = ssh::connect_for_detection(&config_for_detection)?;
-    let device = DeviceProfile::detect_via_ssh(&session)?;
+    let configured_model = config_for_detection.device_model.as_deref().map(DeviceProfile::from_name).transpose()?;
+    let detected = DeviceProfile::detect_via_ssh(&session, configured_model);
+    let device = detected.profile;
     log::info!("Using device profile: {}", device.name);
-    
-    let config = Config::load(&cli, device);
+
+    let mut config = Config::load(&cli, device);
+    config.tablet_arch = detected.arch;
+    // Reuse the password detection got from the keyring
+    (config.password, config.auth) = (config_for_detection.password, config_for_detection.auth);
 
     if let Some(command) = cli.command {
         return run_subcommand(command, &config, device);
@@ -40,16 +122,116 @@ fn main() -> Result<()> {
     if let Err(msg) = config.validate() {
         eprintln!("Error: {}", msg);
         eprintln!("\nRun with --help for usage information");
-        std::process::exit(1);
+        daemon::exit(1);
+    }
+
+    if config.run_pen() && config.run_touch() {
+        check_device_paths(&session, &mut config, device);
+    }
+
+    if let Some(rate) = config.max_event_rate {
+        input::limit_event_rate(rate);
     }
 
     log_startup_info(&config);
-    run_input_forwarding(config, device)
+    log_aspect_advice(&config, device);
+    if tui {
+        status::spawn_dashboard(config.host.clone());
+    }
+    if let Some(addr) = &config.health_addr {
+        health::spawn(addr, config.health_max_idle_secs.map(Duration::from_secs))?;
+    }
+    if config.notify {
+        if let Err(e) = notify::spawn(config.host.clone()) {
+            log::warn!("--notify: {}", e);
+        }
+    }
+
+    let result = run_input_forwarding(config, device);
+    if let Err(e) = &result {
+        notify::fatal(&e.to_string());
+    }
+    result
 }
 
-fn init_logging(is_dump: bool) {
-    let default_level = if is_dump { "warn" } else { "info" };
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default_level)).init();
+/// Take an exclusive lock on a per-host lock file.
+///
+/// Uses `flock`, so the lock goes away with the process however it exits
+/// and a stale file never blocks a restart.
+fn lock_instance(host: &str) -> Result<std::fs::File> {
+    use std::os::fd::AsRawFd;
+
+    let dir = std::env::var_os("XDG_RUNTIME_DIR")
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir);
+    let path = dir.join(format!("rm-pad-{}.lock", host.replace('/', "_")));
+
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .map_err(|e| format!("Failed to open lock file {}: {}", path.display(), e))?;
+
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        return Err(format!(
+            "Another rm-pad instance is already forwarding from {} (lock {}). \
+             Stop it first, or pass --force to start anyway",
+            host,
+            path.display()
+        )
+        .into());
+    }
+
+    Ok(file)
+}
+
+/// Warn (and with `auto_fix_devices`, swap) when pen and touch paths look swapped.
+fn check_device_paths(session: &ssh2::Session, config: &mut Config, device: &DeviceProfile) {
+    let word_bits = caps::word_bits(device.input_event_size);
+    let probe = |spec: &str| {
+        let path = ssh::resolve_device_path(session, spec)?;
+        caps::read_abs_axes(session, &path, word_bits)
+    };
+
+    let (pen_axes, touch_axes) = match (probe(&config.pen_device), probe(&config.touch_device)) {
+        (Ok(pen), Ok(touch)) => (pen, touch),
+        (Err(e), _) | (_, Err(e)) => {
+            log::debug!("Skipping device path check: {}", e);
+            return;
+        }
+    };
+
+    if !caps::looks_swapped(&pen_axes, &touch_axes) {
+        return;
+    }
+
+    if config.auto_fix_devices {
+        log::warn!(
+            "pen_device ({}) looks like a touchscreen and touch_device ({}) like a pen digitizer; swapping them",
+            config.pen_device,
+            config.touch_device
+        );
+        std::mem::swap(&mut config.pen_device, &mut config.touch_device);
+    } else {
+        log::warn!(
+            "pen_device ({}) looks like a touchscreen and touch_device ({}) like a pen digitizer. \
+             The paths are probably swapped; fix the config or use --auto-fix-devices",
+            config.pen_device,
+            config.touch_device
+        );
+    }
+}
+
+/// `quiet` (subcommands, the dashboard) only logs warnings and errors by
+/// default. With `tui`, the log is drawn in the dashboard instead of printed.
+fn init_logging(quiet: bool, tui: bool) {
+    let default_level = if quiet { "warn" } else { "info" };
+    let mut builder = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default_level));
+    if tui {
+        builder.target(env_logger::Target::Pipe(Box::new(status::DashboardLog::default())));
+    }
+    builder.init();
 }
 
 fn run_subcommand(
@@ -58,17 +240,66 @@ fn run_subcommand(
     device_profile: &'static DeviceProfile,
 ) -> Result<()> {
     match command {
-        Command::Dump { device } => match device.as_str() {
-            "touch" => dump::run_touch(config, device_profile),
-            "pen" => dump::run_pen(config, device_profile),
+        Command::Dump {
+            device,
+            frames,
+            apply_orientation,
+        } => match device.as_str() {
+            "touch" if frames => {
+                eprintln!("--frames is only supported for 'pen'.");
+                daemon::exit(1);
+            }
+            "touch" => dump::run_touch(config, device_profile, apply_orientation),
+            "pen" if frames => dump::run_pen_frames(config, device_profile, apply_orientation),
+            "pen" => dump::run_pen(config, device_profile, apply_orientation),
             _ => {
                 eprintln!("Unknown dump device: {}. Use 'touch' or 'pen'.", device);
-                std::process::exit(1);
+                daemon::exit(1);
+            }
+        },
+        Command::Calibrate { device } => match device.as_str() {
+            "touch" => calibrate::run_touch(config, device_profile),
+            _ => {
+                eprintln!("Unknown calibrate device: {}. Only 'touch' is supported.", device);
+                daemon::exit(1);
             }
         },
+        Command::Bench { device, secs } => bench::run(config, device_profile, &device, secs),
+        Command::OrientationTest { device } => orientation_test::run(config, device_profile, &device),
+        Command::Caps { device } => caps::run(config, device_profile, &device),
+        Command::Remote { action, json, lines } => remote::run(config, action, json, lines),
+        Command::SetPassword
+        | Command::Setup
+        | Command::CheckCorpus { .. }
+        | Command::Replay { .. }
+        | Command::Completions { .. } => unreachable!("handled before detection"),
     }
 }
 
+fn print_completions(shell: clap_complete::Shell) {
+    use clap::CommandFactory;
+    clap_complete::generate(shell, &mut Cli::command(), "rm-pad", &mut std::io::stdout());
+}
+
+fn check_corpus(dir: &std::path::Path, update: bool) -> Result<()> {
+    let failures = corpus::check(dir, update)?;
+    if failures > 0 {
+        eprintln!("{} recording(s) did not match", failures);
+        daemon::exit(1);
+    }
+    Ok(())
+}
+
+fn set_keyring_password(config: &Config) -> Result<()> {
+    let password = secret::prompt_hidden(&format!("Root password for {}: ", config.host))?;
+    secret::set_password(&config.keyring_service, &config.keyring_account, &password)?;
+    eprintln!(
+        "Stored password in keyring entry {}/{}. Set auth = \"keyring\" to use it.",
+        config.keyring_service, config.keyring_account
+    );
+    Ok(())
+}
+
 fn log_startup_info(config: &Config) {
     let palm_info = if config.no_palm_rejection {
         "off".into()
@@ -83,13 +314,51 @@ fn log_startup_info(config: &Config) {
         if config.run_touch() { &config.touch_device } else { "off" },
         palm_info,
         config.grab_input,
-        config.orientation
+        if config.pen_orientation == config.touch_orientation {
+            config.pen_orientation.to_string()
+        } else {
+            format!("pen {}, touch {}", config.pen_orientation, config.touch_orientation)
+        }
     );
 }
 
+/// Log the output aspect ratio and warn if it's portrait, which looks
+/// squished on the usual landscape monitor.
+fn log_aspect_advice(config: &Config, device: &DeviceProfile) {
+    let (pen_w, pen_h) = config.pen_orientation.pen_output_dimensions(device.pen_x_max, device.pen_y_max);
+    let (touch_w, touch_h) = config
+        .touch_orientation
+        .touch_output_dimensions(device.touch_x_max, device.touch_y_max);
+
+    let mut portrait = None;
+    if config.run_pen() {
+        log::info!("Pen output area: {}x{} (aspect {:.2})", pen_w, pen_h, pen_w as f64 / pen_h as f64);
+        portrait = portrait.or((pen_h > pen_w).then_some(config.pen_orientation));
+    }
+    if config.run_touch() {
+        log::info!("Touch output area: {}x{} (aspect {:.2})", touch_w, touch_h, touch_w as f64 / touch_h as f64);
+        portrait = portrait.or((touch_h > touch_w).then_some(config.touch_orientation));
+    }
+
+    if let Some(orientation) = portrait {
+        log::warn!(
+            "Orientation {} maps to a portrait-shaped area. On a landscape monitor this looks squished; \
+             unless your screen is rotated, try landscape-right or landscape-left",
+            orientation
+        );
+    }
+}
+
 fn run_input_forwarding(config: Config, device: &'static DeviceProfile) -> Result<()> {
+    if let Some(path) = &config.wait_for {
+        wait_for_path(std::path::Path::new(path));
+    }
+
     let palm_state = create_palm_state(&config);
     let config = Arc::new(config);
+    let stop = StopFlag::default();
+    input::stop_on_signals(&stop);
+    let sleep = TabletSleep::default();
 
     // If grabbing, touch the watchdog file FIRST, then start watchdog thread
     let watchdog_stop = if config.grab_input {
@@ -107,10 +376,21 @@ fn run_input_forwarding(config: Config, device: &'static DeviceProfile) -> Resul
         None
     };
 
-    let pen_handle = spawn_pen_thread(&config, device, &palm_state);
-    let touch_handle = spawn_touch_thread(&config, device, &palm_state);
+    let mouse = create_mouse(&config)?;
+    hooks::run("on_connect", config.on_connect.as_deref(), &config.host, device);
 
-    let result = join_threads(pen_handle, touch_handle);
+    // Not joined: an absent folio must not keep rm-pad alive once pen and touch have stopped
+    spawn_keyboard_thread(&config, device, &stop, &sleep);
+    spawn_power_thread(&config, device, &stop, &sleep);
+
+    let result = if shares_device_node(&config) {
+        join_threads(spawn_shared_thread(&config, device, &palm_state, &mouse, &stop, &sleep), None)
+    } else {
+        let pen_handle = spawn_pen_thread(&config, device, &palm_state, &mouse, &stop, &sleep);
+        let touch_handle = spawn_touch_thread(&config, device, &palm_state, &mouse, &stop, &sleep);
+        join_threads(pen_handle, touch_handle)
+    };
+    hooks::run("on_disconnect", config.on_disconnect.as_deref(), &config.host, device);
 
     // Stop watchdog thread
     if let Some(stop_flag) = watchdog_stop {
@@ -120,6 +400,19 @@ fn run_input_forwarding(config: Config, device: &'static DeviceProfile) -> Resul
     result
 }
 
+/// Block until `path` exists (`wait_for`), so the devices appear after the
+/// compositor in a kiosk boot. Nothing is grabbed on the tablet meanwhile.
+fn wait_for_path(path: &std::path::Path) {
+    if path.exists() {
+        return;
+    }
+    log::info!("Waiting for {} before creating input devices", path.display());
+    while !path.exists() {
+        thread::sleep(Duration::from_millis(250));
+    }
+    log::info!("{} appeared", path.display());
+}
+
 fn create_palm_state(config: &Config) -> Option<SharedPalmState> {
     if config.no_palm_rejection {
         return None;
@@ -131,10 +424,36 @@ fn create_palm_state(config: &Config) -> Option<SharedPalmState> {
     Some(Arc::new(std::sync::Mutex::new(PalmState::new())))
 }
 
+/// Shared mouse for cross-device actions; outlives reconnects of either stream.
+/// Only created if a stream clicks it: the pen's barrel button with
+/// `pen_button_click`, or a gesture bound to a mouse button.
+fn create_mouse(config: &Config) -> Result<Option<SharedMouse>> {
+    let pen_clicks = config.pen_button_click && config.run_pen();
+    let touch_clicks = config.run_touch() && !config.touch_as_pen && input::gesture_clicks_mouse(&config.gesture_keys);
+    if !pen_clicks && !touch_clicks {
+        return Ok(None);
+    }
+
+    log::info!("Creating mouse uinput device");
+    Ok(Some(Arc::new(VirtualMouse::create(config.device_group.as_deref())?)))
+}
+
+/// Spawn a stream thread named after its stream, which shows up in `top -H`,
+/// debuggers and panic messages.
+fn spawn_named(name: &str, f: impl FnOnce() + Send + 'static) -> thread::JoinHandle<()> {
+    thread::Builder::new()
+        .name(name.to_string())
+        .spawn(f)
+        .unwrap_or_else(|e| panic!("Failed to spawn the {} thread: {}", name, e))
+}
+
 fn spawn_pen_thread(
     config: &Arc<Config>,
     device: &'static DeviceProfile,
     palm_state: &Option<SharedPalmState>,
+    mouse: &Option<SharedMouse>,
+    stop: &StopFlag,
+    sleep: &TabletSleep,
 ) -> Option<thread::JoinHandle<()>> {
     if !config.run_pen() {
         return None;
@@ -142,10 +461,39 @@ fn spawn_pen_thread(
 
     let config = config.clone();
     let palm = palm_state.clone();
+    let mouse = mouse.clone();
+    let stop = stop.clone();
+    let sleep = sleep.clone();
+
+    Some(spawn_named("pen", move || {
+        run_with_reconnect("pen", &[&status::PEN], false, &config, &stop, &sleep, |grab| {
+            input::run_pen(&config, device, grab, palm.clone(), mouse.clone(), &stop)
+        });
+    }))
+}
+
+/// Pen and touch configured on the same node: one stream feeds both.
+fn shares_device_node(config: &Config) -> bool {
+    config.run_pen() && config.run_touch() && config.pen_device == config.touch_device
+}
+
+fn spawn_shared_thread(
+    config: &Arc<Config>,
+    device: &'static DeviceProfile,
+    palm_state: &Option<SharedPalmState>,
+    mouse: &Option<SharedMouse>,
+    stop: &StopFlag,
+    sleep: &TabletSleep,
+) -> Option<thread::JoinHandle<()>> {
+    let config = config.clone();
+    let palm = palm_state.clone();
+    let mouse = mouse.clone();
+    let stop = stop.clone();
+    let sleep = sleep.clone();
 
-    Some(thread::spawn(move || {
-        run_with_reconnect("pen", || {
-            input::run_pen(&config, device, palm.clone())
+    Some(spawn_named("pen+touch", move || {
+        run_with_reconnect("pen+touch", &[&status::PEN, &status::TOUCH], false, &config, &stop, &sleep, |grab| {
+            input::run_shared(&config, device, grab, palm.clone(), mouse.clone(), &stop)
         });
     }))
 }
@@ -154,6 +502,9 @@ fn spawn_touch_thread(
     config: &Arc<Config>,
     device: &'static DeviceProfile,
     palm_state: &Option<SharedPalmState>,
+    mouse: &Option<SharedMouse>,
+    stop: &StopFlag,
+    sleep: &TabletSleep,
 ) -> Option<thread::JoinHandle<()>> {
     if !config.run_touch() {
         return None;
@@ -161,35 +512,266 @@ fn spawn_touch_thread(
 
     let config = config.clone();
     let palm = palm_state.clone();
+    let mouse = mouse.clone();
+    let stop = stop.clone();
+    let sleep = sleep.clone();
 
-    Some(thread::spawn(move || {
-        run_with_reconnect("touch", || {
-            input::run_touch(&config, device, palm.clone())
+    Some(spawn_named("touch", move || {
+        run_with_reconnect("touch", &[&status::TOUCH], false, &config, &stop, &sleep, |grab| {
+            input::run_touch(&config, device, grab, palm.clone(), mouse.clone(), &stop)
         });
     }))
 }
 
+/// Forward the keyboard folio (`keyboard_device`). A missing folio is just
+/// another failed connect, so it's picked up once attached; only the first
+/// of those failures is logged as an error.
+fn spawn_keyboard_thread(config: &Arc<Config>, device: &'static DeviceProfile, stop: &StopFlag, sleep: &TabletSleep) {
+    let Some(path) = config.keyboard_device.clone() else {
+        return;
+    };
+    let config = config.clone();
+    let stop = stop.clone();
+    let sleep = sleep.clone();
+
+    spawn_named("keyboard", move || {
+        run_with_reconnect("keyboard", &[], true, &config, &stop, &sleep, |grab| {
+            input::run_keyboard(&config, device, &path, grab, &stop)
+        });
+    });
+}
+
+/// Follow the power button (`pause_on_sleep`). Not joined, like the
+/// keyboard. Its stream drops while the tablet sleeps, so failures are
+/// only warned about once and then retried quietly.
+fn spawn_power_thread(config: &Arc<Config>, device: &'static DeviceProfile, stop: &StopFlag, sleep: &TabletSleep) {
+    let (true, Some(path)) = (config.pause_on_sleep, config.power_device.clone()) else {
+        return;
+    };
+    let config = config.clone();
+    let stop = stop.clone();
+    let sleep = sleep.clone();
+
+    spawn_named("power", move || {
+        let mut warned = false;
+        while !stop.is_set() {
+            match input::run_power(&config, device, &path, &sleep, &stop) {
+                Err(e) if !warned => {
+                    log::warn!("[power] {}; tablet sleep is only noticed once this connects", e);
+                    warned = true;
+                }
+                Err(e) => log::debug!("[power] {}", e),
+                Ok(()) => {}
+            }
+            if stop.sleep(RECONNECT_DELAY) {
+                break;
+            }
+        }
+    });
+}
+
 /// Delay between reconnection attempts.
 const RECONNECT_DELAY: Duration = Duration::from_secs(2);
 
-fn run_with_reconnect<F>(name: &str, mut run_fn: F)
-where
-    F: FnMut() -> Result<()>,
+/// Longest wait for wake-up before trying again anyway, in case the
+/// press that woke the tablet was missed. Also the longest backoff between
+/// retries of an optional stream.
+const SLEEP_RETRY: Duration = Duration::from_secs(30);
+
+/// Run a stream until it ends for good, reconnecting after errors.
+///
+/// `run_fn` gets whether to grab the device; with `grab_fallback`, a failed
+/// grab switches this stream to non-exclusive mode for the rest of the run.
+/// `statuses` are the dashboard entries this stream feeds. Setting `stop`
+/// ends the stream instead of reconnecting. While the tablet `sleep`s
+/// (`pause_on_sleep`), errors are expected and it waits for wake-up.
+///
+/// An `optional` stream's device may simply be absent (a detached folio):
+/// after its first failure, further failures in a row are only logged at
+/// debug level and the retry delay doubles up to `SLEEP_RETRY`. A run that
+/// lasted longer than that counts as having worked.
+fn run_with_reconnect<F>(
+    name: &str,
+    statuses: &[&status::StreamStatus],
+    optional: bool,
+    config: &Config,
+    stop: &StopFlag,
+    sleep: &TabletSleep,
+    mut run_fn: F,
+) where
+    F: FnMut(bool) -> Result<()>,
 {
+    let mut grab = config.grab_input;
+    let mut failures: u32 = 0;
+
     loop {
-        log::info!("[{}] Connecting", name);
+        if failures == 0 || !optional {
+            log::info!("[{}] Connecting", name);
+        }
+        statuses.iter().for_each(|s| s.connecting());
 
-        if let Err(e) = run_fn() {
-            log::error!("[{}] Error: {}", name, e);
+        let started = Instant::now();
+        let mut error = None;
+        let result = run_fn(grab);
+        if result.is_ok() || started.elapsed() >= SLEEP_RETRY {
+            failures = 0;
+        }
+        let failed = result.is_err();
+        match result {
+            _ if stop.is_set() => {
+                log::info!("[{}] Stopped", name);
+                statuses.iter().for_each(|s| s.stopped());
+                return;
+            }
+            // Includes idle_disconnect_secs: a sleeping tablet isn't idle
+            Err(e) if sleep.is_asleep() => {
+                log::info!("[{}] Disconnected while the tablet sleeps: {}", name, e);
+                error = Some("tablet asleep".to_string());
+            }
+            // Only raised once no stream has had input, so they all stop together
+            Err(e) if e.downcast_ref::<IdleDisconnect>().is_some() => {
+                log::info!("[{}] {} on any stream, disconnecting (restart rm-pad to resume)", name, e);
+                stop.stop();
+                statuses.iter().for_each(|s| s.stopped());
+                return;
+            }
+            Err(e) if config.grab_fallback && e.downcast_ref::<ssh::GrabFailed>().is_some() => {
+                log::warn!(
+                    "[{}] {}; falling back to streaming without a grab. The tablet UI will also see this input",
+                    name,
+                    e
+                );
+                grab = false;
+            }
+            Err(e) if optional && failures > 0 => {
+                log::debug!("[{}] Still failing: {}", name, e);
+            }
+            Err(e) => {
+                let log_path = grab::RemotePaths::new(&config.remote_dir).log;
+                let (category, hint) = classify_error(e.as_ref(), &log_path);
+                log::error!("[{}] Error ({}): {}", name, category, e);
+                log::info!("[{}] Hint: {}", name, hint);
+                error = Some(format!("{} ({})", e, category));
+            }
+            Ok(()) => {}
         }
+        if failed {
+            failures = failures.saturating_add(1);
+        }
+        if config.once {
+            log::info!("[{}] Disconnected, exiting (--once)", name);
+            statuses.iter().for_each(|s| s.stopped());
+            return;
+        }
+        statuses.iter().for_each(|s| s.reconnecting(error.clone()));
+
+        if sleep.is_asleep() {
+            log::info!("[{}] Waiting for the tablet to wake", name);
+            if sleep.wait_while_asleep(stop, SLEEP_RETRY) {
+                log::info!("[{}] Stopped", name);
+                statuses.iter().for_each(|s| s.stopped());
+                return;
+            }
+            continue;
+        }
+        let delay = match failures {
+            n if optional && n > 0 => RECONNECT_DELAY.saturating_mul(1 << (n - 1).min(4)).min(SLEEP_RETRY),
+            _ => RECONNECT_DELAY,
+        };
+        if optional && failures > 1 {
+            log::debug!("[{}] Retrying in {}s", name, delay.as_secs());
+        } else {
+            log::warn!("[{}] Disconnected, reconnecting in {}s", name, delay.as_secs());
+        }
+        if stop.sleep(delay) {
+            log::info!("[{}] Stopped", name);
+            statuses.iter().for_each(|s| s.stopped());
+            return;
+        }
+    }
+}
 
-        log::warn!(
-            "[{}] Disconnected, reconnecting in {}s",
-            name,
-            RECONNECT_DELAY.as_secs()
+/// Sort a stream error into a category with a hint for the reconnect log.
+fn classify_error(e: &(dyn std::error::Error + 'static), log_path: &str) -> (&'static str, String) {
+    use std::io::ErrorKind;
+
+    if let Some(io) = e.downcast_ref::<std::io::Error>() {
+        return match io.kind() {
+            ErrorKind::UnexpectedEof => (
+                "stream",
+                format!(
+                    "the tablet closed the input stream; with grab_input, check {} on the tablet",
+                    log_path
+                ),
+            ),
+            ErrorKind::PermissionDenied => (
+                "uinput",
+                "no permission to create input devices; see the udev rules in the README".into(),
+            ),
+            ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::BrokenPipe
+            | ErrorKind::TimedOut
+            | ErrorKind::HostUnreachable
+            | ErrorKind::NetworkUnreachable => {
+                ("network", "check the USB or Wi-Fi connection to the tablet".into())
+            }
+            _ => ("io", "unexpected I/O error; run with RUST_LOG=debug for details".into()),
+        };
+    }
+
+    if e.downcast_ref::<ssh::GrabFailed>().is_some() {
+        return (
+            "grab",
+            format!(
+                "another program holds the device (see {} on the tablet); set grab_fallback to stream without grabbing",
+                log_path
+            ),
+        );
+    }
+
+    if e.downcast_ref::<ssh::HelperMissing>().is_some() {
+        return (
+            "grab",
+            "the tablet probably rebooted and cleared remote_dir; the helper is uploaded again on reconnect".into(),
+        );
+    }
+
+    if e.downcast_ref::<ssh::RemoteExited>().is_some() {
+        return (
+            "remote",
+            format!(
+                "the network is fine, the command on the tablet exited; look at the tablet side (e.g. {})",
+                log_path
+            ),
+        );
+    }
+
+    if e.downcast_ref::<ssh::ConnectionLost>().is_some() {
+        return ("network", "check the USB or Wi-Fi connection to the tablet".into());
+    }
+
+    if e.downcast_ref::<ssh2::Error>().is_some() {
+        return (
+            "ssh",
+            "the SSH session failed; the tablet may be asleep or have dropped off the network".into(),
         );
-        thread::sleep(RECONNECT_DELAY);
     }
+
+    if e.downcast_ref::<grab::UploadFailed>().is_some() {
+        return ("grab", format!("the grab helper failed, see {} on the tablet", log_path));
+    }
+
+    if e.downcast_ref::<ssh::ConnectFailed>().is_some() {
+        return ("network", "check the USB or Wi-Fi connection to the tablet".into());
+    }
+
+    if e.downcast_ref::<ssh::AuthFailed>().is_some() {
+        return ("auth", "check the configured key or password".into());
+    }
+
+    ("other", "run with RUST_LOG=debug for details".into())
 }
 
 fn join_threads(
@@ -0,0 +1,165 @@
+//! Desktop notifications on connect, disconnect and fatal errors (`notify`).
+//!
+//! A background thread watches the stream status (see `status`) and
+//! announces when input starts coming through and when it drops. A new
+//! state has to hold for `SETTLE` before it's announced, so reconnect churn
+//! produces one notification instead of one per attempt. Needs the
+//! `notify` cargo feature and a notification daemon on the session bus.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::status;
+
+/// How long a connection state has to last before it's announced.
+const SETTLE: Duration = Duration::from_secs(5);
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Start announcing connection changes for `host`.
+pub fn spawn(host: String) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    imp::check()?;
+    ENABLED.store(true, Ordering::Relaxed);
+
+    thread::spawn(move || {
+        let mut debounce = Debounce::default();
+        loop {
+            thread::sleep(Duration::from_secs(1));
+            match debounce.update(observe(), Instant::now()) {
+                Some(true) => send("Tablet input connected", &format!("Forwarding from {}", host)),
+                Some(false) => {
+                    let error = status::PEN.last_error().or_else(|| status::TOUCH.last_error());
+                    let body = match error {
+                        Some(error) => format!("Reconnecting to {}: {}", host, error),
+                        None => format!("Reconnecting to {}", host),
+                    };
+                    send("Tablet input lost", &body);
+                }
+                None => {}
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Announce an error rm-pad can't recover from. Does nothing unless `spawn` succeeded.
+pub fn fatal(message: &str) {
+    if ENABLED.load(Ordering::Relaxed) {
+        send("rm-pad stopped", message);
+    }
+}
+
+fn send(summary: &str, body: &str) {
+    if let Err(e) = imp::show(summary, body) {
+        log::warn!("Failed to show notification: {}", e);
+    }
+}
+
+/// Down if any stream dropped, up once any forwards again, unknown otherwise.
+fn observe() -> Option<bool> {
+    let streams = [&status::PEN, &status::TOUCH];
+    if streams.iter().any(|s| s.has_dropped()) {
+        Some(false)
+    } else if streams.iter().any(|s| s.is_streaming()) {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+/// Reports a connection state once it has held for `SETTLE`.
+#[derive(Default)]
+struct Debounce {
+    announced: Option<bool>,
+    pending: Option<(bool, Instant)>,
+}
+
+impl Debounce {
+    fn update(&mut self, observed: Option<bool>, now: Instant) -> Option<bool> {
+        let Some(up) = observed.filter(|&up| self.announced != Some(up)) else {
+            self.pending = None;
+            return None;
+        };
+        // Failing to connect at startup is not "lost"
+        if self.announced.is_none() && !up {
+            return None;
+        }
+
+        match self.pending {
+            Some((pending, since)) if pending == up => {
+                if now.duration_since(since) < SETTLE {
+                    return None;
+                }
+                self.announced = Some(up);
+                self.pending = None;
+                Some(up)
+            }
+            _ => {
+                self.pending = Some((up, now));
+                None
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "notify"))]
+mod imp {
+    pub fn check() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        Err("rm-pad was built without notification support (rebuild with `--features notify`)".into())
+    }
+
+    pub fn show(_summary: &str, _body: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        check()
+    }
+}
+
+#[cfg(feature = "notify")]
+mod imp {
+    use notify_rust::Notification;
+
+    pub fn check() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        Ok(())
+    }
+
+    pub fn show(summary: &str, body: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        Notification::new()
+            .appname("rm-pad")
+            .summary(summary)
+            .body(body)
+            .icon("input-tablet")
+            .show()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debounce_reconnect_churn() {
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        let mut debounce = Debounce::default();
+
+        // Not connected yet: nothing to report
+        assert_eq!(debounce.update(Some(false), at(0)), None);
+        assert_eq!(debounce.update(Some(false), at(10)), None);
+
+        assert_eq!(debounce.update(Some(true), at(11)), None);
+        assert_eq!(debounce.update(Some(true), at(16)), Some(true));
+        assert_eq!(debounce.update(Some(true), at(30)), None);
+
+        // Dropping in and out faster than SETTLE never settles
+        for t in (31..60).step_by(2) {
+            assert_eq!(debounce.update(Some(false), at(t)), None);
+            assert_eq!(debounce.update(Some(true), at(t + 1)), None);
+        }
+
+        assert_eq!(debounce.update(Some(false), at(60)), None);
+        assert_eq!(debounce.update(Some(false), at(65)), Some(false));
+        assert_eq!(debounce.update(Some(true), at(70)), None);
+        assert_eq!(debounce.update(Some(true), at(75)), Some(true));
+    }
+}
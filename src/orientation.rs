@@ -114,10 +114,29 @@ impl FromStr for Orientation {
     }
 }
 
+/// Shift an output-space point by `offset` (`offset_x`/`offset_y`) and clamp
+/// it to `region`, given as top-left and bottom-right corner.
+pub fn apply_offset(
+    (x, y): (i32, i32),
+    (dx, dy): (i32, i32),
+    ((x_min, y_min), (x_max, y_max)): ((i32, i32), (i32, i32)),
+) -> (i32, i32) {
+    (x.saturating_add(dx).clamp(x_min, x_max), y.saturating_add(dy).clamp(y_min, y_max))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_apply_offset_clamps() {
+        let region = ((0, 0), (100, 100));
+        assert_eq!(apply_offset((10, 20), (5, -5), region), (15, 15));
+        assert_eq!(apply_offset((95, 3), (10, -10), region), (100, 0));
+        assert_eq!(apply_offset((50, 50), (0, 0), region), (50, 50));
+        assert_eq!(apply_offset((50, 50), (-40, 0), ((20, 0), (80, 100))), (20, 50));
+    }
+
     #[test]
     fn test_landscape_right_transform() {
         let o = Orientation::LandscapeRight;
@@ -0,0 +1,165 @@
+//! Find the right orientation by touching a corner (`rm-pad orientation-test`).
+//!
+//! Streams raw pen or touch positions and shows, for every orientation,
+//! which part of the screen the cursor would land on. The four
+//! orientations send any corner of the tablet to four different corners of
+//! the screen, so touching the corner that is top-left the way the tablet
+//! is held picks out exactly one of them: the one that says "top-left".
+//!
+//! Nothing is forwarded, so the cursor stays where it is while the line is read.
+
+use std::io::{Read, Write};
+
+use crate::config::Config;
+use crate::device::DeviceProfile;
+use crate::input::{parse_input_event, read_event, ABS_MT_POSITION_X, ABS_MT_POSITION_Y, ABS_X, ABS_Y, EV_ABS, EV_SYN};
+use crate::orientation::Orientation;
+use crate::ssh;
+
+const ORIENTATIONS: [Orientation; 4] = [
+    Orientation::Portrait,
+    Orientation::LandscapeRight,
+    Orientation::LandscapeLeft,
+    Orientation::Inverted,
+];
+
+/// Share of the screen, from each edge, that counts as "at" that edge.
+const EDGE: f64 = 0.2;
+
+/// Where on the screen `pos` lies in an output space of size `dims`.
+fn region(pos: (i32, i32), dims: (i32, i32)) -> &'static str {
+    let side = |value: i32, max: i32| {
+        let fraction = value as f64 / max.max(1) as f64;
+        if fraction < EDGE {
+            0
+        } else if fraction > 1.0 - EDGE {
+            2
+        } else {
+            1
+        }
+    };
+    match (side(pos.0, dims.0), side(pos.1, dims.1)) {
+        (0, 0) => "top-left",
+        (1, 0) => "top",
+        (2, 0) => "top-right",
+        (0, 1) => "left",
+        (2, 1) => "right",
+        (0, 2) => "bottom-left",
+        (1, 2) => "bottom",
+        (2, 2) => "bottom-right",
+        _ => "middle",
+    }
+}
+
+/// Screen region the raw position `(x, y)` maps to under `orientation`.
+fn cursor_region(orientation: Orientation, pen: bool, device: &DeviceProfile, x: i32, y: i32) -> &'static str {
+    if pen {
+        let (x_max, y_max) = (device.pen_x_max, device.pen_y_max);
+        let pos = orientation.transform_pen(x.clamp(0, x_max), y.clamp(0, y_max), x_max, y_max);
+        region(pos, orientation.pen_output_dimensions(x_max, y_max))
+    } else {
+        let (x_max, y_max) = (device.touch_x_max, device.touch_y_max);
+        let pos = orientation.transform_touch(x.clamp(0, x_max), y.clamp(0, y_max), x_max, y_max);
+        region(pos, orientation.touch_output_dimensions(x_max, y_max))
+    }
+}
+
+/// The live line for a raw position: every orientation's region, the
+/// configured one marked with `*`, and the orientation to use if it's a corner.
+fn guidance(configured: Orientation, pen: bool, device: &DeviceProfile, x: i32, y: i32) -> String {
+    let mut line = format!("raw {:5},{:5}  cursor goes:", x, y);
+    let mut pick = None;
+    for orientation in ORIENTATIONS {
+        let region = cursor_region(orientation, pen, device, x, y);
+        let mark = if orientation == configured { "*" } else { "" };
+        line.push_str(&format!("  {}{} {}", orientation, mark, region));
+        if region == "top-left" {
+            pick = Some(orientation);
+        }
+    }
+    match pick {
+        Some(orientation) if orientation == configured => line.push_str("  -> configured orientation is right"),
+        Some(orientation) => {
+            let key = if pen { "pen_orientation" } else { "touch_orientation" };
+            line.push_str(&format!("  -> if this is top-left, set {} = \"{}\"", key, orientation));
+        }
+        None => {}
+    }
+    line
+}
+
+/// `kind` is "pen" or "touch".
+pub fn run(config: &Config, device: &DeviceProfile, kind: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (pen, path, configured, (x_code, y_code)) = match kind {
+        "pen" => (true, &config.pen_device, config.pen_orientation, (ABS_X, ABS_Y)),
+        "touch" => (false, &config.touch_device, config.touch_orientation, (ABS_MT_POSITION_X, ABS_MT_POSITION_Y)),
+        _ => return Err(format!("Unknown device '{}'. Use 'pen' or 'touch'", kind).into()),
+    };
+    let (cleanup, mut channel) = ssh::open_input_stream(path, config, false)?;
+    // Waiting for the user to touch the tablet isn't a lost connection
+    cleanup.set_read_timeout(None);
+
+    eprintln!("Testing {} orientation on {} (Ctrl+C when done)", kind, cleanup.device_path());
+    eprintln!("Hold the tablet the way you use it and {} its top-left corner.", if pen { "hover the pen over" } else { "touch" });
+    eprintln!("The orientation whose cursor goes top-left is the one to set ({} is marked *).\n", configured);
+
+    let result = show_guidance(&mut channel, device, configured, pen, (x_code, y_code));
+    ssh::check_stream_end(&mut channel, &cleanup, config, false, result)
+}
+
+/// Print the guidance line for the position at every SYN until the stream ends.
+fn show_guidance(
+    channel: &mut impl Read,
+    device: &DeviceProfile,
+    configured: Orientation,
+    pen: bool,
+    (x_code, y_code): (u16, u16),
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut buf = vec![0u8; device.input_event_size];
+    let (mut x, mut y) = (0, 0);
+    let mut last = String::new();
+    let mut stdout = std::io::stdout();
+
+    loop {
+        read_event(channel, &mut buf)?;
+
+        let Some(ev) = parse_input_event(&buf) else {
+            continue;
+        };
+        match (ev.event_type().raw(), ev.raw_code()) {
+            (EV_ABS, code) if code == x_code => x = ev.raw_value(),
+            (EV_ABS, code) if code == y_code => y = ev.raw_value(),
+            (EV_SYN, _) => {
+                let line = guidance(configured, pen, device, x, y);
+                if line != last {
+                    write!(stdout, "\r{}\x1b[K", line)?;
+                    stdout.flush()?;
+                    last = line;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::RM2;
+
+    #[test]
+    fn test_each_orientation_gets_its_own_corner() {
+        assert_eq!(region((5, 5), (100, 100)), "top-left");
+        assert_eq!(region((50, 95), (100, 100)), "bottom");
+        assert_eq!(region((50, 50), (100, 100)), "middle");
+
+        // Touch has Y=0 at the bottom; raw (0, 0) is top-left only in landscape-right
+        let corners: Vec<_> = ORIENTATIONS.iter().map(|&o| cursor_region(o, false, &RM2, 0, 0)).collect();
+        assert_eq!(corners, ["bottom-left", "top-left", "bottom-right", "top-right"]);
+
+        let line = guidance(Orientation::Portrait, false, &RM2, 0, 0);
+        assert!(line.ends_with("set touch_orientation = \"landscape-right\""));
+        let line = guidance(Orientation::LandscapeRight, true, &RM2, 0, 0);
+        assert!(line.ends_with("configured orientation is right"));
+    }
+}
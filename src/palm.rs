@@ -6,6 +6,8 @@ use std::time::Instant;
 pub struct PalmState {
     pub pen_down: bool,
     pub last_pen_up: Option<Instant>,
+    /// Last pen frame of any kind, hover included.
+    pub last_pen_activity: Option<Instant>,
 }
 
 impl PalmState {
@@ -0,0 +1,138 @@
+//! Inspect and clear rm-pad's files on the tablet (`rm-pad remote`).
+//!
+//! `status` lists the grab helper, its log and the watchdog file with size
+//! and age; `log` prints the end of the helper log; `clear` removes the log
+//! and the watchdog file (the helper is re-checked on every connect anyway).
+//! With `--json`, each prints one JSON object instead.
+
+use serde_json::json;
+
+use crate::config::Config;
+use crate::grab::RemotePaths;
+use crate::ssh;
+
+/// The helper exits once the watchdog file is older than this (evgrab.c's
+/// WATCHDOG_TIMEOUT), so a fresher one means a grab is probably running.
+const WATCHDOG_TIMEOUT_SECS: u64 = 5;
+
+/// What `rm-pad remote` does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum RemoteAction {
+    /// Show the helper, log and watchdog file
+    Status,
+    /// Print the end of the helper log
+    Log,
+    /// Remove the log and the watchdog file
+    Clear,
+}
+
+#[derive(Debug, PartialEq)]
+struct RemoteFile {
+    path: String,
+    /// `None` if the file doesn't exist.
+    size: Option<u64>,
+    age_secs: Option<u64>,
+}
+
+pub fn run(config: &Config, action: RemoteAction, json: bool, lines: usize) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let paths = RemotePaths::new(&config.remote_dir);
+    let session = ssh::connect_for_detection(config)?;
+
+    match action {
+        RemoteAction::Status => {
+            let files = [&paths.helper, &paths.log, &paths.watchdog];
+            let script: Vec<String> = files
+                .iter()
+                .map(|f| format!("stat -c '%n %s %Y' {f} 2>/dev/null || echo '{f} -'", f = f))
+                .collect();
+            let output = ssh::run_command(&session, &format!("{}; date +%s", script.join("; ")))?;
+            let [helper, log, watchdog] = parse_status(&output).try_into().map_err(|_| "Unexpected stat output from the tablet")?;
+            print_status(&[("helper", helper), ("log", log), ("watchdog", watchdog)], json);
+        }
+        RemoteAction::Log => {
+            let output = ssh::run_command(&session, &format!("tail -n {} {} 2>/dev/null || true", lines, paths.log))?;
+            if json {
+                println!("{}", json!({ "path": paths.log, "lines": output.lines().collect::<Vec<_>>() }));
+            } else {
+                print!("{}", output);
+            }
+        }
+        RemoteAction::Clear => {
+            ssh::run_command(&session, &format!("rm -f {} {}", paths.log, paths.watchdog))?;
+            if json {
+                println!("{}", json!({ "removed": [paths.log, paths.watchdog] }));
+            } else {
+                println!("Removed {} and {}", paths.log, paths.watchdog);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parse `<path> <size> <mtime>` (or `<path> -`) lines followed by the
+/// tablet's current time.
+fn parse_status(output: &str) -> Vec<RemoteFile> {
+    let lines: Vec<&str> = output.lines().filter(|l| !l.trim().is_empty()).collect();
+    let Some((now, files)) = lines.split_last() else {
+        return Vec::new();
+    };
+    let now: Option<u64> = now.trim().parse().ok();
+
+    files
+        .iter()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let path = fields.next()?.to_string();
+            let size = fields.next().and_then(|s| s.parse().ok());
+            let mtime: Option<u64> = fields.next().and_then(|s| s.parse().ok());
+            let age_secs = now.zip(mtime).map(|(now, mtime)| now.saturating_sub(mtime));
+            Some(RemoteFile { path, size, age_secs })
+        })
+        .collect()
+}
+
+fn print_status(files: &[(&str, RemoteFile)], json: bool) {
+    if json {
+        let entries: serde_json::Map<String, serde_json::Value> = files
+            .iter()
+            .map(|(name, f)| {
+                let entry = json!({ "path": f.path, "exists": f.size.is_some(), "size": f.size, "age_secs": f.age_secs });
+                (name.to_string(), entry)
+            })
+            .collect();
+        println!("{}", serde_json::Value::Object(entries));
+        return;
+    }
+
+    for (name, f) in files {
+        let state = match (f.size, f.age_secs) {
+            (None, _) => "missing".to_string(),
+            (Some(size), Some(age)) => format!("{} bytes, modified {}s ago", size, age),
+            (Some(size), None) => format!("{} bytes", size),
+        };
+        let note = match f.age_secs {
+            Some(age) if *name == "watchdog" && age <= WATCHDOG_TIMEOUT_SECS => " (fresh: rm-pad is probably forwarding)",
+            _ => "",
+        };
+        println!("{:<9} {:<28} {}{}", name, f.path, state, note);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_status() {
+        let output = "/tmp/rm-pad-grab 14232 1700000000\n/tmp/rm-pad-grab.log -\n/tmp/rm-pad-watchdog 0 1700000995\n1700001000\n";
+        assert_eq!(
+            parse_status(output),
+            vec![
+                RemoteFile { path: "/tmp/rm-pad-grab".into(), size: Some(14232), age_secs: Some(1000) },
+                RemoteFile { path: "/tmp/rm-pad-grab.log".into(), size: None, age_secs: None },
+                RemoteFile { path: "/tmp/rm-pad-watchdog".into(), size: Some(0), age_secs: Some(5) },
+            ]
+        );
+        assert!(parse_status("").is_empty());
+    }
+}
@@ -0,0 +1,150 @@
+//! Replay a recording into the real output devices (`rm-pad replay`).
+//!
+//! Takes the same raw recordings as the corpus (`rm2-pen-stroke.bin`, or
+//! `.bin.zst`) and forwards them as if they came from the tablet, using the
+//! configured output and options. A corpus-style name picks the profile and
+//! decoder; `--device-model` and `--kind` override it, so any capture can be
+//! replayed. Events keep
+//! their recorded spacing scaled by `--speed`: `2` is twice as fast, `0` as
+//! fast as possible.
+
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::corpus;
+use crate::device::{DeviceProfile, RM2};
+use crate::input;
+
+/// Profile and decoder ("pen" or "touch") for a recording: `device_model`
+/// and `kind` if given, otherwise what a corpus-style name says. Without
+/// either the profile is the reMarkable 2, but the kind has to be given.
+pub fn recording_kind(
+    path: &Path,
+    device_model: Option<&str>,
+    kind: Option<&str>,
+) -> Result<(&'static DeviceProfile, &'static str), Box<dyn std::error::Error + Send + Sync>> {
+    let named = corpus::parse_name(path).ok();
+    let device = match device_model {
+        Some(name) => DeviceProfile::from_name(name)?,
+        None => named.map_or(&RM2, |(device, _)| device),
+    };
+    let kind = match kind.or(named.map(|(_, kind)| kind)) {
+        Some("pen") => "pen",
+        Some("touch") => "touch",
+        Some(other) => return Err(format!("Unknown recording kind '{}' (pen or touch)", other).into()),
+        None => return Err(format!("{}: the name doesn't say pen or touch, pass --kind", path.display()).into()),
+    };
+    Ok((device, kind))
+}
+
+pub fn run(
+    path: &Path,
+    speed: f64,
+    device: &'static DeviceProfile,
+    kind: &str,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if !speed.is_finite() || speed < 0.0 {
+        return Err("--speed must be 0 (no delay) or a positive factor".into());
+    }
+
+    let file = corpus::open_recording(path)?;
+    let mut reader = TimedReader::new(file, device.input_event_size, speed);
+    eprintln!("Replaying {} as {} {} at {}x", path.display(), device.name, kind, speed);
+
+    let result = match kind {
+        "pen" => input::forward_pen(&mut reader, device, config, None, None),
+        _ => input::forward_touch(&mut reader, device, config, None, None, None),
+    };
+    match result {
+        Err(e) if corpus::is_eof(e.as_ref()) => {
+            eprintln!("Replay finished");
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Hands out a raw event stream one event at a time, sleeping for the
+/// recorded gap before each event (divided by `speed`; 0 never sleeps).
+struct TimedReader<R> {
+    inner: R,
+    speed: f64,
+    event: Vec<u8>,
+    pos: usize,
+    last: Option<Duration>,
+}
+
+impl<R: Read> TimedReader<R> {
+    fn new(inner: R, event_size: usize, speed: f64) -> Self {
+        Self {
+            inner,
+            speed,
+            event: vec![0u8; event_size],
+            pos: event_size,
+            last: None,
+        }
+    }
+
+    /// How long to wait before an event stamped `time`.
+    fn gap(&mut self, time: Duration) -> Option<Duration> {
+        let last = self.last.replace(time)?;
+        if self.speed == 0.0 {
+            return None;
+        }
+        time.checked_sub(last).map(|gap| gap.div_f64(self.speed))
+    }
+}
+
+impl<R: Read> Read for TimedReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.event.len() {
+            match self.inner.read_exact(&mut self.event) {
+                Ok(()) => self.pos = 0,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(0),
+                Err(e) => return Err(e),
+            }
+            if let Some(gap) = input::parse_timed_event(&self.event).and_then(|timed| self.gap(timed.time)) {
+                std::thread::sleep(gap);
+            }
+        }
+
+        let n = out.len().min(self.event.len() - self.pos);
+        out[..n].copy_from_slice(&self.event[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gap_scales_with_speed() {
+        let ms = Duration::from_millis;
+
+        let mut double = TimedReader::new(io::empty(), 16, 2.0);
+        assert_eq!(double.gap(ms(1000)), None);
+        assert_eq!(double.gap(ms(1010)), Some(ms(5)));
+
+        let mut unpaced = TimedReader::new(io::empty(), 16, 0.0);
+        assert_eq!(unpaced.gap(ms(1000)), None);
+        assert_eq!(unpaced.gap(ms(1500)), None);
+    }
+
+    #[test]
+    fn test_recording_kind() {
+        let (device, kind) = recording_kind(Path::new("corpus/rmpp-touch-tap.bin"), None, None).unwrap();
+        assert_eq!((device.name, kind), (crate::device::RMPP.name, "touch"));
+
+        // Any name works with --kind; the flags win over the name
+        let capture = Path::new("/tmp/session.bin");
+        assert!(recording_kind(capture, None, None).is_err());
+        assert_eq!(recording_kind(capture, None, Some("pen")).unwrap().0.name, RM2.name);
+        let (device, kind) = recording_kind(Path::new("rm2-pen-x.bin"), Some("rmpp"), Some("touch")).unwrap();
+        assert_eq!((device.name, kind), (crate::device::RMPP.name, "touch"));
+    }
+}
@@ -0,0 +1,139 @@
+//! Keep the pen's aspect ratio on the host monitor (`fit_screen`).
+//!
+//! The compositor stretches a tablet's whole declared range over the
+//! monitor, so a 4:3 tablet on a 16:9 screen comes out wide. With
+//! `fit_screen`, one axis of the declared range is widened to the screen's
+//! shape and the tablet is centred in it: it maps onto a centred area of
+//! the screen with bars at the sides (or top and bottom) it can't reach.
+//!
+//! The screen size is `screen_size` if set, otherwise the preferred mode
+//! of the first connected monitor in `/sys/class/drm`.
+
+use std::path::Path;
+
+use crate::config::Config;
+
+const DRM_DIR: &str = "/sys/class/drm";
+
+/// Declared output range and where the tablet sits inside it.
+#[derive(Debug, PartialEq)]
+pub struct Fit {
+    pub max: (i32, i32),
+    pub offset: (i32, i32),
+    /// The tablet's own output range, unchanged by the fit.
+    pub tablet: (i32, i32),
+}
+
+impl Fit {
+    /// Top-left and bottom-right corner of the tablet inside the output range.
+    /// Positions outside it land in the bars beside the tablet.
+    pub fn region(&self) -> ((i32, i32), (i32, i32)) {
+        let (x, y) = self.offset;
+        (self.offset, (x + self.tablet.0, y + self.tablet.1))
+    }
+}
+
+/// Fit `tablet` (output maxima) into the aspect ratio of `screen`, or leave it as is without one.
+pub fn fit(tablet: (i32, i32), screen: Option<(u32, u32)>) -> Fit {
+    let Some((screen_w, screen_h)) = screen else {
+        return Fit { max: tablet, offset: (0, 0), tablet };
+    };
+    let (tablet_w, tablet_h) = (tablet.0 as i64, tablet.1 as i64);
+    let (screen_w, screen_h) = (screen_w as i64, screen_h as i64);
+
+    if tablet_w * screen_h > tablet_h * screen_w {
+        // Tablet is wider: bars above and below
+        let height = (tablet_w * screen_h / screen_w) as i32;
+        Fit { max: (tablet.0, height), offset: (0, (height - tablet.1) / 2), tablet }
+    } else {
+        let width = (tablet_h * screen_w / screen_h) as i32;
+        Fit { max: (width, tablet.1), offset: ((width - tablet.0) / 2, 0), tablet }
+    }
+}
+
+/// The screen to fit the pen to, or `None` without `fit_screen`.
+pub fn size(config: &Config) -> Result<Option<(u32, u32)>, Box<dyn std::error::Error + Send + Sync>> {
+    if !config.fit_screen {
+        return Ok(None);
+    }
+    if let Some(size) = &config.screen_size {
+        return Ok(Some(parse_size(size)?));
+    }
+
+    let size = detect(Path::new(DRM_DIR))
+        .ok_or("fit_screen: no connected monitor found in /sys/class/drm; set screen_size = \"WxH\"")?;
+    log::debug!("fit_screen: detected a {}x{} screen", size.0, size.1);
+    Ok(Some(size))
+}
+
+/// Parse `WxH`, e.g. `1920x1080`.
+pub fn parse_size(s: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("Invalid screen size '{}'. Expected WxH, e.g. 1920x1080", s);
+    let (w, h) = s.trim().split_once(['x', 'X']).ok_or_else(invalid)?;
+    match (w.parse(), h.parse()) {
+        (Ok(w), Ok(h)) if w > 0 && h > 0 => Ok((w, h)),
+        _ => Err(invalid()),
+    }
+}
+
+/// Preferred mode of the first connected connector (`card0-HDMI-A-1` etc.), by name.
+fn detect(drm: &Path) -> Option<(u32, u32)> {
+    let mut connectors: Vec<_> = std::fs::read_dir(drm)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| std::fs::read_to_string(path.join("status")).is_ok_and(|s| s.trim() == "connected"))
+        .collect();
+    connectors.sort();
+
+    connectors.iter().find_map(|path| {
+        let modes = std::fs::read_to_string(path.join("modes")).ok()?;
+        parse_size(modes.lines().next()?).ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_letterboxes() {
+        // 4:3 tablet on a 16:9 screen: widened, tablet centred
+        let wide = fit((1600, 1200), Some((1920, 1080)));
+        assert_eq!((wide.max, wide.offset), ((2133, 1200), (266, 0)));
+        assert_eq!(wide.region(), ((266, 0), (1866, 1200)));
+        // Portrait tablet output on a landscape screen
+        let portrait = fit((1200, 1600), Some((1600, 1200)));
+        assert_eq!((portrait.max, portrait.offset), ((2133, 1600), (466, 0)));
+        // Wider than the screen: bars above and below
+        let tall = fit((2000, 1000), Some((1600, 1200)));
+        assert_eq!((tall.max, tall.offset), ((2000, 1500), (0, 250)));
+        assert_eq!(tall.region(), ((0, 250), (2000, 1250)));
+        let off = fit((1600, 1200), None);
+        assert_eq!((off.max, off.offset, off.region()), ((1600, 1200), (0, 0), ((0, 0), (1600, 1200))));
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1920x1080"), Ok((1920, 1080)));
+        assert!(parse_size("1920").is_err());
+        assert!(parse_size("0x1080").is_err());
+    }
+
+    #[test]
+    fn test_detect_first_connected() {
+        let drm = std::env::temp_dir().join(format!("rm-pad-drm-{}", std::process::id()));
+        for (name, status, modes) in [
+            ("card0-DP-1", "disconnected", ""),
+            ("card0-HDMI-A-1", "connected", "2560x1440\n1920x1080\n"),
+            ("card1-eDP-1", "connected", "1920x1200\n"),
+        ] {
+            std::fs::create_dir_all(drm.join(name)).unwrap();
+            std::fs::write(drm.join(name).join("status"), format!("{}\n", status)).unwrap();
+            std::fs::write(drm.join(name).join("modes"), modes).unwrap();
+        }
+
+        let size = detect(&drm);
+        std::fs::remove_dir_all(&drm).unwrap();
+        assert_eq!(size, Some((2560, 1440)));
+    }
+}
@@ -0,0 +1,68 @@
+//! Tablet password storage in the system keyring, and hidden terminal prompts.
+//!
+//! Keyring access needs the `keyring` cargo feature (Secret Service via D-Bus);
+//! without it the functions return an error explaining how to enable it.
+
+use std::io::{self, BufRead, Write};
+
+#[cfg(feature = "keyring")]
+pub fn get_password(service: &str, account: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let entry = keyring::Entry::new(service, account)?;
+    entry
+        .get_password()
+        .map_err(|e| format!("Failed to read keyring entry {}/{}: {}", service, account, e).into())
+}
+
+#[cfg(feature = "keyring")]
+pub fn set_password(
+    service: &str,
+    account: &str,
+    password: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    keyring::Entry::new(service, account)?.set_password(password)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "keyring"))]
+pub fn get_password(_service: &str, _account: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    Err(NO_KEYRING.into())
+}
+
+#[cfg(not(feature = "keyring"))]
+pub fn set_password(
+    _service: &str,
+    _account: &str,
+    _password: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    Err(NO_KEYRING.into())
+}
+
+#[cfg(not(feature = "keyring"))]
+const NO_KEYRING: &str = "rm-pad was built without keyring support (rebuild with `--features keyring`)";
+
+/// Prompt on stderr and read a line from stdin with echo disabled.
+pub fn prompt_hidden(prompt: &str) -> io::Result<String> {
+    eprint!("{}", prompt);
+    io::stderr().flush()?;
+
+    let fd = libc::STDIN_FILENO;
+    let mut term: libc::termios = unsafe { std::mem::zeroed() };
+    let is_tty = unsafe { libc::tcgetattr(fd, &mut term) } == 0;
+
+    if is_tty {
+        let mut hidden = term;
+        hidden.c_lflag &= !libc::ECHO;
+        unsafe { libc::tcsetattr(fd, libc::TCSANOW, &hidden) };
+    }
+
+    let mut line = String::new();
+    let result = io::stdin().lock().read_line(&mut line);
+
+    if is_tty {
+        unsafe { libc::tcsetattr(fd, libc::TCSANOW, &term) };
+        eprintln!();
+    }
+
+    result?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
@@ -0,0 +1,134 @@
+//! First-time key setup (`rm-pad setup`).
+//!
+//! Generates an ed25519 key without a passphrase with the local
+//! `ssh-keygen` (an existing key at the path is reused), logs in to the tablet with the root password, adds
+//! the public key to `~/.ssh/authorized_keys` there unless it is already
+//! listed, and then logs in again with only the key to check it is accepted.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::config::{Auth, Config};
+use crate::secret;
+use crate::ssh;
+
+/// Comment stored with the generated key, shown in authorized_keys.
+const KEY_COMMENT: &str = "rm-pad";
+
+/// Shell command that adds `public_key` to root's authorized_keys.
+///
+/// Creates `~/.ssh` and the file if missing, ends a last line that has no
+/// newline before appending, and leaves the file alone if the key is in it.
+fn install_command(public_key: &str) -> String {
+    let key = public_key.trim();
+    format!(
+        "umask 077 && mkdir -p ~/.ssh && touch ~/.ssh/authorized_keys && \
+         if ! grep -qxF '{key}' ~/.ssh/authorized_keys; then \
+         if [ -s ~/.ssh/authorized_keys ] && [ -n \"$(tail -c 1 ~/.ssh/authorized_keys)\" ]; then echo >> ~/.ssh/authorized_keys; fi; \
+         echo '{key}' >> ~/.ssh/authorized_keys; fi"
+    )
+}
+
+/// Generate the key pair at `path` unless both halves are already there.
+fn ensure_key(path: &Path) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    // ssh-keygen appends .pub to the whole name
+    let mut public_path = path.as_os_str().to_owned();
+    public_path.push(".pub");
+    let public_path = PathBuf::from(public_path);
+    if path.exists() && public_path.exists() {
+        eprintln!("Using the existing key {}", path.display());
+    } else if path.exists() {
+        return Err(format!(
+            "{} exists but {} does not; move the key away or set key_path to a new file",
+            path.display(),
+            public_path.display()
+        )
+        .into());
+    } else {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let status = Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-C", KEY_COMMENT, "-f"])
+            .arg(path)
+            .status()
+            .map_err(|e| format!("Could not run ssh-keygen (is OpenSSH installed?): {}", e))?;
+        if !status.success() {
+            return Err(format!("ssh-keygen failed ({})", status).into());
+        }
+        eprintln!("Generated {} without a passphrase; anyone who can read it can log in to the tablet", path.display());
+    }
+
+    let public_key = fs::read_to_string(&public_path)?;
+    if public_key.contains('\'') || public_key.lines().count() != 1 {
+        return Err(format!("{} does not look like a public key", public_path.display()).into());
+    }
+    Ok(public_key)
+}
+
+pub fn run(config: &Config) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // The root password must not go to the default address by accident
+    config.check_host()?;
+    let path = config.setup_key_path();
+    let public_key = ensure_key(&path)?;
+
+    let password = match &config.password {
+        Some(password) => password.clone(),
+        None => secret::prompt_hidden(&format!("Root password for {}: ", config.host))?,
+    };
+    let session = ssh::connect_with(config, &Auth::Password(password))?;
+    ssh::run_command(&session, &install_command(&public_key))
+        .map_err(|e| format!("Could not add the key to ~/.ssh/authorized_keys on the tablet: {}", e))?;
+    eprintln!("Added the key to ~/.ssh/authorized_keys on {}", config.host);
+
+    ssh::connect_with(config, &Auth::Keys(vec![path.clone()], None))
+        .map_err(|e| format!("The tablet did not accept the new key: {}", e))?;
+    match config.auth() {
+        Auth::Keys(paths, _) if paths.contains(&path) => eprintln!("Key login works, rm-pad will use {}", path.display()),
+        Auth::Keys(..) => eprintln!("Key login works. Add {} to key_path so rm-pad uses it", path.display()),
+        _ => eprintln!("Key login works. Remove password (and auth = \"keyring\") from the config to use it"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_install_command_appends_once() {
+        let command = install_command("ssh-ed25519 AAAAC3Nz rm-pad\n");
+        assert!(command.starts_with("umask 077 && mkdir -p ~/.ssh && touch ~/.ssh/authorized_keys"));
+        assert!(command.contains("grep -qxF 'ssh-ed25519 AAAAC3Nz rm-pad' ~/.ssh/authorized_keys"));
+        assert!(command.ends_with("echo 'ssh-ed25519 AAAAC3Nz rm-pad' >> ~/.ssh/authorized_keys; fi"));
+    }
+
+    #[test]
+    fn test_run_needs_a_host_with_no_default_host() {
+        let mut config = Config::defaults(&crate::device::RM2);
+        config.no_default_host = true;
+        let key = std::env::temp_dir().join(format!("rm-pad-setup-nohost-{}", std::process::id()));
+        config.key_path = vec![key.to_string_lossy().into_owned()];
+
+        let err = run(&config).unwrap_err().to_string();
+        assert!(err.contains("--no-default-host"), "{}", err);
+        // Nothing was generated either
+        assert!(!config.setup_key_path().exists());
+    }
+
+    #[test]
+    fn test_ensure_key_refuses_lone_private_key() {
+        let dir = std::env::temp_dir().join(format!("rm-pad-setup-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("rm-key");
+        fs::write(&path, "private").unwrap();
+
+        // The lone private key is left for the user to deal with, not overwritten
+        let err = ensure_key(&path).unwrap_err().to_string();
+        let kept = fs::read_to_string(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(err.contains("rm-key.pub does not"), "{}", err);
+        assert_eq!(kept, "private");
+    }
+}
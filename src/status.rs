@@ -0,0 +1,293 @@
+//! Live status of the streams, for the `--tui` dashboard (and `notify`,
+//! `health_addr`).
+//!
+//! The loops and the reconnect logic update a few atomics per stream; a
+//! background thread redraws the dashboard from them once a second. The
+//! counters are cheap enough to update unconditionally. While the dashboard
+//! is up, the log goes to `DashboardLog` and its last lines are drawn below
+//! the streams, since anything printed would be drawn over.
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicU8, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const STATE_OFF: u8 = 0;
+const STATE_CONNECTING: u8 = 1;
+const STATE_STREAMING: u8 = 2;
+const STATE_RECONNECTING: u8 = 3;
+const STATE_STOPPED: u8 = 4;
+
+/// Reference point for `last_frame`, set on first use.
+fn epoch() -> Instant {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    *EPOCH.get_or_init(Instant::now)
+}
+
+/// Status of one stream (pen or touch).
+pub struct StreamStatus {
+    state: AtomicU8,
+    /// Frames written to the output device.
+    forwarded: AtomicU64,
+    /// Frames dropped by `skip_repeated_frames`.
+    skipped: AtomicU64,
+    /// Motion frames dropped by `max_event_rate`.
+    throttled: AtomicU64,
+    contacts: AtomicI32,
+    palm_suppressed: AtomicBool,
+    /// Disconnected with no frame since.
+    dropped: AtomicBool,
+    /// Milliseconds after `epoch()` of the last frame, plus one; 0 for none yet.
+    last_frame: AtomicU64,
+    last_error: Mutex<Option<String>>,
+}
+
+/// How many frames a stream forwarded and dropped on the way out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameCounts {
+    pub forwarded: u64,
+    pub skipped: u64,
+    pub throttled: u64,
+}
+
+impl StreamStatus {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(STATE_OFF),
+            forwarded: AtomicU64::new(0),
+            skipped: AtomicU64::new(0),
+            throttled: AtomicU64::new(0),
+            contacts: AtomicI32::new(0),
+            palm_suppressed: AtomicBool::new(false),
+            dropped: AtomicBool::new(false),
+            last_frame: AtomicU64::new(0),
+            last_error: Mutex::new(None),
+        }
+    }
+
+    pub fn connecting(&self) {
+        self.state.store(STATE_CONNECTING, Ordering::Relaxed);
+    }
+
+    pub fn reconnecting(&self, error: Option<String>) {
+        self.state.store(STATE_RECONNECTING, Ordering::Relaxed);
+        self.contacts.store(0, Ordering::Relaxed);
+        self.dropped.store(true, Ordering::Relaxed);
+        if let (Some(error), Ok(mut last)) = (error, self.last_error.lock()) {
+            *last = Some(error);
+        }
+    }
+
+    pub fn stopped(&self) {
+        self.state.store(STATE_STOPPED, Ordering::Relaxed);
+    }
+
+    /// Lost its connection and hasn't forwarded anything since.
+    pub fn has_dropped(&self) -> bool {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// The stream is enabled; pen and touch report off when unused.
+    pub fn is_active(&self) -> bool {
+        self.state.load(Ordering::Relaxed) != STATE_OFF
+    }
+
+    /// Time since the last forwarded frame, if there was one.
+    pub fn since_last_frame(&self) -> Option<Duration> {
+        let stamp = self.last_frame.load(Ordering::Relaxed).checked_sub(1)?;
+        Some(epoch().elapsed().saturating_sub(Duration::from_millis(stamp)))
+    }
+
+    pub fn is_streaming(&self) -> bool {
+        self.state.load(Ordering::Relaxed) == STATE_STREAMING
+    }
+
+    pub fn last_error(&self) -> Option<String> {
+        self.last_error.lock().ok().and_then(|e| e.clone())
+    }
+
+    /// Called for every frame the loop handled; `forwarded` counts what reached the output.
+    pub fn frame(&self, contacts: i32, palm_suppressed: bool) {
+        self.state.store(STATE_STREAMING, Ordering::Relaxed);
+        self.contacts.store(contacts, Ordering::Relaxed);
+        self.palm_suppressed.store(palm_suppressed, Ordering::Relaxed);
+        self.dropped.store(false, Ordering::Relaxed);
+        self.last_frame
+            .store(epoch().elapsed().as_millis() as u64 + 1, Ordering::Relaxed);
+    }
+
+    /// A frame was written to the output device.
+    pub fn forwarded(&self) {
+        self.forwarded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A frame was dropped because it repeated the last values.
+    pub fn skipped(&self) {
+        self.skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A motion frame was dropped to stay within `max_event_rate`.
+    pub fn throttled(&self) {
+        self.throttled.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn frame_counts(&self) -> FrameCounts {
+        FrameCounts {
+            forwarded: self.forwarded.load(Ordering::Relaxed),
+            skipped: self.skipped.load(Ordering::Relaxed),
+            throttled: self.throttled.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Default for StreamStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Display for FrameCounts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} forwarded, {} skipped as repeats, {} throttled",
+            self.forwarded, self.skipped, self.throttled
+        )
+    }
+}
+
+pub static PEN: StreamStatus = StreamStatus::new();
+pub static TOUCH: StreamStatus = StreamStatus::new();
+
+/// How many log lines the dashboard shows.
+const LOG_LINES: usize = 6;
+
+/// Latest log lines for the dashboard, oldest first.
+static LOG: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// Log target while the dashboard is up: keeps the last `LOG_LINES` lines.
+#[derive(Default)]
+pub struct DashboardLog {
+    /// A line not yet ended by a newline.
+    partial: String,
+}
+
+impl Write for DashboardLog {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.partial.push_str(&String::from_utf8_lossy(buf));
+        while let Some(end) = self.partial.find('\n') {
+            let line: String = self.partial.drain(..=end).collect();
+            let mut log = LOG.lock().unwrap_or_else(|e| e.into_inner());
+            if log.len() == LOG_LINES {
+                log.pop_front();
+            }
+            log.push_back(line.trim_end().to_string());
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Redraw the dashboard on stdout every second until the process exits.
+pub fn spawn_dashboard(host: String) {
+    thread::spawn(move || {
+        let mut last_frames = [0u64; 2];
+        let mut stdout = std::io::stdout();
+
+        loop {
+            let mut screen = String::new();
+            let _ = writeln!(screen, "rm-pad -> {}   (Ctrl+C to quit)\n", host);
+            for (i, (name, status)) in [("pen", &PEN), ("touch", &TOUCH)].into_iter().enumerate() {
+                let frames = status.frame_counts().forwarded;
+                let rate = frames - last_frames[i];
+                last_frames[i] = frames;
+                let _ = writeln!(screen, "{}", describe(name, status, rate));
+            }
+            let log = LOG.lock().unwrap_or_else(|e| e.into_inner());
+            if !log.is_empty() {
+                screen.push('\n');
+                for line in log.iter() {
+                    let _ = writeln!(screen, "{}", line);
+                }
+            }
+            drop(log);
+
+            // Home, draw, clear the rest: no flicker from a full clear
+            let _ = write!(stdout, "\x1b[H{}\x1b[J", screen.replace('\n', "\x1b[K\n"));
+            let _ = stdout.flush();
+            thread::sleep(Duration::from_secs(1));
+        }
+    });
+}
+
+fn describe(name: &str, status: &StreamStatus, frames_per_sec: u64) -> String {
+    let state = match status.state.load(Ordering::Relaxed) {
+        STATE_OFF => return format!("{:6} off", name),
+        STATE_CONNECTING => "connecting",
+        STATE_STREAMING => "streaming",
+        STATE_RECONNECTING => "reconnecting",
+        _ => "stopped",
+    };
+
+    let mut line = format!(
+        "{:6} {:12} {:5} frames/s  contacts {}",
+        name,
+        state,
+        frames_per_sec,
+        status.contacts.load(Ordering::Relaxed)
+    );
+    let counts = status.frame_counts();
+    if counts.skipped > 0 || counts.throttled > 0 {
+        let _ = write!(line, "  skipped {}  throttled {}", counts.skipped, counts.throttled);
+    }
+    if status.palm_suppressed.load(Ordering::Relaxed) {
+        line.push_str("  [palm rejection active]");
+    }
+    if let Some(error) = status.last_error() {
+        let _ = write!(line, "\n       last error: {}", error);
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dashboard_shows_dropped_frames() {
+        let status = StreamStatus::new();
+        status.frame(1, false);
+        status.forwarded();
+        assert_eq!(describe("pen", &status, 1), "pen    streaming        1 frames/s  contacts 1");
+
+        status.skipped();
+        status.throttled();
+        status.throttled();
+        assert_eq!(
+            status.frame_counts(),
+            FrameCounts { forwarded: 1, skipped: 1, throttled: 2 }
+        );
+        assert!(describe("pen", &status, 1).ends_with("contacts 1  skipped 1  throttled 2"));
+    }
+
+    #[test]
+    fn test_dashboard_log_keeps_last_lines() {
+        let mut log = DashboardLog::default();
+        for i in 0..LOG_LINES + 2 {
+            write!(log, "[WARN] line {}", i).unwrap();
+            log.write_all(b"\n").unwrap();
+        }
+        write!(log, "unfinished").unwrap();
+
+        let lines: Vec<String> = LOG.lock().unwrap().iter().cloned().collect();
+        assert_eq!(lines.len(), LOG_LINES);
+        assert_eq!(lines[0], "[WARN] line 2");
+        assert_eq!(lines[LOG_LINES - 1], format!("[WARN] line {}", LOG_LINES + 1));
+    }
+}